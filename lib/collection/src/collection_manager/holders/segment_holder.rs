@@ -993,6 +993,26 @@ impl<'s> SegmentHolder {
         })
     }
 
+    /// Take a snapshot of segments changed by operation `op_num` or later
+    ///
+    /// Segments not touched since `op_num` are skipped.
+    pub fn snapshot_segments_since(
+        segments: LockedSegmentHolder,
+        segments_path: &Path,
+        collection_params: Option<&CollectionParams>,
+        temp_dir: &Path,
+        snapshot_dir_path: &Path,
+        op_num: SeqNumberType,
+    ) -> OperationResult<()> {
+        Self::proxy_all_segments_and_apply(segments, segments_path, collection_params, |segment| {
+            let read_segment = segment.read();
+            if read_segment.version() >= op_num {
+                read_segment.take_snapshot(temp_dir, snapshot_dir_path)?;
+            }
+            Ok(())
+        })
+    }
+
     pub fn report_optimizer_error<E: Into<CollectionError>>(&mut self, error: E) {
         // Save only the first error
         // If is more likely to be the real cause of all further problems
@@ -0,0 +1,151 @@
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Structure that removes a file or directory when dropped, unless committed.
+///
+/// Cleanup is synchronous, so it also happens when an async task holding the guard is cancelled.
+#[derive(Debug)]
+pub struct CleanupGuard {
+    path: Option<PathBuf>,
+    /// Entries of the directory at `path` to keep, see [`CleanupGuard::new_entries`]
+    existing_entries: Option<HashSet<OsString>>,
+}
+
+impl CleanupGuard {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: Some(path.into()),
+            existing_entries: None,
+        }
+    }
+
+    /// Guard a directory owned by the caller, which may already exist
+    ///
+    /// Only entries created in the directory after this call are removed. The directory itself
+    /// and its existing entries are kept.
+    pub fn new_entries(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = dir.into();
+        let existing_entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries
+                .map(|entry| entry.map(|entry| entry.file_name()))
+                .collect::<io::Result<_>>()?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(err) => return Err(err),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            existing_entries: Some(existing_entries),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        // unwrap safety: path is only taken on commit or drop, which consume the guard
+        self.path.as_deref().unwrap()
+    }
+
+    /// Keep the path, it won't be removed when the guard is dropped.
+    pub fn commit(mut self) -> PathBuf {
+        // unwrap safety: path is only taken on commit or drop, which consume the guard
+        self.path.take().unwrap()
+    }
+}
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        let Some(path) = self.path.take() else {
+            return;
+        };
+
+        let Some(existing_entries) = self.existing_entries.take() else {
+            remove_path(&path);
+            return;
+        };
+
+        let entries = match std::fs::read_dir(&path) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return,
+            Err(err) => {
+                log::warn!("Failed to clean up {}: {err}", path.display());
+                return;
+            }
+        };
+        for entry in entries {
+            match entry {
+                Ok(entry) if !existing_entries.contains(&entry.file_name()) => {
+                    remove_path(&entry.path());
+                }
+                Ok(_) => {}
+                Err(err) => log::warn!("Failed to clean up {}: {err}", path.display()),
+            }
+        }
+    }
+}
+
+fn remove_path(path: &Path) {
+    let result = if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else if path.exists() {
+        std::fs::remove_file(path)
+    } else {
+        Ok(())
+    };
+
+    if let Err(err) = result {
+        log::warn!("Failed to clean up {}: {err}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_cleanup_guard() {
+        let dir = Builder::new().prefix("cleanup_guard").tempdir().unwrap();
+
+        let removed = dir.path().join("removed");
+        std::fs::create_dir_all(removed.join("nested")).unwrap();
+        std::fs::write(removed.join("nested").join("file"), b"data").unwrap();
+        drop(CleanupGuard::new(&removed));
+        assert!(!removed.exists());
+
+        let kept = dir.path().join("kept");
+        std::fs::write(&kept, b"data").unwrap();
+        assert_eq!(CleanupGuard::new(&kept).commit(), kept);
+        assert!(kept.exists());
+
+        // Nothing to remove
+        drop(CleanupGuard::new(dir.path().join("missing")));
+    }
+
+    #[test]
+    fn test_cleanup_guard_new_entries() {
+        let dir = Builder::new().prefix("cleanup_guard").tempdir().unwrap();
+        std::fs::write(dir.path().join("existing"), b"data").unwrap();
+
+        let guard = CleanupGuard::new_entries(dir.path()).unwrap();
+        std::fs::write(dir.path().join("created"), b"data").unwrap();
+        std::fs::create_dir_all(dir.path().join("created_dir").join("nested")).unwrap();
+        drop(guard);
+
+        let entries: Vec<_> = std::fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec![OsString::from("existing")]);
+
+        // Committed guard keeps new entries
+        let guard = CleanupGuard::new_entries(dir.path()).unwrap();
+        std::fs::write(dir.path().join("created"), b"data").unwrap();
+        guard.commit();
+        assert!(dir.path().join("created").exists());
+
+        // Missing directory has nothing to remove
+        drop(CleanupGuard::new_entries(dir.path().join("missing")).unwrap());
+    }
+}
@@ -1,18 +1,43 @@
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use ringbuffer::{ConstGenericRingBuffer, RingBuffer as _};
+use ringbuffer::{AllocRingBuffer, RingBuffer as _};
+use serde::{Deserialize, Serialize};
 
 /// A progress ETA calculator.
-/// Calculates the ETA roughly based on the last ten seconds of measurements.
-pub struct EtaCalculator(ConstGenericRingBuffer<(Instant, usize), { Self::SIZE }>);
+/// Calculates the ETA roughly based on the last ten seconds of measurements, by default.
+///
+/// Measurements are kept in a ring allocated on the heap, each covering at least 625 ms. The
+/// capacity of the ring defines the time window to consider.
+pub struct EtaCalculator(AllocRingBuffer<(Instant, usize)>);
+
+/// Serializable state of an [`EtaCalculator`], see [`EtaCalculator::snapshot`]
+///
+/// `Instant`s can't be persisted, so measurements are stored by their age at the time of the
+/// snapshot.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EtaSnapshot {
+    capacity: usize,
+    /// Age and progress of every measurement, oldest first
+    measurements: Vec<(Duration, usize)>,
+}
 
 impl EtaCalculator {
-    const SIZE: usize = 16;
+    const DEFAULT_CAPACITY: usize = 16;
     const DURATION: Duration = Duration::from_millis(625);
 
     #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
-        Self::new_raw(Instant::now())
+        Self::with_capacity(Self::DEFAULT_CAPACITY)
+    }
+
+    /// Create a calculator keeping up to `capacity` measurements, based on the last
+    /// `capacity * 625 ms` of progress.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `capacity` is zero.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self::new_raw(Instant::now(), capacity)
     }
 
     /// Capture the current progress and time.
@@ -25,14 +50,66 @@ impl EtaCalculator {
         self.estimate_raw(Instant::now(), target_progress)
     }
 
-    fn new_raw(now: Instant) -> Self {
-        Self([(now, 0)].as_ref().into())
+    /// Calculate the wall-clock time at which the target progress is reached.
+    ///
+    /// Returns `None` if the ETA is unknown.
+    pub fn estimated_completion(&self, target_progress: usize) -> Option<SystemTime> {
+        self.estimated_completion_raw(Instant::now(), SystemTime::now(), target_progress)
+    }
+
+    /// Capture the state of this calculator, e.g. to persist it with a resumable operation.
+    pub fn snapshot(&self) -> EtaSnapshot {
+        self.snapshot_raw(Instant::now())
+    }
+
+    /// Continue from a state captured by [`EtaCalculator::snapshot`].
+    ///
+    /// Measurements are rebased onto the current time, so the time between snapshot and restore
+    /// is not counted as a stall.
+    pub fn restore(snapshot: EtaSnapshot) -> Self {
+        Self::restore_raw(Instant::now(), snapshot)
+    }
+
+    fn new_raw(now: Instant, capacity: usize) -> Self {
+        let mut ring = AllocRingBuffer::new(capacity);
+        ring.push((now, 0));
+        Self(ring)
+    }
+
+    fn snapshot_raw(&self, now: Instant) -> EtaSnapshot {
+        EtaSnapshot {
+            capacity: self.0.capacity(),
+            measurements: self
+                .0
+                .iter()
+                .map(|&(time, progress)| (now.saturating_duration_since(time), progress))
+                .collect(),
+        }
+    }
+
+    fn restore_raw(now: Instant, snapshot: EtaSnapshot) -> Self {
+        let capacity = snapshot.capacity.max(1);
+        // Measurements older than the monotonic clock can't be represented, and are dropped
+        let measurements: Vec<_> = snapshot
+            .measurements
+            .into_iter()
+            .filter_map(|(age, progress)| Some((now.checked_sub(age)?, progress)))
+            .collect();
+        if measurements.is_empty() {
+            return Self::new_raw(now, capacity);
+        }
+
+        let mut ring = AllocRingBuffer::new(capacity);
+        for measurement in measurements {
+            ring.push(measurement);
+        }
+        Self(ring)
     }
 
     fn set_progress_raw(&mut self, now: Instant, current_progress: usize) {
         if self.0.back().map_or(false, |(_, l)| current_progress < *l) {
             // Progress went backwards, reset the state.
-            *self = Self::new();
+            *self = Self::new_raw(now, self.0.capacity());
         }
 
         // Consider this progress history: `[recent, older, even_older, ..., oldest]`.
@@ -61,7 +138,7 @@ impl EtaCalculator {
         let &(old_time, old_progress) = self
             .0
             .iter()
-            .find(|(time, _)| now - *time <= Self::DURATION * Self::SIZE as u32)?;
+            .find(|(time, _)| now - *time <= Self::DURATION * self.0.capacity() as u32)?;
 
         if last_progress == old_progress {
             // No progress, no rate.
@@ -73,6 +150,21 @@ impl EtaCalculator {
         let eta = (value_diff as f64 / rate - elapsed).max(0.0);
         Duration::try_from_secs_f64(eta).ok()
     }
+
+    /// Measurements are based on monotonic `now`, only the result is projected onto the
+    /// wall-clock `system_now`, taken at the same moment.
+    fn estimated_completion_raw(
+        &self,
+        now: Instant,
+        system_now: SystemTime,
+        target_progress: usize,
+    ) -> Option<SystemTime> {
+        let eta = self.estimate_raw(now, target_progress)?;
+        if eta == Duration::MAX {
+            return None;
+        }
+        system_now.checked_add(eta)
+    }
 }
 
 #[cfg(test)]
@@ -117,4 +209,113 @@ mod tests {
         assert_eq!(eta.estimate_raw(now, 60).unwrap(), Duration::from_secs(0));
         assert_eq!(eta.estimate_raw(now, 50).unwrap(), Duration::from_secs(0));
     }
+
+    #[test]
+    fn test_eta_calculator_snapshot() {
+        let mut now = Instant::now();
+        let mut eta = EtaCalculator::new_raw(now, EtaCalculator::DEFAULT_CAPACITY);
+
+        let delta = Duration::from_millis(500);
+        for i in 0..=40 {
+            now += delta;
+            eta.set_progress_raw(now, i);
+        }
+        let estimate = eta.estimate_raw(now, 100).unwrap();
+
+        let snapshot = eta.snapshot_raw(now);
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let snapshot: EtaSnapshot = serde_json::from_str(&json).unwrap();
+
+        // Restored much later, the downtime doesn't count as a stall
+        let later = now + Duration::from_secs(3600);
+        let restored = EtaCalculator::restore_raw(later, snapshot.clone());
+        assert_eq!(restored.0.capacity(), eta.0.capacity());
+        assert_eq!(restored.snapshot_raw(later), snapshot);
+        assert_relative_eq!(
+            restored.estimate_raw(later, 100).unwrap().as_secs_f64(),
+            estimate.as_secs_f64(),
+            max_relative = 0.01,
+        );
+
+        // Progress continues from the restored state
+        let mut restored = restored;
+        let mut later = later;
+        for i in 41..=50 {
+            later += delta;
+            restored.set_progress_raw(later, i);
+        }
+        assert_relative_eq!(
+            restored.estimate_raw(later, 100).unwrap().as_secs_f64(),
+            ((100 - 50) * delta).as_secs_f64(),
+            max_relative = 0.02,
+        );
+    }
+
+    #[test]
+    fn test_eta_calculator_capacity() {
+        for capacity in [1, 2, 4, 16, 33] {
+            let mut now = Instant::now();
+            let mut eta = EtaCalculator::new_raw(now, capacity);
+
+            // Every measurement is pushed, and wraps around many times
+            let delta = Duration::from_millis(700);
+            for i in 0..=100 {
+                now += delta;
+                eta.set_progress_raw(now, i);
+            }
+            assert_eq!(eta.0.capacity(), capacity);
+            assert_eq!(eta.0.len(), capacity);
+            assert_eq!(eta.0.front().unwrap().1, 101 - capacity);
+            assert_eq!(eta.0.back().unwrap().1, 100);
+
+            let estimate = eta.estimate_raw(now, 200);
+            if capacity == 1 {
+                // Single measurement, no rate
+                assert!(estimate.is_none());
+            } else {
+                assert_relative_eq!(
+                    estimate.unwrap().as_secs_f64(),
+                    (100 * delta).as_secs_f64(),
+                    max_relative = 0.02,
+                );
+            }
+
+            // Resetting on backwards progress keeps the capacity
+            eta.set_progress_raw(now, 0);
+            assert_eq!(eta.0.capacity(), capacity);
+        }
+    }
+
+    #[test]
+    fn test_eta_calculator_estimated_completion() {
+        let mut now = Instant::now();
+        let system_now = SystemTime::now();
+        let mut eta = EtaCalculator::new_raw(now, EtaCalculator::DEFAULT_CAPACITY);
+
+        let delta = Duration::from_millis(500);
+        for i in 0..=40 {
+            now += delta;
+            eta.set_progress_raw(now, i);
+        }
+
+        let estimate = eta.estimate_raw(now, 100).unwrap();
+        let completion = eta.estimated_completion_raw(now, system_now, 100).unwrap();
+        assert_eq!(completion, system_now + estimate);
+        assert_relative_eq!(
+            completion.duration_since(system_now).unwrap().as_secs_f64(),
+            ((100 - 40) * delta).as_secs_f64(),
+            max_relative = 0.02,
+        );
+
+        // Already reached
+        assert_eq!(
+            eta.estimated_completion_raw(now, system_now, 40),
+            Some(system_now),
+        );
+
+        // Unknown on a stall
+        assert!(eta
+            .estimated_completion_raw(now + Duration::from_secs(20), system_now, 100)
+            .is_none());
+    }
 }
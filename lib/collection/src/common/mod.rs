@@ -1,4 +1,5 @@
 pub mod batching;
+pub mod cleanup_guard;
 pub mod eta_calculator;
 pub mod fetch_vectors;
 pub mod file_utils;
@@ -17,6 +17,7 @@ pub mod validation;
 pub mod vector_ops;
 pub mod vector_params_builder;
 
+use std::cmp::Ordering;
 use std::collections::HashMap;
 
 use segment::json_path::JsonPath;
@@ -109,6 +110,27 @@ impl ClockTag {
         self.force = force;
         self
     }
+
+    /// Whether both tags are from the same clock, i.e. have the same `(peer_id, clock_id)`
+    pub fn is_same_clock(&self, other: &Self) -> bool {
+        self.peer_id == other.peer_id && self.clock_id == other.clock_id
+    }
+
+    /// Order tags of the same clock by their tick
+    ///
+    /// Tags of different clocks are incomparable, and return `None`. Unlike `PartialOrd`, this
+    /// ignores the token and force flag, so tags with the same tick compare as equal.
+    pub fn tick_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.is_same_clock(other)
+            .then(|| self.clock_tick.cmp(&other.clock_tick))
+    }
+
+    /// Whether this tag has a higher tick than `other` of the same clock
+    ///
+    /// Always `false` for tags of different clocks, see [`ClockTag::tick_cmp`].
+    pub fn is_newer_than(&self, other: &Self) -> bool {
+        self.tick_cmp(other) == Some(Ordering::Greater)
+    }
 }
 
 impl From<api::grpc::qdrant::ClockTag> for ClockTag {
@@ -334,6 +356,33 @@ mod tests {
         }
     }
 
+    #[test]
+    fn clock_tag_tick_ordering() {
+        let tag = ClockTag::new(1, 0, 10);
+
+        // Same clock is ordered by tick, regardless of token
+        assert_eq!(
+            tag.tick_cmp(&ClockTag::new(1, 0, 5)),
+            Some(Ordering::Greater)
+        );
+        assert_eq!(
+            tag.tick_cmp(&ClockTag::new(1, 0, 10)),
+            Some(Ordering::Equal)
+        );
+        assert_eq!(tag.tick_cmp(&ClockTag::new(1, 0, 11)), Some(Ordering::Less));
+        assert!(tag.is_newer_than(&ClockTag::new(1, 0, 5)));
+        assert!(!tag.is_newer_than(&ClockTag::new(1, 0, 10)));
+        assert!(!tag.is_newer_than(&ClockTag::new(1, 0, 11)));
+
+        // Different peer or clock ID is incomparable
+        for other in [ClockTag::new(2, 0, 5), ClockTag::new(1, 1, 5)] {
+            assert!(!tag.is_same_clock(&other));
+            assert_eq!(tag.tick_cmp(&other), None);
+            assert!(!tag.is_newer_than(&other));
+            assert!(!other.is_newer_than(&tag));
+        }
+    }
+
     impl Arbitrary for OperationWithClockTag {
         type Parameters = ();
         type Strategy = BoxedStrategy<Self>;
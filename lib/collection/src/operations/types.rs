@@ -1026,6 +1026,10 @@ impl From<OperationError> for CollectionError {
                 description: "Conversion between multi and regular vectors failed".to_string(),
             },
             OperationError::WrongPayloadKey { description } => Self::BadInput { description },
+            OperationError::ImmutableIndex { .. } => Self::ServiceError {
+                error: format!("{err}"),
+                backtrace: None,
+            },
         }
     }
 }
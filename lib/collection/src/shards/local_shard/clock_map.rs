@@ -1,9 +1,13 @@
-use std::collections::{hash_map, HashMap};
+use std::collections::{hash_map, HashMap, VecDeque};
 use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
 use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use api::grpc::qdrant::RecoveryPointClockTag;
-use io::file_operations;
+use atomicwrites::{AtomicFile, OverwriteBehavior};
 use serde::{Deserialize, Serialize};
 use tonic::Status;
 
@@ -17,9 +21,80 @@ pub struct ClockMap {
     clocks: HashMap<Key, Clock>,
     /// Whether this clock map has changed since the last time it was persisted.
     changed: bool,
+    /// When any clock in this map was last advanced.
+    ///
+    /// Not persisted, so it is `None` until the first clock is advanced after loading.
+    last_advance: Option<Instant>,
+    /// Whether this clock map is in-memory only, and must never be persisted.
+    in_memory: bool,
+    /// Log of recent accept/reject decisions, if enabled. Not persisted.
+    decision_log: Option<ClockDecisionLog>,
+    /// Sink for accept/reject metrics, if set. Not persisted.
+    metrics: Option<ClockMetricsSink>,
+    /// Number of ticks behind the current tick, that operations are still accepted for.
+    /// Not persisted.
+    grace: u64,
+    /// Number of operations accepted only because of `force`. Not persisted.
+    forced_operations: u64,
 }
 
 impl ClockMap {
+    /// Create an empty clock map, that is never persisted to disk
+    ///
+    /// Storing an in-memory clock map is a no-op.
+    pub fn in_memory() -> Self {
+        Self {
+            in_memory: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_in_memory(&self) -> bool {
+        self.in_memory
+    }
+
+    /// Create an empty clock map, that logs the last `capacity` accept/reject decisions
+    ///
+    /// The decision log is meant for debugging replication divergence and is disabled by default.
+    pub fn with_decision_log(capacity: usize) -> Self {
+        Self {
+            decision_log: Some(ClockDecisionLog::new(capacity)),
+            ..Default::default()
+        }
+    }
+
+    /// Create an empty clock map, that accepts operations up to `grace` ticks behind the current
+    /// tick, e.g. to not drop retried operations during recovery
+    ///
+    /// A `grace` of 0 is strict rejection of all operations that are not newer than the clock.
+    pub fn with_grace(grace: u64) -> Self {
+        Self {
+            grace,
+            ..Default::default()
+        }
+    }
+
+    /// Report accept/reject decisions of [`ClockMap::advance_clock_and_correct_tag`] to `metrics`
+    pub fn set_metrics(&mut self, metrics: Arc<dyn ClockMetrics>) {
+        self.metrics = Some(ClockMetricsSink(metrics));
+    }
+
+    /// Number of operations accepted only because they were forced, and would have been rejected
+    /// otherwise, since this clock map was created or loaded
+    pub fn forced_operations_count(&self) -> u64 {
+        self.forced_operations
+    }
+
+    /// Get recent accept/reject decisions, oldest first
+    ///
+    /// Returns an empty list if the decision log is not enabled.
+    pub fn recent_decisions(&self) -> Vec<ClockDecisionRecord> {
+        self.decision_log
+            .as_ref()
+            .map(|log| log.records.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
     pub fn load_or_default(path: &Path) -> Result<Self> {
         let result = Self::load(path);
 
@@ -33,16 +108,49 @@ impl ClockMap {
     }
 
     pub fn load(path: &Path) -> Result<Self> {
-        let clock_map = file_operations::read_json(path)?;
+        Self::load_from(BufReader::new(File::open(path)?))
+    }
+
+    /// Load a clock map from `reader`, e.g. from a snapshot archive
+    pub fn load_from<R: Read>(reader: R) -> Result<Self> {
+        let clock_map = serde_json::from_reader(reader)?;
         Ok(clock_map)
     }
 
+    /// Whether this clock map tracks any clocks.
+    pub fn is_empty(&self) -> bool {
+        self.clocks.is_empty()
+    }
+
+    /// Number of clocks tracked by this clock map.
+    pub fn len(&self) -> usize {
+        self.clocks.len()
+    }
+
+    /// Persist this clock map at `path`
+    ///
+    /// This is a no-op for an in-memory clock map.
     pub fn store(&mut self, path: &Path) -> Result<()> {
-        file_operations::atomic_save_json(path, self)?;
+        if self.in_memory {
+            return Ok(());
+        }
+
+        AtomicFile::new(path, OverwriteBehavior::AllowOverwrite)
+            .write(|file| self.store_to(BufWriter::new(file)))?;
         self.changed = false;
         Ok(())
     }
 
+    /// Write this clock map to `writer`, e.g. into a snapshot archive
+    ///
+    /// Unlike [`ClockMap::store`], this also writes an in-memory clock map, and does not reset
+    /// the changed flag, because the persisted clock map is not updated.
+    pub fn store_to<W: Write>(&self, mut writer: W) -> Result<()> {
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()?;
+        Ok(())
+    }
+
     pub fn store_if_changed(&mut self, path: &Path) -> Result<()> {
         if self.changed {
             self.store(path)?;
@@ -72,16 +180,78 @@ impl ClockMap {
         //   - (this is handled by `advance_clock_impl`, so we don't need to check for `clock_tick = 0` explicitly)
         //
         // TODO: Should we *reject* operations with `force = true`, *if* `clock_tick = 0`!?
+        //
+        // With a grace window, we also *accept* operations at most `grace` ticks behind
+        // `current_tick`, without correcting their `clock_tick`.
+
+        let accepted_unforced =
+            clock_accepted || self.is_within_grace(clock_tag.clock_tick, current_tick);
+        let operation_accepted = accepted_unforced || clock_tag.force;
+
+        if !accepted_unforced && clock_tag.force {
+            self.forced_operations += 1;
+            log::info!(
+                "Force accepted stale operation of peer {}, clock {} at tick {}, \
+                 current tick is {current_tick}",
+                clock_tag.peer_id,
+                clock_tag.clock_id,
+                clock_tag.clock_tick,
+            );
+        }
 
-        let operation_accepted = clock_accepted || clock_tag.force;
+        let original_tag = *clock_tag;
 
         if !operation_accepted {
             clock_tag.clock_tick = current_tick;
         }
 
+        if let Some(ClockMetricsSink(metrics)) = &self.metrics {
+            if operation_accepted {
+                metrics.on_accept();
+            } else {
+                metrics.on_reject();
+                if clock_tag.clock_tick > original_tag.clock_tick {
+                    metrics.on_correction(clock_tag.clock_tick - original_tag.clock_tick);
+                }
+            }
+        }
+
+        if let Some(decision_log) = &mut self.decision_log {
+            decision_log.push(ClockDecisionRecord {
+                clock_tag: original_tag,
+                accepted: operation_accepted,
+                corrected_tick: clock_tag.clock_tick,
+            });
+        }
+
         operation_accepted
     }
 
+    /// Tick `clock_tag` would be corrected to by [`ClockMap::advance_clock_and_correct_tag`]
+    ///
+    /// Returns `None` if the operation would be accepted as-is. Does not change the clock map.
+    pub fn peek_correction(&self, clock_tag: &ClockTag) -> Option<u64> {
+        let new_tick = clock_tag.clock_tick;
+
+        let (clock_accepted, current_tick) = match self.clocks.get(&Key::from_tag(*clock_tag)) {
+            Some(clock) => clock.peek_advance_to(new_tick, clock_tag.token),
+            // New clocks are initialized, unless `new_tick = 0`
+            None => (new_tick > 0, new_tick),
+        };
+
+        let operation_accepted = clock_accepted
+            || clock_tag.force
+            || self.is_within_grace(clock_tag.clock_tick, current_tick);
+        (!operation_accepted).then_some(current_tick)
+    }
+
+    /// Whether `tick` is at most `grace` ticks behind `current_tick`
+    ///
+    /// Operations with `clock_tick = 0` are never within the grace window.
+    fn is_within_grace(&self, tick: u64, current_tick: u64) -> bool {
+        self.grace > 0 && tick > 0 && current_tick.saturating_sub(tick) <= self.grace
+    }
+
     /// Advance clock referenced by `clock_tag` to `clock_tick`, if it's newer than current tick.
     ///
     /// If the clock is not yet tracked by the `ClockMap`, it is initialized to
@@ -90,6 +260,19 @@ impl ClockMap {
         let _ = self.advance_clock_impl(clock_tag);
     }
 
+    /// Advance clock `clock_id` of `peer_id` to at least `tick`, without an actual operation,
+    /// e.g. for heartbeats or synthetic clock advancement during recovery
+    ///
+    /// If the clock is not yet tracked, it is initialized to `tick`, unless `tick = 0`. An
+    /// advanced clock gets a fresh token, so an operation with exactly `tick` is rejected after.
+    ///
+    /// Returns the current tick of the clock.
+    pub fn touch(&mut self, peer_id: PeerId, clock_id: u32, tick: u64) -> u64 {
+        let (_accepted, current_tick) =
+            self.advance_clock_impl(ClockTag::new(peer_id, clock_id, tick));
+        current_tick
+    }
+
     /// Advance clock referenced by `clock_tag` to `clock_tick`, if it's newer than current tick.
     ///
     /// If the clock is not yet tracked by the `ClockMap`, it is initialized to
@@ -102,8 +285,19 @@ impl ClockMap {
         let new_tick = clock_tag.clock_tick;
         let new_token = clock_tag.token;
 
+        let now = Instant::now();
+
         let (is_accepted, new_tick) = match self.clocks.entry(key) {
-            hash_map::Entry::Occupied(mut entry) => entry.get_mut().advance_to(new_tick, new_token),
+            hash_map::Entry::Occupied(mut entry) => {
+                let clock = entry.get_mut();
+                let (is_accepted, new_tick) = clock.advance_to(new_tick, new_token);
+
+                if is_accepted {
+                    clock.last_advance = Some(now);
+                }
+
+                (is_accepted, new_tick)
+            }
             hash_map::Entry::Vacant(entry) => {
                 // Initialize new clock and accept the operation if `new_tick > 0`.
                 // Reject the operation if `new_tick = 0`.
@@ -111,7 +305,7 @@ impl ClockMap {
                 let is_non_zero_tick = new_tick > 0;
 
                 if is_non_zero_tick {
-                    entry.insert(Clock::new(new_tick, new_token));
+                    entry.insert(Clock::new(new_tick, new_token)).last_advance = Some(now);
                 }
 
                 (is_non_zero_tick, new_tick)
@@ -121,6 +315,7 @@ impl ClockMap {
         // Assume the state changed when the clock tag was accepted
         if is_accepted {
             self.changed = true;
+            self.last_advance = Some(now);
         }
 
         (is_accepted, new_tick)
@@ -138,9 +333,50 @@ impl ClockMap {
                 .iter()
                 .map(|(&key, clock)| (key, (clock.current_tick, clock.token)))
                 .collect(),
+            last_update: self.last_advance,
         }
     }
 
+    /// Get the time any clock in this clock map was last advanced.
+    ///
+    /// Returns `None` if no clock was advanced since this clock map was created or loaded.
+    pub fn last_advance(&self) -> Option<Instant> {
+        self.last_advance
+    }
+
+    /// Remove clocks that were not advanced for longer than `max_age`, e.g. short-lived clocks
+    ///
+    /// Clocks that were not advanced since this clock map was loaded are kept, because their age
+    /// is unknown.
+    ///
+    /// Returns the number of removed clocks.
+    pub fn expire_stale(&mut self, max_age: Duration) -> usize {
+        let clocks_before = self.clocks.len();
+
+        self.clocks.retain(|_, clock| {
+            clock
+                .last_advance
+                .map_or(true, |last_advance| last_advance.elapsed() <= max_age)
+        });
+
+        let removed = clocks_before - self.clocks.len();
+        if removed > 0 {
+            self.changed = true;
+        }
+        removed
+    }
+
+    /// Get the highest tick over all clocks of `peer_id`, e.g. to monitor replication lag.
+    ///
+    /// Returns `None` if no clock of the peer is tracked.
+    pub fn max_tick_for_peer(&self, peer_id: PeerId) -> Option<u64> {
+        self.clocks
+            .iter()
+            .filter(|(key, _)| key.peer_id == peer_id)
+            .map(|(_, clock)| clock.current_tick)
+            .max()
+    }
+
     #[cfg(test)]
     pub fn current_tick(&self, peer_id: PeerId, clock_id: u32) -> Option<u64> {
         self.clocks
@@ -149,6 +385,74 @@ impl ClockMap {
     }
 }
 
+/// A single accept/reject decision made by [`ClockMap::advance_clock_and_correct_tag`]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ClockDecisionRecord {
+    /// Clock tag as received, before correction
+    pub clock_tag: ClockTag,
+    pub accepted: bool,
+    /// Clock tick returned to the sender, equal to the received tick if accepted
+    pub corrected_tick: u64,
+}
+
+/// Metrics of the accept/reject decisions made by [`ClockMap::advance_clock_and_correct_tag`]
+///
+/// All methods are no-ops by default.
+pub trait ClockMetrics: Send + Sync {
+    /// An operation was accepted
+    fn on_accept(&self) {}
+
+    /// An operation was rejected
+    fn on_reject(&self) {}
+
+    /// The clock tag of a rejected operation was corrected forward by `delta` ticks
+    fn on_correction(&self, _delta: u64) {}
+}
+
+/// Shared metrics sink of a clock map, only equal to a clone of itself
+#[derive(Clone)]
+struct ClockMetricsSink(Arc<dyn ClockMetrics>);
+
+impl fmt::Debug for ClockMetricsSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ClockMetricsSink").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for ClockMetricsSink {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+/// Bounded log of the most recent clock decisions
+#[derive(Clone, Debug, Default, PartialEq)]
+struct ClockDecisionLog {
+    capacity: usize,
+    records: VecDeque<ClockDecisionRecord>,
+}
+
+impl ClockDecisionLog {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    fn push(&mut self, record: ClockDecisionRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+
+        self.records.push_back(record);
+    }
+}
+
 #[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Deserialize, Serialize)]
 pub struct Key {
     peer_id: PeerId,
@@ -165,10 +469,15 @@ impl Key {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[derive(Copy, Clone, Debug, Deserialize, Serialize)]
 struct Clock {
     current_tick: u64,
     token: ClockToken,
+    /// When this clock was last advanced.
+    ///
+    /// Not persisted, so it is `None` until the clock is advanced after loading.
+    #[serde(skip)]
+    last_advance: Option<Instant>,
 }
 
 impl Clock {
@@ -176,6 +485,7 @@ impl Clock {
         Self {
             current_tick,
             token,
+            last_advance: None,
         }
     }
 
@@ -198,20 +508,42 @@ impl Clock {
         (operation_accepted, self.current_tick)
     }
 
+    /// Same as [`Clock::advance_to`], but without updating the clock.
+    fn peek_advance_to(&self, new_tick: u64, new_token: ClockToken) -> (bool, u64) {
+        let mut clock = *self;
+        clock.advance_to(new_tick, new_token)
+    }
+
     #[cfg(test)]
     fn current_tick(&self) -> u64 {
         self.current_tick
     }
 }
 
+/// Clocks are equal if they have the same tick and token, regardless of when they were advanced
+impl PartialEq for Clock {
+    fn eq(&self, other: &Self) -> bool {
+        self.current_tick == other.current_tick && self.token == other.token
+    }
+}
+
+impl Eq for Clock {}
+
 /// A recovery point, being a list of distributed clocks with their tick value and unique token
 ///
 /// The recovery point describes from what point we want to get operations from another node in
 /// case of recovery. In other words, the recovery point has the first clock tick values the
 /// recovering node has not seen yet.
-#[derive(Clone, Debug, Default)]
+///
+/// Serialized in the same format as [`ClockMap`], without the last update time.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+#[serde(from = "ClockMapHelper", into = "ClockMapHelper")]
 pub struct RecoveryPoint {
     clocks: HashMap<Key, (u64, ClockToken)>,
+    /// When any clock was last advanced on the node this recovery point was created on.
+    ///
+    /// Only known for local recovery points, it is not transferred over gRPC.
+    last_update: Option<Instant>,
 }
 
 impl RecoveryPoint {
@@ -219,6 +551,16 @@ impl RecoveryPoint {
         self.clocks.is_empty()
     }
 
+    /// Number of clocks in this recovery point.
+    pub fn len(&self) -> usize {
+        self.clocks.len()
+    }
+
+    /// Get the time any clock was last advanced, at the moment this recovery point was created.
+    pub fn last_update(&self) -> Option<Instant> {
+        self.last_update
+    }
+
     /// Iterate over all recovery point entries as clock tags.
     pub fn iter_as_clock_tags(&self) -> impl Iterator<Item = ClockTag> + '_ {
         self.clocks.iter().map(|(key, &(tick, token))| {
@@ -311,6 +653,71 @@ impl RecoveryPoint {
         is_equal
     }
 
+    /// Check whether the operation tagged with `tag` must be replayed to the peer this recovery
+    /// point was received from.
+    ///
+    /// An operation is missing on the peer if its tick is *newer* than the tick of its clock in
+    /// this recovery point, or if the clock is not in this recovery point at all.
+    pub fn should_replay(&self, tag: &ClockTag) -> bool {
+        self.clocks
+            .get(&Key::from_tag(*tag))
+            .map_or(true, |&(tick, _token)| tag.clock_tick > tick)
+    }
+
+    /// Compute which clock ticks the `behind` recovery point is missing compared to this one.
+    ///
+    /// For every clock that is newer in this recovery point, the diff contains the range of
+    /// ticks `behind` must catch up on. Clocks that are not present in `behind` at all must be
+    /// caught up on from tick 1. Clocks that are equal or older in this recovery point are not
+    /// part of the diff, but are kept with their tick in this recovery point, so that the diff
+    /// can still be sent as a complete recovery point.
+    pub fn diff(&self, behind: &Self) -> RecoveryPointDiff {
+        let mut diff = RecoveryPointDiff::default();
+
+        for (&key, &(tick, token)) in &self.clocks {
+            let range = match behind.clocks.get(&key) {
+                Some(&(behind_tick, _)) if behind_tick >= tick => {
+                    diff.current.insert(key, (tick, token));
+                    continue;
+                }
+                Some(&(behind_tick, _)) => ClockRange {
+                    from_tick: behind_tick + 1,
+                    to_tick: tick,
+                    token,
+                    is_missing: false,
+                },
+                None => ClockRange {
+                    from_tick: 1,
+                    to_tick: tick,
+                    token,
+                    is_missing: true,
+                },
+            };
+
+            diff.clocks.insert(key, range);
+        }
+
+        diff
+    }
+
+    /// Check whether this (target) recovery point can catch up on `source` by replaying the WAL
+    /// of the source, rather than transferring a full snapshot.
+    ///
+    /// `wal_first_tick_per_clock` holds the first tick of every clock still present in the WAL of
+    /// the source, keyed by `(peer_id, clock_id)`. Returns `false` if, for any clock this
+    /// recovery point lags behind on, the needed ticks were already truncated from the WAL.
+    pub fn is_reconcilable_with(
+        &self,
+        source: &RecoveryPoint,
+        wal_first_tick_per_clock: &HashMap<(PeerId, u32), u64>,
+    ) -> bool {
+        source.diff(self).iter().all(|(peer_id, clock_id, range)| {
+            wal_first_tick_per_clock
+                .get(&(peer_id, clock_id))
+                .map_or(false, |&first_tick| range.from_tick >= first_tick)
+        })
+    }
+
     #[cfg(test)]
     pub(crate) fn insert(&mut self, peer_id: PeerId, clock_id: u32, clock_tick: u64) {
         let random_token = rand::random::<ClockToken>();
@@ -319,6 +726,53 @@ impl RecoveryPoint {
     }
 }
 
+/// Difference between two recovery points, produced by [`RecoveryPoint::diff`]
+///
+/// Lists, per clock, the range of clock ticks a lagging peer must catch up on.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RecoveryPointDiff {
+    clocks: HashMap<Key, ClockRange>,
+    /// Clocks the lagging peer is not behind on, with their tick and token in the recovery point
+    /// the diff was taken from
+    current: HashMap<Key, (u64, ClockToken)>,
+}
+
+impl RecoveryPointDiff {
+    pub fn is_empty(&self) -> bool {
+        self.clocks.is_empty()
+    }
+
+    /// Number of clocks the lagging peer must catch up on.
+    pub fn len(&self) -> usize {
+        self.clocks.len()
+    }
+
+    /// Get the range of clock ticks to catch up on for the given clock, if it is lagging.
+    pub fn get(&self, peer_id: PeerId, clock_id: u32) -> Option<ClockRange> {
+        self.clocks.get(&Key::new(peer_id, clock_id)).copied()
+    }
+
+    /// Iterate over all lagging clocks as `(peer_id, clock_id, range)`.
+    pub fn iter(&self) -> impl Iterator<Item = (PeerId, u32, ClockRange)> + '_ {
+        self.clocks
+            .iter()
+            .map(|(key, &range)| (key.peer_id, key.clock_id, range))
+    }
+}
+
+/// Inclusive range of clock ticks a lagging peer must catch up on for a single clock
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct ClockRange {
+    /// First tick the lagging peer has not seen yet.
+    pub from_tick: u64,
+    /// Last tick the lagging peer must catch up to.
+    pub to_tick: u64,
+    /// Token of the clock at `to_tick`.
+    pub token: ClockToken,
+    /// Whether the lagging peer does not know about this clock at all.
+    pub is_missing: bool,
+}
+
 impl fmt::Display for RecoveryPoint {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "RecoveryPoint[")?;
@@ -364,22 +818,65 @@ impl From<RecoveryPoint> for api::grpc::qdrant::RecoveryPoint {
     }
 }
 
+/// If the same clock is listed multiple times, the highest tick is kept. On equal ticks, the last
+/// listed clock wins.
 impl TryFrom<api::grpc::qdrant::RecoveryPoint> for RecoveryPoint {
     type Error = Status;
 
     fn try_from(rp: api::grpc::qdrant::RecoveryPoint) -> Result<Self, Self::Error> {
-        let clocks = rp
+        let mut clocks = HashMap::with_capacity(rp.clocks.len());
+
+        for tag in rp.clocks {
+            match clocks.entry(Key::new(tag.peer_id, tag.clock_id)) {
+                hash_map::Entry::Occupied(mut entry) => {
+                    let (tick, _token) = *entry.get();
+                    if tag.clock_tick >= tick {
+                        entry.insert((tag.clock_tick, tag.token));
+                    }
+                }
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert((tag.clock_tick, tag.token));
+                }
+            }
+        }
+
+        Ok(Self {
+            clocks,
+            last_update: None,
+        })
+    }
+}
+
+/// Transfer a diff as the recovery point of the lagging peer.
+///
+/// Every lagging clock is set to the last tick the lagging peer has seen, and all other clocks to
+/// their current tick. Resolving it against the WAL of the peer that is ahead therefore yields
+/// exactly the ticks in the diff. Omitting the other clocks would make them be replayed from
+/// tick 1.
+impl From<&RecoveryPointDiff> for api::grpc::qdrant::RecoveryPoint {
+    fn from(diff: &RecoveryPointDiff) -> Self {
+        let lagging = diff
             .clocks
-            .into_iter()
-            .map(|tag| {
-                (
-                    Key::new(tag.peer_id, tag.clock_id),
-                    (tag.clock_tick, tag.token),
-                )
-            })
-            .collect();
+            .iter()
+            .map(|(key, range)| RecoveryPointClockTag {
+                peer_id: key.peer_id,
+                clock_id: key.clock_id,
+                clock_tick: range.from_tick - 1,
+                token: range.token,
+            });
+        let current =
+            diff.current
+                .iter()
+                .map(|(key, &(clock_tick, token))| RecoveryPointClockTag {
+                    peer_id: key.peer_id,
+                    clock_id: key.clock_id,
+                    clock_tick,
+                    token,
+                });
 
-        Ok(Self { clocks })
+        Self {
+            clocks: lagging.chain(current).collect(),
+        }
     }
 }
 
@@ -401,6 +898,37 @@ impl From<ClockMapHelper> for ClockMap {
         Self {
             clocks: helper.clocks.into_iter().map(Into::into).collect(),
             changed: false,
+            last_advance: None,
+            in_memory: false,
+            decision_log: None,
+            metrics: None,
+            grace: 0,
+            forced_operations: 0,
+        }
+    }
+}
+
+impl From<RecoveryPoint> for ClockMapHelper {
+    fn from(recovery_point: RecoveryPoint) -> Self {
+        Self {
+            clocks: recovery_point
+                .clocks
+                .into_iter()
+                .map(|(key, (tick, token))| (key, Clock::new(tick, token)).into())
+                .collect(),
+        }
+    }
+}
+
+impl From<ClockMapHelper> for RecoveryPoint {
+    fn from(helper: ClockMapHelper) -> Self {
+        Self {
+            clocks: helper
+                .clocks
+                .into_iter()
+                .map(|KeyClockHelper { key, clock }| (key, (clock.current_tick, clock.token)))
+                .collect(),
+            last_update: None,
         }
     }
 }
@@ -434,12 +962,11 @@ pub enum Error {
     SerdeJson(#[from] serde_json::Error),
 }
 
-impl From<file_operations::Error> for Error {
-    fn from(err: file_operations::Error) -> Self {
+impl From<atomicwrites::Error<Error>> for Error {
+    fn from(err: atomicwrites::Error<Error>) -> Self {
         match err {
-            file_operations::Error::Io(err) => err.into(),
-            file_operations::Error::SerdeJson(err) => err.into(),
-            _ => unreachable!(),
+            atomicwrites::Error::Internal(err) => err.into(),
+            atomicwrites::Error::User(err) => err,
         }
     }
 }
@@ -455,6 +982,8 @@ impl From<Error> for CollectionError {
 
 #[cfg(test)]
 mod test {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
     use proptest::prelude::*;
 
     use super::*;
@@ -480,13 +1009,549 @@ mod test {
         let json = serde_json::to_value(&input).unwrap();
         let mut output: ClockMap = serde_json::from_value(json).unwrap();
 
-        // Propagate changed flag to allow comparison
+        // Propagate changed flag and last advance time to allow comparison
         // Normally we would not need to do this, but we bypass the regular load/store functions
         output.changed = input.changed;
+        output.last_advance = input.last_advance;
 
         assert_eq!(input, output);
     }
 
+    #[test]
+    fn clock_map_store_to_load_from() {
+        let mut input = ClockMap::default();
+        input.advance_clock(ClockTag::new(1, 1, 1));
+        input.advance_clock(ClockTag::new(2, 1, 42));
+
+        let mut buffer = Vec::new();
+        input.store_to(&mut buffer).unwrap();
+        // Writing to a stream does not persist the clock map
+        assert!(input.changed);
+
+        let mut output = ClockMap::load_from(buffer.as_slice()).unwrap();
+        assert_eq!(output.len(), 2);
+
+        // Propagate changed flag and last advance time, which are not persisted
+        output.changed = input.changed;
+        output.last_advance = input.last_advance;
+        assert_eq!(input, output);
+
+        assert!(ClockMap::load_from(&b"not a clock map"[..]).is_err());
+    }
+
+    #[test]
+    fn clock_map_expire_stale() {
+        let max_age = Duration::from_millis(100);
+
+        let mut clock_map = ClockMap::default();
+        clock_map.advance_clock(ClockTag::new(1, 0, 1));
+        clock_map.advance_clock(ClockTag::new(1, 1, 1));
+        clock_map.advance_clock(ClockTag::new(2, 0, 1));
+
+        // Nothing is stale yet
+        assert_eq!(clock_map.expire_stale(max_age), 0);
+
+        std::thread::sleep(max_age * 2);
+
+        // Advance one of the existing clocks, and a new one
+        clock_map.advance_clock(ClockTag::new(1, 1, 2));
+        clock_map.advance_clock(ClockTag::new(3, 0, 1));
+        // A rejected clock tag doesn't count as advance
+        clock_map.advance_clock(ClockTag::new(2, 0, 0));
+
+        clock_map.changed = false;
+        assert_eq!(clock_map.expire_stale(max_age), 2);
+        assert!(clock_map.changed);
+        assert_eq!(clock_map.len(), 2);
+        assert_eq!(clock_map.current_tick(1, 0), None);
+        assert_eq!(clock_map.current_tick(2, 0), None);
+        assert_eq!(clock_map.current_tick(1, 1), Some(2));
+        assert_eq!(clock_map.current_tick(3, 0), Some(1));
+
+        // Clocks loaded from disk are never advanced, and are kept
+        let json = serde_json::to_value(&clock_map).unwrap();
+        let mut loaded: ClockMap = serde_json::from_value(json).unwrap();
+        std::thread::sleep(max_age * 2);
+        assert_eq!(loaded.expire_stale(max_age), 0);
+        assert_eq!(loaded.len(), 2);
+    }
+
+    #[test]
+    fn clock_map_in_memory() {
+        let dir = tempfile::Builder::new()
+            .prefix("clock_map")
+            .tempdir()
+            .unwrap();
+        let path = dir.path().join("clock_map.json");
+
+        let mut clock_map = ClockMap::in_memory();
+        assert!(clock_map.is_in_memory());
+
+        assert!(clock_map.advance_clock_and_correct_tag(&mut ClockTag::new(1, 1, 1)));
+        clock_map.advance_clock(ClockTag::new(1, 2, 8));
+        assert_eq!(clock_map.current_tick(1, 1), Some(1));
+        assert_eq!(clock_map.current_tick(1, 2), Some(8));
+
+        let recovery_point = clock_map.to_recovery_point();
+        assert_eq!(recovery_point.len(), 2);
+
+        // Storing is a no-op
+        clock_map.store(&path).unwrap();
+        clock_map.store_if_changed(&path).unwrap();
+        assert!(!path.exists());
+
+        // Regular clock map is persisted
+        let mut clock_map = ClockMap::default();
+        assert!(!clock_map.is_in_memory());
+        clock_map.advance_clock(ClockTag::new(1, 1, 1));
+        clock_map.store(&path).unwrap();
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn clock_map_peek_correction() {
+        let mut clock_map = ClockMap::default();
+
+        let newer = tag(10);
+        let older = tag(5);
+        let same_tick_other_token = tag(10);
+        let zero = tag(0);
+        let forced = tag(3).force(true);
+
+        // New clock
+        assert_eq!(clock_map.peek_correction(&newer), None);
+        assert_eq!(clock_map.peek_correction(&zero), Some(0));
+
+        for clock_tag in [
+            newer,
+            older,
+            newer,
+            same_tick_other_token,
+            zero,
+            forced,
+            tag(11),
+        ] {
+            let snapshot = clock_map.clone();
+            let peeked = clock_map.peek_correction(&clock_tag);
+
+            // Peeking doesn't change the clock map
+            assert_eq!(clock_map, snapshot);
+
+            let mut corrected_tag = clock_tag;
+            let accepted = clock_map.advance_clock_and_correct_tag(&mut corrected_tag);
+            match peeked {
+                None => assert!(accepted),
+                Some(tick) => {
+                    assert!(!accepted);
+                    assert_eq!(corrected_tag.clock_tick, tick);
+                }
+            }
+        }
+    }
+
+    fn grpc_round_trip(recovery_point: &RecoveryPoint) -> RecoveryPoint {
+        let grpc = api::grpc::qdrant::RecoveryPoint::from(recovery_point);
+        assert_eq!(grpc.clocks.len(), recovery_point.len());
+        RecoveryPoint::try_from(grpc).unwrap()
+    }
+
+    #[test]
+    fn recovery_point_grpc_round_trip() {
+        // Empty
+        let recovery_point = RecoveryPoint::default();
+        assert!(grpc_round_trip(&recovery_point).is_empty());
+
+        // Single clock
+        let mut clock_map = ClockMap::default();
+        clock_map.advance_clock(tag(10));
+        let recovery_point = clock_map.to_recovery_point();
+        assert_eq!(
+            grpc_round_trip(&recovery_point).clocks,
+            recovery_point.clocks
+        );
+
+        // Many clocks, in arbitrary order
+        for peer_id in 0..10 {
+            for clock_id in 0..10 {
+                let tick = peer_id * 100 + u64::from(clock_id) + 1;
+                clock_map.advance_clock(ClockTag::new(peer_id, clock_id, tick));
+            }
+        }
+        let recovery_point = clock_map.to_recovery_point();
+        assert_eq!(recovery_point.len(), 101);
+        assert_eq!(
+            grpc_round_trip(&recovery_point).clocks,
+            recovery_point.clocks
+        );
+    }
+
+    #[test]
+    fn recovery_point_grpc_duplicate_clocks() {
+        let clock_tag = |clock_tick, token| RecoveryPointClockTag {
+            peer_id: PEER_ID,
+            clock_id: CLOCK_ID,
+            clock_tick,
+            token,
+        };
+
+        let grpc = api::grpc::qdrant::RecoveryPoint {
+            clocks: vec![
+                clock_tag(5, 1),
+                clock_tag(10, 2),
+                clock_tag(7, 3),
+                clock_tag(10, 4),
+                clock_tag(3, 5),
+            ],
+        };
+        let recovery_point = RecoveryPoint::try_from(grpc).unwrap();
+
+        // Highest tick is kept, the last one of equal ticks
+        assert_eq!(recovery_point.len(), 1);
+        assert_eq!(recovery_point.clocks[&Key::new(PEER_ID, CLOCK_ID)], (10, 4),);
+    }
+
+    #[test]
+    fn clock_map_max_tick_for_peer() {
+        let mut clock_map = ClockMap::default();
+        assert_eq!(clock_map.max_tick_for_peer(PEER_ID), None);
+
+        for (clock_id, tick) in [(0, 5), (1, 12), (2, 7)] {
+            clock_map.advance_clock(ClockTag::new(PEER_ID, clock_id, tick));
+        }
+        clock_map.advance_clock(ClockTag::new(PEER_ID + 1, 0, 100));
+
+        assert_eq!(clock_map.max_tick_for_peer(PEER_ID), Some(12));
+        assert_eq!(clock_map.max_tick_for_peer(PEER_ID + 1), Some(100));
+        assert_eq!(clock_map.max_tick_for_peer(PEER_ID + 2), None);
+    }
+
+    #[derive(Debug, Default)]
+    struct CountingMetrics {
+        accepted: AtomicU64,
+        rejected: AtomicU64,
+        corrections: AtomicU64,
+        corrected_ticks: AtomicU64,
+    }
+
+    impl ClockMetrics for CountingMetrics {
+        fn on_accept(&self) {
+            self.accepted.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_reject(&self) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+        }
+
+        fn on_correction(&self, delta: u64) {
+            self.corrections.fetch_add(1, Ordering::Relaxed);
+            self.corrected_ticks.fetch_add(delta, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn clock_map_metrics() {
+        let metrics = Arc::new(CountingMetrics::default());
+        let mut helper = Helper {
+            clock_map: ClockMap::default(),
+        };
+        helper.clock_map.set_metrics(metrics.clone());
+
+        let counts = || {
+            [
+                &metrics.accepted,
+                &metrics.rejected,
+                &metrics.corrections,
+                &metrics.corrected_ticks,
+            ]
+            .map(|count| count.load(Ordering::Relaxed))
+        };
+
+        helper.advance(tag(10)).assert(true, 10);
+        assert_eq!(counts(), [1, 0, 0, 0]);
+
+        // Rejected and corrected from tick 4 to 10
+        helper.advance(tag(4)).assert(false, 10);
+        assert_eq!(counts(), [1, 1, 1, 6]);
+
+        // Rejected, but tick 0 of a new clock isn't corrected
+        let new_clock = ClockTag::new(PEER_ID, CLOCK_ID + 1, 0);
+        helper.advance(new_clock).assert(false, 0);
+        assert_eq!(counts(), [1, 2, 1, 6]);
+
+        // Forced operations are accepted
+        helper.advance(tag(3).force(true)).assert(true, 3);
+        assert_eq!(counts(), [2, 2, 1, 6]);
+    }
+
+    #[test]
+    fn clock_map_decision_log() {
+        // Disabled by default
+        let mut clock_map = ClockMap::default();
+        let _ = clock_map.advance_clock_and_correct_tag(&mut tag(1));
+        assert!(clock_map.recent_decisions().is_empty());
+
+        let mut helper = Helper {
+            clock_map: ClockMap::with_decision_log(3),
+        };
+
+        let accepted = tag(10);
+        let rejected = tag(5);
+        let forced = tag(3).force(true);
+        let newer = tag(11);
+
+        helper.advance(accepted).assert(true, 10);
+        helper.advance(rejected).assert(false, 10);
+        helper.advance(forced).assert(true, 3);
+
+        let decisions = helper.clock_map.recent_decisions();
+        let expected = [
+            (accepted, true, 10),
+            (rejected, false, 10),
+            (forced, true, 3),
+        ];
+        assert_eq!(decisions.len(), expected.len());
+        for (decision, (clock_tag, accepted, corrected_tick)) in decisions.iter().zip(expected) {
+            assert_eq!(decision.clock_tag, clock_tag);
+            assert_eq!(decision.accepted, accepted);
+            assert_eq!(decision.corrected_tick, corrected_tick);
+        }
+
+        // Oldest decision is dropped once the log is full
+        helper.advance(newer).assert(true, 11);
+        let decisions = helper.clock_map.recent_decisions();
+        assert_eq!(decisions.len(), 3);
+        assert_eq!(decisions[0].clock_tag, rejected);
+        assert_eq!(decisions[2].clock_tag, newer);
+    }
+
+    #[test]
+    fn clock_map_grace() {
+        // Strict rejection without grace
+        let mut helper = Helper {
+            clock_map: ClockMap::with_grace(0),
+        };
+        helper.advance(tag(10)).assert(true, 10);
+        helper.advance(tag(10)).assert(false, 10);
+        helper.advance(tag(9)).assert(false, 10);
+
+        let mut helper = Helper {
+            clock_map: ClockMap::with_grace(3),
+        };
+        helper.advance(tag(10)).assert(true, 10);
+
+        // Ticks inside the grace window are accepted as-is, without moving the clock back
+        for tick in 7..=10 {
+            assert_eq!(helper.clock_map.peek_correction(&tag(tick)), None);
+            helper.advance(tag(tick)).assert(true, tick);
+        }
+        assert_eq!(helper.clock_map.current_tick(PEER_ID, CLOCK_ID), Some(10));
+
+        // Ticks outside the grace window are rejected and corrected
+        assert_eq!(helper.clock_map.peek_correction(&tag(6)), Some(10));
+        helper.advance(tag(6)).assert(false, 10);
+
+        // Tick 0 is always rejected
+        helper.advance(tag(0)).assert(false, 10);
+
+        // Newer ticks move the grace window forward
+        helper.advance(tag(20)).assert(true, 20);
+        helper.advance(tag(10)).assert(false, 20);
+        helper.advance(tag(17)).assert(true, 17);
+    }
+
+    #[test]
+    fn clock_map_forced_operations() {
+        let mut helper = Helper::empty();
+        assert_eq!(helper.clock_map.forced_operations_count(), 0);
+
+        // Forcing an operation that is accepted anyway is not counted
+        helper.advance(tag(10).force(true)).assert(true, 10);
+        helper.advance(tag(11)).assert(true, 11);
+        assert_eq!(helper.clock_map.forced_operations_count(), 0);
+
+        // Forcing a stale operation is counted
+        helper.advance(tag(5).force(true)).assert(true, 5);
+        assert_eq!(helper.clock_map.forced_operations_count(), 1);
+        helper.advance(tag(0).force(true)).assert(true, 0);
+        assert_eq!(helper.clock_map.forced_operations_count(), 2);
+
+        // Rejected stale operations are not counted
+        helper.advance(tag(5)).assert(false, 11);
+        assert_eq!(helper.clock_map.forced_operations_count(), 2);
+
+        // Neither are forced operations accepted within the grace window
+        let mut helper = Helper {
+            clock_map: ClockMap::with_grace(2),
+        };
+        helper.advance(tag(10)).assert(true, 10);
+        helper.advance(tag(9).force(true)).assert(true, 9);
+        assert_eq!(helper.clock_map.forced_operations_count(), 0);
+        helper.advance(tag(7).force(true)).assert(true, 7);
+        assert_eq!(helper.clock_map.forced_operations_count(), 1);
+    }
+
+    #[test]
+    fn clock_map_touch() {
+        let mut clock_map = ClockMap::default();
+
+        // Tick 0 doesn't initialize a clock
+        assert_eq!(clock_map.touch(PEER_ID, CLOCK_ID, 0), 0);
+        assert!(clock_map.is_empty());
+
+        // New clock is initialized
+        assert_eq!(clock_map.touch(PEER_ID, CLOCK_ID, 5), 5);
+        assert_eq!(clock_map.current_tick(PEER_ID, CLOCK_ID), Some(5));
+        assert!(clock_map.last_advance().is_some());
+
+        // Existing clock is advanced to a higher tick, but never moved back
+        assert_eq!(clock_map.touch(PEER_ID, CLOCK_ID, 10), 10);
+        assert_eq!(clock_map.touch(PEER_ID, CLOCK_ID, 3), 10);
+        assert_eq!(clock_map.current_tick(PEER_ID, CLOCK_ID), Some(10));
+
+        // Other clocks are not affected
+        assert_eq!(clock_map.current_tick(PEER_ID, CLOCK_ID + 1), None);
+        assert_eq!(clock_map.len(), 1);
+
+        // Operations behind the touched clock are rejected and corrected
+        let mut clock_tag = tag(7);
+        assert!(!clock_map.advance_clock_and_correct_tag(&mut clock_tag));
+        assert_eq!(clock_tag.clock_tick, 10);
+    }
+
+    #[test]
+    fn clock_map_is_empty() {
+        let mut clock_map = ClockMap::default();
+        assert!(clock_map.is_empty());
+        assert_eq!(clock_map.len(), 0);
+        assert!(clock_map.to_recovery_point().is_empty());
+        assert_eq!(clock_map.to_recovery_point().len(), 0);
+
+        // Tick 0 is rejected and does not initialize a clock
+        clock_map.advance_clock(ClockTag::new(1, 1, 0));
+        assert!(clock_map.is_empty());
+
+        clock_map.advance_clock(ClockTag::new(1, 1, 1));
+        assert!(!clock_map.is_empty());
+        assert_eq!(clock_map.len(), 1);
+        assert!(!clock_map.to_recovery_point().is_empty());
+        assert_eq!(clock_map.to_recovery_point().len(), 1);
+    }
+
+    #[test]
+    fn recovery_point_diff() {
+        let mut ahead = RecoveryPoint::default();
+        ahead.insert(1, 0, 10);
+        ahead.insert(1, 1, 5);
+        ahead.insert(2, 0, 7);
+        ahead.insert(3, 0, 3);
+
+        let mut behind = RecoveryPoint::default();
+        // Behind on clock (1, 0)
+        behind.insert(1, 0, 6);
+        // Ahead on clock (1, 1)
+        behind.insert(1, 1, 8);
+        // Equal on clock (3, 0)
+        behind.insert(3, 0, 3);
+        // Missing clock (2, 0) entirely
+
+        let diff = ahead.diff(&behind);
+        assert_eq!(diff.len(), 2);
+
+        let range = diff.get(1, 0).unwrap();
+        assert_eq!((range.from_tick, range.to_tick), (7, 10));
+        assert!(!range.is_missing);
+
+        let range = diff.get(2, 0).unwrap();
+        assert_eq!((range.from_tick, range.to_tick), (1, 7));
+        assert!(range.is_missing);
+
+        assert_eq!(diff.get(1, 1), None);
+        assert_eq!(diff.get(3, 0), None);
+
+        // Nothing to catch up on in the other direction, other than the clock we're ahead on
+        let diff = behind.diff(&ahead);
+        assert_eq!(diff.len(), 1);
+        let range = diff.get(1, 1).unwrap();
+        assert_eq!((range.from_tick, range.to_tick), (6, 8));
+
+        // Nothing to catch up on with itself
+        assert!(ahead.diff(&ahead).is_empty());
+    }
+
+    #[test]
+    fn recovery_point_should_replay() {
+        let mut recovery_point = RecoveryPoint::default();
+        recovery_point.insert(1, 0, 10);
+
+        // Below and equal to the recovery point, the peer already has the operation
+        assert!(!recovery_point.should_replay(&ClockTag::new(1, 0, 9)));
+        assert!(!recovery_point.should_replay(&ClockTag::new(1, 0, 10)));
+        // Above the recovery point, the peer is missing the operation
+        assert!(recovery_point.should_replay(&ClockTag::new(1, 0, 11)));
+        // Clocks missing from the recovery point are replayed entirely
+        assert!(recovery_point.should_replay(&ClockTag::new(1, 1, 1)));
+        assert!(recovery_point.should_replay(&ClockTag::new(2, 0, 1)));
+    }
+
+    #[test]
+    fn recovery_point_is_reconcilable_with() {
+        let mut source = RecoveryPoint::default();
+        source.insert(1, 0, 20);
+        source.insert(2, 0, 10);
+
+        let mut target = RecoveryPoint::default();
+        target.insert(1, 0, 15);
+        target.insert(2, 0, 10);
+
+        // Target needs ticks 16..=20 of clock (1, 0), which the WAL still covers
+        let wal = HashMap::from([((1, 0), 12), ((2, 0), 8)]);
+        assert!(target.is_reconcilable_with(&source, &wal));
+        let wal = HashMap::from([((1, 0), 16)]);
+        assert!(target.is_reconcilable_with(&source, &wal));
+
+        // WAL was truncated beyond the first tick the target needs
+        let wal = HashMap::from([((1, 0), 17), ((2, 0), 1)]);
+        assert!(!target.is_reconcilable_with(&source, &wal));
+
+        // Clock the target doesn't know about must be replayed from tick 1
+        target.clocks.remove(&Key::new(2, 0));
+        let wal = HashMap::from([((1, 0), 1), ((2, 0), 1)]);
+        assert!(target.is_reconcilable_with(&source, &wal));
+        let wal = HashMap::from([((1, 0), 1), ((2, 0), 2)]);
+        assert!(!target.is_reconcilable_with(&source, &wal));
+        let wal = HashMap::from([((1, 0), 1)]);
+        assert!(!target.is_reconcilable_with(&source, &wal));
+
+        // Nothing to catch up on
+        assert!(source.is_reconcilable_with(&source, &HashMap::new()));
+    }
+
+    #[test]
+    fn recovery_point_diff_to_grpc() {
+        let mut ahead = RecoveryPoint::default();
+        ahead.insert(1, 0, 10);
+        ahead.insert(2, 0, 7);
+
+        ahead.insert(3, 0, 4);
+
+        let mut behind = RecoveryPoint::default();
+        behind.insert(1, 0, 6);
+        behind.insert(3, 0, 4);
+
+        let diff = ahead.diff(&behind);
+        let grpc = api::grpc::qdrant::RecoveryPoint::from(&diff);
+
+        let mut ticks = grpc
+            .clocks
+            .iter()
+            .map(|tag| (tag.peer_id, tag.clock_id, tag.clock_tick))
+            .collect::<Vec<_>>();
+        ticks.sort_unstable();
+
+        // Clocks the lagging peer is not behind on are included at their current tick
+        assert_eq!(ticks, vec![(1, 0, 6), (2, 0, 0), (3, 0, 4)]);
+    }
+
     #[test]
     fn clock_map_accept_last_operation_multiple_times() {
         let mut helper = Helper::empty();
@@ -602,6 +1667,35 @@ mod test {
         }
     }
 
+    #[test]
+    fn clock_map_last_advance() {
+        let mut helper = Helper::empty();
+        assert_eq!(helper.clock_map.last_advance(), None);
+
+        // Accepted advance updates the time
+        helper.advance(tag(10)).assert(true, 10);
+        let first_advance = helper.clock_map.last_advance();
+        assert!(first_advance.is_some());
+        assert_eq!(
+            helper.clock_map.to_recovery_point().last_update(),
+            first_advance
+        );
+
+        // Rejected advance does not update the time
+        helper.advance(tag(5)).assert(false, 10);
+        helper.advance(tag(0)).assert(false, 10);
+        assert_eq!(helper.clock_map.last_advance(), first_advance);
+
+        // Newer accepted advance updates the time again
+        helper.advance(tag(11)).assert(true, 11);
+        let second_advance = helper.clock_map.last_advance();
+        assert!(second_advance >= first_advance);
+        assert_eq!(
+            helper.clock_map.to_recovery_point().last_update(),
+            second_advance
+        );
+    }
+
     proptest! {
         #[test]
         fn clock_map_workflow(execution in proptest::collection::vec(clock_tag(), 0..4096)) {
@@ -19,6 +19,7 @@ use common::cpu::CpuBudget;
 use common::panic;
 use common::types::TelemetryDetail;
 use indicatif::{ProgressBar, ProgressStyle};
+use io::file_operations;
 use itertools::Itertools;
 use parking_lot::{Mutex as ParkingMutex, RwLock};
 use segment::data_types::vectors::VectorElementType;
@@ -65,6 +66,9 @@ use crate::wal_delta::{LockedWal, RecoverableWal};
 /// If rendering WAL load progression in basic text form, report progression every 60 seconds.
 const WAL_LOAD_REPORT_EVERY: Duration = Duration::from_secs(60);
 
+/// File in a shard snapshot holding the recovery point at the time the snapshot was created
+pub const SNAPSHOT_RECOVERY_POINT_FILE: &str = "recovery_point.json";
+
 /// LocalShard
 ///
 /// LocalShard is an entity that can be moved between peers and contains some part of one collections data.
@@ -681,6 +685,13 @@ impl LocalShard {
                 std::fs::remove_file(&entry_path)?;
             }
         }
+
+        // The embedded recovery point is not part of the shard, read it before restoring
+        let recovery_point_path = snapshot_path.join(SNAPSHOT_RECOVERY_POINT_FILE);
+        if recovery_point_path.exists() {
+            std::fs::remove_file(&recovery_point_path)?;
+        }
+
         Ok(())
     }
 
@@ -693,6 +704,10 @@ impl LocalShard {
     ) -> CollectionResult<()> {
         let snapshot_shard_path = target_path;
 
+        // Capture recovery point before snapshotting any data, so that it never claims operations
+        // the snapshot doesn't contain
+        let recovery_point = self.recovery_point().await;
+
         // snapshot all shard's segment
         let snapshot_segments_shard_path = snapshot_shard_path.join("segments");
         create_dir_all(&snapshot_segments_shard_path).await?;
@@ -741,6 +756,137 @@ impl LocalShard {
         let target_shard_config_path = snapshot_shard_path.join(SHARD_CONFIG_FILE);
         copy(&shard_config_path, &target_shard_config_path).await?;
 
+        file_operations::atomic_save_json(
+            &snapshot_shard_path.join(SNAPSHOT_RECOVERY_POINT_FILE),
+            &recovery_point,
+        )?;
+
+        Ok(())
+    }
+
+    /// Read the recovery point embedded in the shard snapshot at `snapshot_path`
+    ///
+    /// This is where replication has to resume after restoring the snapshot. It must be read
+    /// before [`LocalShard::restore_snapshot`], which removes it.
+    pub fn snapshot_recovery_point(snapshot_path: &Path) -> CollectionResult<RecoveryPoint> {
+        let path = snapshot_path.join(SNAPSHOT_RECOVERY_POINT_FILE);
+        if !path.exists() {
+            return Err(CollectionError::service_error(format!(
+                "Shard snapshot {} has no recovery point",
+                snapshot_path.display(),
+            )));
+        }
+        Ok(file_operations::read_json(&path)?)
+    }
+
+    /// Create incremental snapshot for local shard into `target_path`
+    ///
+    /// Only includes segments changed after the given recovery point, and the WAL tail from the
+    /// first operation after it. It can be restored on top of a full snapshot taken at `since` or
+    /// later, see [`LocalShard::apply_incremental_snapshot`]. Like a full snapshot, it embeds the
+    /// current recovery point, see [`LocalShard::snapshot_recovery_point`].
+    pub async fn create_incremental_snapshot(
+        &self,
+        since: &RecoveryPoint,
+        temp_path: &Path,
+        target_path: &Path,
+    ) -> CollectionResult<()> {
+        // Capture recovery point before snapshotting any data, see `create_snapshot`
+        let recovery_point = self.recovery_point().await;
+
+        let from_op_num = match self.wal.resolve_wal_delta(since.clone()).await {
+            Ok(Some(op_num)) => op_num,
+            // Nothing happened since the recovery point
+            Ok(None) => self.wal.wal.lock().last_index() + 1,
+            Err(err) => {
+                return Err(CollectionError::service_error(format!(
+                    "Failed to resolve WAL delta for incremental snapshot: {err}"
+                )));
+            }
+        };
+
+        let snapshot_segments_shard_path = target_path.join("segments");
+        create_dir_all(&snapshot_segments_shard_path).await?;
+
+        let segments = self.segments.clone();
+        let wal = self.wal.wal.clone();
+        let snapshot_shard_path_owned = target_path.to_owned();
+        let segments_path = Self::segments_path(&self.path);
+        let collection_params = self.collection_config.read().await.params.clone();
+        let temp_path = temp_path.to_owned();
+
+        tokio::task::spawn_blocking(move || {
+            // Operations applied to segments while snapshotting are still in the WAL tail
+            SegmentHolder::snapshot_segments_since(
+                segments,
+                &segments_path,
+                Some(&collection_params),
+                &temp_path,
+                &snapshot_segments_shard_path,
+                from_op_num,
+            )?;
+
+            Self::snapshot_wal_tail(wal, &snapshot_shard_path_owned, from_op_num)
+        })
+        .await??;
+
+        LocalShardClocks::copy_data(&self.path, target_path).await?;
+
+        // copy shard's config
+        let shard_config_path = ShardConfig::get_config_path(&self.path);
+        let target_shard_config_path = target_path.join(SHARD_CONFIG_FILE);
+        copy(&shard_config_path, &target_shard_config_path).await?;
+
+        file_operations::atomic_save_json(
+            &target_path.join(SNAPSHOT_RECOVERY_POINT_FILE),
+            &recovery_point,
+        )?;
+
+        Ok(())
+    }
+
+    /// Apply incremental snapshot in `incremental_path` on top of full snapshot in `snapshot_path`
+    ///
+    /// Both must not be restored yet. Changed segments replace the ones in the full snapshot, and
+    /// the WAL tail replaces its WAL. Points duplicated in outdated segments are deduplicated when
+    /// the shard is loaded. The embedded recovery point is replaced by the newer one of the
+    /// incremental snapshot.
+    pub async fn apply_incremental_snapshot(
+        snapshot_path: &Path,
+        incremental_path: &Path,
+    ) -> CollectionResult<()> {
+        let segments_path = Self::segments_path(snapshot_path);
+        let mut segments = tokio::fs::read_dir(Self::segments_path(incremental_path)).await?;
+        while let Some(entry) = segments.next_entry().await? {
+            copy(entry.path(), segments_path.join(entry.file_name())).await?;
+        }
+
+        let wal_path = Self::wal_path(snapshot_path);
+        if wal_path.exists() {
+            remove_dir_all(&wal_path).await?;
+        }
+        let incremental_wal_path = Self::wal_path(incremental_path);
+        tokio::task::spawn_blocking(move || {
+            let options = fs_extra::dir::CopyOptions::new().copy_inside(true);
+            fs_extra::dir::copy(&incremental_wal_path, &wal_path, &options).map_err(|err| {
+                CollectionError::service_error(format!(
+                    "Error while copy WAL {incremental_wal_path:?} {err}"
+                ))
+            })
+        })
+        .await??;
+
+        LocalShardClocks::copy_data(incremental_path, snapshot_path).await?;
+
+        let shard_config_path = ShardConfig::get_config_path(incremental_path);
+        copy(&shard_config_path, snapshot_path.join(SHARD_CONFIG_FILE)).await?;
+
+        copy(
+            incremental_path.join(SNAPSHOT_RECOVERY_POINT_FILE),
+            snapshot_path.join(SNAPSHOT_RECOVERY_POINT_FILE),
+        )
+        .await?;
+
         Ok(())
     }
 
@@ -792,6 +938,48 @@ impl LocalShard {
         Ok(())
     }
 
+    /// snapshot WAL tail
+    ///
+    /// writes all WAL records starting at `from_op_num` into a new WAL in
+    /// `snapshot_shard_path/wal`, keeping their operation numbers
+    pub fn snapshot_wal_tail(
+        wal: LockedWal,
+        snapshot_shard_path: &Path,
+        from_op_num: u64,
+    ) -> CollectionResult<()> {
+        let wal_guard = wal.lock();
+
+        let target_path = Self::wal_path(snapshot_shard_path);
+        std::fs::create_dir_all(&target_path).map_err(|err| {
+            CollectionError::service_error(format!(
+                "Can not crate directory {}: {}",
+                target_path.display(),
+                err
+            ))
+        })?;
+
+        let options = WalOptions {
+            segment_capacity: wal_guard.segment_capacity(),
+            segment_queue_len: 0,
+        };
+
+        Wal::generate_empty_wal_starting_at_index(target_path.clone(), &options, from_op_num)
+            .map_err(|err| {
+                CollectionError::service_error(format!("Error while create empty WAL: {err}"))
+            })?;
+
+        let mut target_wal: SerdeWal<OperationWithClockTag> =
+            SerdeWal::new(target_path.to_str().unwrap(), options)
+                .map_err(|err| CollectionError::service_error(format!("Wal error: {err}")))?;
+
+        for (_, operation) in wal_guard.read(from_op_num) {
+            target_wal.write(&operation)?;
+        }
+        target_wal.flush()?;
+
+        Ok(())
+    }
+
     pub fn estimate_cardinality<'a>(
         &'a self,
         filter: Option<&'a Filter>,
@@ -967,6 +1155,27 @@ impl LocalShard {
         &self.update_tracker
     }
 
+    /// Wait for all submitted updates to be applied, then flush WAL and all segments to disk
+    ///
+    /// Acts as a barrier before creating a snapshot, so that it contains all accepted updates,
+    /// including ones accepted with `wait=false` that are still queued.
+    pub async fn flush_and_wait(&self) -> CollectionResult<()> {
+        // The Plunger notifies us once all updates submitted so far have been processed
+        let (tx, rx) = oneshot::channel();
+        self.update_sender
+            .load()
+            .send(UpdateSignal::Plunger(tx))
+            .await?;
+        rx.await?;
+
+        self.update_tracker.wait_for_idle().await;
+
+        self.wal.wal.lock().flush()?;
+        self.segments.read().flush_all(true)?;
+
+        Ok(())
+    }
+
     /// Get the recovery point for the current shard
     ///
     /// This is sourced from the last seen clocks from other nodes that we know about.
@@ -974,6 +1183,23 @@ impl LocalShard {
         self.wal.recovery_point().await
     }
 
+    /// Get the WAL status for the current shard
+    ///
+    /// Reports the range of operations available in the WAL together with the recovery point
+    /// they back.
+    pub async fn wal_status(&self) -> WalStatus {
+        let (first_index, last_index) = {
+            let wal = self.wal.wal.lock();
+            (wal.first_index(), wal.last_index())
+        };
+
+        WalStatus {
+            first_index,
+            last_index,
+            recovery_point: self.recovery_point().await,
+        }
+    }
+
     /// Update the cutoff point on the current shard
     ///
     /// This also updates the highest seen clocks.
@@ -997,6 +1223,16 @@ impl Drop for LocalShard {
     }
 }
 
+/// Range of operations in the WAL of a shard, and the recovery point backed by it
+#[derive(Clone, Debug)]
+pub struct WalStatus {
+    /// First operation number still available in the WAL
+    pub first_index: u64,
+    /// Last operation number available in the WAL
+    pub last_index: u64,
+    pub recovery_point: RecoveryPoint,
+}
+
 /// Convenience struct for combining clock maps belonging to a shard
 ///
 /// Holds a clock map for tracking the highest clocks and the cutoff clocks.
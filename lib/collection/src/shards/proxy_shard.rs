@@ -127,6 +127,10 @@ impl ProxyShard {
     pub fn update_tracker(&self) -> &UpdateTracker {
         self.wrapped_shard.update_tracker()
     }
+
+    pub async fn flush_and_wait(&self) -> CollectionResult<()> {
+        self.wrapped_shard.flush_and_wait().await
+    }
 }
 
 #[async_trait]
@@ -172,6 +172,16 @@ impl QueueProxyShard {
             .update_tracker()
     }
 
+    /// Get the wrapped local shard, or `None` if the queue proxy has been finalized
+    pub fn wrapped_shard(&self) -> Option<&LocalShard> {
+        self.inner.as_ref().map(|inner| &inner.wrapped_shard)
+    }
+
+    /// Get the wrapped local shard mutably, or `None` if the queue proxy has been finalized
+    pub fn wrapped_shard_mut(&mut self) -> Option<&mut LocalShard> {
+        self.inner.as_mut().map(|inner| &mut inner.wrapped_shard)
+    }
+
     /// Check if the queue proxy shard is already finalized
     #[cfg(debug_assertions)]
     fn is_finalized(&self) -> bool {
@@ -145,10 +145,8 @@ impl ShardReplicaSet {
         };
 
         // Get `max_ack_version` without "taking" local shard (to maintain cancel safety)
-        let local_shard = match local.deref() {
-            Some(Shard::Local(local)) => local,
-            Some(Shard::ForwardProxy(proxy)) => &proxy.wrapped_shard,
-            _ => unreachable!(),
+        let Some(local_shard) = local.deref().as_ref().and_then(Shard::local) else {
+            unreachable!()
         };
 
         let wal_keep_from = local_shard
@@ -1,15 +1,18 @@
 use core::marker::{Send, Sync};
 use std::future::{self, Future};
 use std::path::Path;
+use std::time::Duration;
 
 use common::types::TelemetryDetail;
+use uuid::Uuid;
 
 use super::local_shard::clock_map::RecoveryPoint;
 use super::update_tracker::UpdateTracker;
+use crate::common::cleanup_guard::CleanupGuard;
 use crate::operations::types::{CollectionError, CollectionResult};
 use crate::shards::dummy_shard::DummyShard;
 use crate::shards::forward_proxy_shard::ForwardProxyShard;
-use crate::shards::local_shard::LocalShard;
+use crate::shards::local_shard::{LocalShard, WalStatus};
 use crate::shards::proxy_shard::ProxyShard;
 use crate::shards::queue_proxy_shard::QueueProxyShard;
 use crate::shards::shard_trait::ShardOperation;
@@ -31,6 +34,37 @@ pub type ShardReplicasPlacement = Vec<PeerId>;
 /// ] - 3 shards, each has 2 replicas
 pub type ShardsPlacement = Vec<ShardReplicasPlacement>;
 
+/// Default placement of `replication_factor` replicas for each of `shard_count` shards
+///
+/// Replicas are assigned to `peers` round-robin, so that the number of replicas per peer differs
+/// by at most one, and replicas of the same shard are always placed on different peers.
+pub fn balanced_placement(
+    shard_count: usize,
+    replication_factor: usize,
+    peers: &[PeerId],
+) -> CollectionResult<ShardsPlacement> {
+    if replication_factor > peers.len() {
+        return Err(CollectionError::bad_input(format!(
+            "Can't place {replication_factor} replicas of a shard on {} peers",
+            peers.len(),
+        )));
+    }
+
+    // Replicas of a shard take consecutive positions, which are distinct peers as long as there
+    // are no more replicas than peers
+    let placement = (0..shard_count)
+        .map(|shard_idx| {
+            (0..replication_factor)
+                .map(|replica_idx| {
+                    peers[(shard_idx * replication_factor + replica_idx) % peers.len()]
+                })
+                .collect()
+        })
+        .collect();
+
+    Ok(placement)
+}
+
 /// Shard
 ///
 /// Contains a part of the collection's points
@@ -63,6 +97,32 @@ impl Shard {
         }
     }
 
+    /// Get the underlying local shard, if any.
+    ///
+    /// Returns the wrapped local shard for forward and queue proxies. Returns `None` for
+    /// proxy shards, because updates must go through the proxy to be tracked, and for dummy
+    /// shards.
+    pub fn local(&self) -> Option<&LocalShard> {
+        match self {
+            Shard::Local(local_shard) => Some(local_shard),
+            Shard::ForwardProxy(proxy_shard) => Some(&proxy_shard.wrapped_shard),
+            Shard::QueueProxy(proxy_shard) => proxy_shard.wrapped_shard(),
+            Shard::Proxy(_) | Shard::Dummy(_) => None,
+        }
+    }
+
+    /// Get the underlying local shard mutably, if any.
+    ///
+    /// See [`Shard::local`].
+    pub fn local_mut(&mut self) -> Option<&mut LocalShard> {
+        match self {
+            Shard::Local(local_shard) => Some(local_shard),
+            Shard::ForwardProxy(proxy_shard) => Some(&mut proxy_shard.wrapped_shard),
+            Shard::QueueProxy(proxy_shard) => proxy_shard.wrapped_shard_mut(),
+            Shard::Proxy(_) | Shard::Dummy(_) => None,
+        }
+    }
+
     pub fn get_telemetry_data(&self, detail: TelemetryDetail) -> LocalShardTelemetry {
         let mut telemetry = match self {
             Shard::Local(local_shard) => local_shard.get_telemetry_data(detail),
@@ -81,6 +141,17 @@ impl Shard {
         target_path: &Path,
         save_wal: bool,
     ) -> CollectionResult<()> {
+        // Dedicated working directory for intermediary files of this snapshot. It's not needed
+        // once the snapshot is done, so it's removed in any case, even if we're cancelled.
+        let working_dir =
+            CleanupGuard::new(temp_path.join(format!("shard-snapshot-{}", Uuid::new_v4())));
+        tokio::fs::create_dir_all(working_dir.path()).await?;
+        let temp_path = working_dir.path();
+
+        // Remove partially written snapshot files, unless it completes. The target directory
+        // belongs to the caller, so it's kept along with anything that was in it before.
+        let target = CleanupGuard::new_entries(target_path)?;
+
         match self {
             Shard::Local(local_shard) => {
                 local_shard
@@ -107,7 +178,80 @@ impl Shard {
                     .create_snapshot(temp_path, target_path, save_wal)
                     .await
             }
+        }?;
+
+        target.commit();
+        Ok(())
+    }
+
+    /// Read the recovery point embedded in a shard snapshot, see
+    /// [`LocalShard::snapshot_recovery_point`]
+    ///
+    /// Only snapshots of local-backed shards contain one.
+    pub fn snapshot_recovery_point(snapshot_path: &Path) -> CollectionResult<RecoveryPoint> {
+        LocalShard::snapshot_recovery_point(snapshot_path)
+    }
+
+    /// Create snapshot of only the changes since `since`, see
+    /// [`LocalShard::create_incremental_snapshot`]
+    pub async fn create_incremental_snapshot(
+        &self,
+        since: &RecoveryPoint,
+        temp_path: &Path,
+        target_path: &Path,
+    ) -> CollectionResult<()> {
+        let Self::Local(local_shard) = self else {
+            return Err(CollectionError::service_error(format!(
+                "Incremental snapshot not supported on {}",
+                self.variant_name(),
+            )));
+        };
+
+        let working_dir =
+            CleanupGuard::new(temp_path.join(format!("shard-snapshot-{}", Uuid::new_v4())));
+        tokio::fs::create_dir_all(working_dir.path()).await?;
+
+        // Keep the caller's target directory, see `create_snapshot`
+        let target = CleanupGuard::new_entries(target_path)?;
+
+        local_shard
+            .create_incremental_snapshot(since, working_dir.path(), target_path)
+            .await?;
+
+        target.commit();
+        Ok(())
+    }
+
+    /// Estimated size of a snapshot of this shard in bytes, based on the size of its files
+    pub fn estimate_snapshot_size(&self) -> CollectionResult<u64> {
+        let Some(local_shard) = self.local() else {
+            return Err(CollectionError::service_error(format!(
+                "Snapshot size estimation not supported on {}",
+                self.variant_name(),
+            )));
+        };
+
+        fs_extra::dir::get_size(local_shard.shard_path()).map_err(|err| {
+            CollectionError::service_error(format!("Failed to get shard directory size: {err}"))
+        })
+    }
+
+    /// Estimated duration of transferring this shard to another peer, given the available
+    /// bandwidth in bytes per second
+    pub fn estimate_transfer_duration(
+        &self,
+        bandwidth_bytes_per_sec: u64,
+    ) -> CollectionResult<Duration> {
+        if bandwidth_bytes_per_sec == 0 {
+            return Err(CollectionError::bad_input(
+                "Bandwidth must be greater than zero",
+            ));
         }
+
+        let size = self.estimate_snapshot_size()?;
+        Ok(Duration::from_secs_f64(
+            size as f64 / bandwidth_bytes_per_sec as f64,
+        ))
     }
 
     pub async fn on_optimizer_config_update(&self) -> CollectionResult<()> {
@@ -120,6 +264,23 @@ impl Shard {
         }
     }
 
+    /// Wait for updates in progress to finish, and flush them to disk, see
+    /// [`LocalShard::flush_and_wait`]
+    ///
+    /// Proxies flush their wrapped local shard. A dummy shard has nothing to flush.
+    pub async fn flush_and_wait(&self) -> CollectionResult<()> {
+        match self {
+            Self::Local(local_shard) => local_shard.flush_and_wait().await,
+            Self::Proxy(proxy_shard) => proxy_shard.flush_and_wait().await,
+            Self::ForwardProxy(proxy_shard) => proxy_shard.wrapped_shard.flush_and_wait().await,
+            Self::QueueProxy(proxy_shard) => match proxy_shard.wrapped_shard() {
+                Some(local_shard) => local_shard.flush_and_wait().await,
+                None => Ok(()),
+            },
+            Self::Dummy(_) => Ok(()),
+        }
+    }
+
     pub fn is_update_in_progress(&self) -> bool {
         self.update_tracker()
             .map_or(false, UpdateTracker::is_update_in_progress)
@@ -148,6 +309,16 @@ impl Shard {
         Some(update_tracker)
     }
 
+    /// Whether [`Shard::shard_recovery_point`] is supported by this shard variant
+    ///
+    /// Lets callers check for a recovery point upfront, instead of handling the error.
+    pub fn supports_recovery_point(&self) -> bool {
+        match self {
+            Self::Local(_) | Self::ForwardProxy(_) => true,
+            Self::Proxy(_) | Self::QueueProxy(_) | Self::Dummy(_) => false,
+        }
+    }
+
     pub async fn shard_recovery_point(&self) -> CollectionResult<RecoveryPoint> {
         match self {
             Self::Local(local_shard) => Ok(local_shard.recovery_point().await),
@@ -162,6 +333,16 @@ impl Shard {
         }
     }
 
+    pub async fn wal_status(&self) -> CollectionResult<WalStatus> {
+        match self.local() {
+            Some(local_shard) => Ok(local_shard.wal_status().await),
+            None => Err(CollectionError::service_error(format!(
+                "WAL status not supported on {}",
+                self.variant_name(),
+            ))),
+        }
+    }
+
     pub async fn update_cutoff(&self, cutoff: &RecoveryPoint) -> CollectionResult<()> {
         match self {
             Self::Local(local_shard) => local_shard.update_cutoff(cutoff).await,
@@ -210,3 +391,57 @@ impl Shard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::*;
+
+    #[test]
+    fn test_balanced_placement() {
+        let peers = [10, 20, 30, 40, 50];
+
+        for shard_count in [1, 3, 5, 7, 12] {
+            for replication_factor in 1..=peers.len() {
+                let placement =
+                    balanced_placement(shard_count, replication_factor, &peers).unwrap();
+                assert_eq!(placement.len(), shard_count);
+
+                let mut replicas_per_peer: HashMap<PeerId, usize> = HashMap::new();
+                for replicas in &placement {
+                    assert_eq!(replicas.len(), replication_factor);
+                    let distinct: HashSet<_> = replicas.iter().collect();
+                    assert_eq!(
+                        distinct.len(),
+                        replicas.len(),
+                        "duplicate peer in {replicas:?}"
+                    );
+
+                    for &peer_id in replicas {
+                        *replicas_per_peer.entry(peer_id).or_default() += 1;
+                    }
+                }
+
+                // Even spread, peers without replicas count as empty
+                let min = peers
+                    .iter()
+                    .map(|peer_id| replicas_per_peer.get(peer_id).copied().unwrap_or(0))
+                    .min()
+                    .unwrap();
+                let max = replicas_per_peer.values().copied().max().unwrap();
+                assert!(max - min <= 1, "uneven placement {placement:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_balanced_placement_not_enough_peers() {
+        assert!(balanced_placement(3, 4, &[1, 2, 3]).is_err());
+        assert!(balanced_placement(3, 1, &[]).is_err());
+        assert_eq!(
+            balanced_placement(0, 2, &[1, 2]).unwrap(),
+            ShardsPlacement::new()
+        );
+    }
+}
@@ -8,15 +8,19 @@ use tokio::sync::watch;
 pub struct UpdateTracker {
     update_operations: Arc<AtomicUsize>,
     update_notifier: Arc<watch::Sender<()>>,
+    /// Notified when the last update in progress finishes
+    idle_notifier: Arc<watch::Sender<()>>,
 }
 
 impl Default for UpdateTracker {
     fn default() -> Self {
         let (update_notifier, _) = watch::channel(());
+        let (idle_notifier, _) = watch::channel(());
 
         Self {
             update_operations: Default::default(),
             update_notifier: Arc::new(update_notifier),
+            idle_notifier: Arc::new(idle_notifier),
         }
     }
 }
@@ -37,28 +41,49 @@ impl UpdateTracker {
         }
     }
 
+    /// Wait until no update is in progress anymore
+    ///
+    /// Returns immediately if there is no update in progress.
+    pub async fn wait_for_idle(&self) {
+        // Subscribe before checking, to not miss the last update finishing in between
+        let mut idle_subscriber = self.idle_notifier.subscribe();
+
+        while self.is_update_in_progress() {
+            if idle_subscriber.changed().await.is_err() {
+                // Sender is owned by `self`, so it can't be dropped while we wait
+                return;
+            }
+        }
+    }
+
     pub fn update(&self) -> UpdateGuard {
         if self.update_operations.fetch_add(1, Ordering::Relaxed) == 0 {
             self.update_notifier.send_replace(());
         }
 
-        UpdateGuard::new(self.update_operations.clone())
+        UpdateGuard::new(self.update_operations.clone(), self.idle_notifier.clone())
     }
 }
 
 #[derive(Debug)]
 pub struct UpdateGuard {
     update_operations: Arc<AtomicUsize>,
+    idle_notifier: Arc<watch::Sender<()>>,
 }
 
 impl UpdateGuard {
-    fn new(update_operations: Arc<AtomicUsize>) -> Self {
-        Self { update_operations }
+    fn new(update_operations: Arc<AtomicUsize>, idle_notifier: Arc<watch::Sender<()>>) -> Self {
+        Self {
+            update_operations,
+            idle_notifier,
+        }
     }
 }
 
 impl Drop for UpdateGuard {
     fn drop(&mut self) {
-        self.update_operations.fetch_sub(1, Ordering::Relaxed);
+        if self.update_operations.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.idle_notifier.send_replace(());
+        }
     }
 }
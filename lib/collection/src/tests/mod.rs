@@ -1,6 +1,7 @@
 pub mod fixtures;
 mod points_dedup;
 mod sha_256_test;
+mod shard_local_test;
 mod shard_query;
 mod snapshot_test;
 mod sparse_vectors_validation_tests;
@@ -0,0 +1,457 @@
+use std::sync::atomic::{AtomicBool, AtomicU64};
+use std::sync::Arc;
+
+use common::cpu::CpuBudget;
+use itertools::Itertools;
+use parking_lot::Mutex;
+use segment::data_types::vectors::VectorStruct;
+use segment::entry::entry_point::SegmentEntry;
+use segment::segment_constructor::load_segment;
+use tempfile::{Builder, TempDir};
+use tokio::runtime::Handle;
+use tokio::sync::RwLock;
+
+use crate::operations::point_ops::PointStruct;
+use crate::operations::{ClockTag, ClockToken, CollectionUpdateOperations, OperationWithClockTag};
+use crate::shards::channel_service::ChannelService;
+use crate::shards::dummy_shard::DummyShard;
+use crate::shards::forward_proxy_shard::ForwardProxyShard;
+use crate::shards::local_shard::clock_map::RecoveryPoint;
+use crate::shards::local_shard::{LocalShard, SNAPSHOT_RECOVERY_POINT_FILE};
+use crate::shards::proxy_shard::ProxyShard;
+use crate::shards::queue_proxy_shard::QueueProxyShard;
+use crate::shards::remote_shard::RemoteShard;
+use crate::shards::shard::{PeerId, Shard};
+use crate::shards::shard_trait::ShardOperation;
+use crate::shards::transfer::transfer_tasks_pool::TransferTaskProgress;
+use crate::tests::fixtures::*;
+
+async fn build_local_shard(collection_dir: &TempDir) -> LocalShard {
+    let config = create_collection_config();
+
+    LocalShard::build(
+        0,
+        "test".to_string(),
+        collection_dir.path(),
+        Arc::new(RwLock::new(config)),
+        Arc::new(Default::default()),
+        Handle::current(),
+        CpuBudget::default(),
+    )
+    .await
+    .unwrap()
+}
+
+fn remote_shard() -> RemoteShard {
+    RemoteShard::new(0, "test".to_string(), 2, ChannelService::default())
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_local_accessor() {
+    let dirs = (0..4)
+        .map(|_| Builder::new().prefix("test_collection").tempdir().unwrap())
+        .collect::<Vec<_>>();
+
+    // Local shard
+    let mut shard = Shard::Local(build_local_shard(&dirs[0]).await);
+    assert!(shard.local().is_some());
+    assert!(shard.local_mut().is_some());
+    drop(shard);
+
+    // Forward proxy shard wraps a local shard
+    let mut shard = Shard::ForwardProxy(ForwardProxyShard::new(
+        build_local_shard(&dirs[1]).await,
+        remote_shard(),
+    ));
+    assert!(shard.local().is_some());
+    assert!(shard.local_mut().is_some());
+    drop(shard);
+
+    // Queue proxy shard wraps a local shard
+    let mut shard = Shard::QueueProxy(QueueProxyShard::new(
+        build_local_shard(&dirs[2]).await,
+        remote_shard(),
+        Arc::new(AtomicU64::new(u64::MAX)),
+        Arc::new(Mutex::new(TransferTaskProgress::new())),
+    ));
+    assert!(shard.local().is_some());
+    assert!(shard.local_mut().is_some());
+    let Shard::QueueProxy(queue_proxy) = shard else {
+        unreachable!();
+    };
+    let _ = queue_proxy.forget_updates_and_finalize();
+
+    // Proxy shard must not expose its wrapped local shard
+    let mut shard = Shard::Proxy(ProxyShard::new(build_local_shard(&dirs[3]).await).await);
+    assert!(shard.local().is_none());
+    assert!(shard.local_mut().is_none());
+    drop(shard);
+
+    // Dummy shard has no local shard
+    let mut shard = Shard::Dummy(DummyShard::new("test"));
+    assert!(shard.local().is_none());
+    assert!(shard.local_mut().is_none());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_supports_recovery_point() {
+    let dirs = (0..4)
+        .map(|_| Builder::new().prefix("test_collection").tempdir().unwrap())
+        .collect::<Vec<_>>();
+
+    // Capability must agree with the result of getting the recovery point
+    async fn check(shard: &Shard, supported: bool) {
+        assert_eq!(shard.supports_recovery_point(), supported);
+        assert_eq!(shard.shard_recovery_point().await.is_ok(), supported);
+    }
+
+    let shard = Shard::Local(build_local_shard(&dirs[0]).await);
+    check(&shard, true).await;
+    drop(shard);
+
+    let shard = Shard::ForwardProxy(ForwardProxyShard::new(
+        build_local_shard(&dirs[1]).await,
+        remote_shard(),
+    ));
+    check(&shard, true).await;
+    drop(shard);
+
+    let shard = Shard::QueueProxy(QueueProxyShard::new(
+        build_local_shard(&dirs[2]).await,
+        remote_shard(),
+        Arc::new(AtomicU64::new(u64::MAX)),
+        Arc::new(Mutex::new(TransferTaskProgress::new())),
+    ));
+    check(&shard, false).await;
+    let Shard::QueueProxy(queue_proxy) = shard else {
+        unreachable!();
+    };
+    let _ = queue_proxy.forget_updates_and_finalize();
+
+    let shard = Shard::Proxy(ProxyShard::new(build_local_shard(&dirs[3]).await).await);
+    check(&shard, false).await;
+    drop(shard);
+
+    check(&Shard::Dummy(DummyShard::new("test")), false).await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_wal_status() {
+    let collection_dir = Builder::new().prefix("test_collection").tempdir().unwrap();
+
+    let shard = Shard::Local(build_local_shard(&collection_dir).await);
+
+    let local_shard = shard.local().unwrap();
+    local_shard
+        .update(upsert_operation().into(), true)
+        .await
+        .unwrap();
+    local_shard
+        .update(create_payload_index_operation().into(), true)
+        .await
+        .unwrap();
+    local_shard
+        .update(delete_point_operation(4).into(), true)
+        .await
+        .unwrap();
+
+    let wal_status = shard.wal_status().await.unwrap();
+    assert_eq!(wal_status.first_index, 0);
+    assert_eq!(wal_status.last_index, 2);
+
+    // Operations without clock tags don't advance the recovery point
+    assert!(wal_status.recovery_point.is_empty());
+
+    // Dummy shard has no WAL
+    let shard = Shard::Dummy(DummyShard::new("test"));
+    assert!(shard.wal_status().await.is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_estimate_transfer_duration() {
+    let collection_dir = Builder::new().prefix("test_collection").tempdir().unwrap();
+
+    let shard = Shard::Local(build_local_shard(&collection_dir).await);
+    shard
+        .local()
+        .unwrap()
+        .update(upsert_operation().into(), true)
+        .await
+        .unwrap();
+
+    let size = shard.estimate_snapshot_size().unwrap();
+    assert!(size > 0);
+
+    // Transfer of the whole shard at `size` bytes per second takes about a second
+    let duration = shard.estimate_transfer_duration(size).unwrap();
+    assert!((duration.as_secs_f64() - 1.0).abs() < 0.1, "{duration:?}");
+
+    let duration = shard.estimate_transfer_duration(size * 4).unwrap();
+    assert!(
+        (duration.as_secs_f64() - 0.25).abs() < 0.025,
+        "{duration:?}"
+    );
+
+    assert!(shard.estimate_transfer_duration(0).is_err());
+
+    // A proxy estimates the size of its wrapped local shard
+    let Shard::Local(local_shard) = shard else {
+        unreachable!();
+    };
+    let shard = Shard::ForwardProxy(ForwardProxyShard::new(local_shard, remote_shard()));
+    assert!(shard.estimate_snapshot_size().unwrap() > 0);
+    drop(shard);
+
+    // Dummy shard has no data to transfer
+    let shard = Shard::Dummy(DummyShard::new("test"));
+    assert!(shard.estimate_snapshot_size().is_err());
+    assert!(shard.estimate_transfer_duration(size).is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_flush_and_wait() {
+    let collection_dir = Builder::new().prefix("test_collection").tempdir().unwrap();
+    let copy_dir = Builder::new()
+        .prefix("test_collection_copy")
+        .tempdir()
+        .unwrap();
+
+    let shard = Shard::Local(build_local_shard(&collection_dir).await);
+
+    // Don't wait for the update to be applied
+    shard
+        .local()
+        .unwrap()
+        .update(upsert_operation().into(), false)
+        .await
+        .unwrap();
+    shard.flush_and_wait().await.unwrap();
+    assert!(!shard.is_update_in_progress());
+
+    // Flushed update is in the segment files of a copy of the shard, without replaying the WAL
+    let options = fs_extra::dir::CopyOptions::new().content_only(true);
+    fs_extra::dir::copy(collection_dir.path(), copy_dir.path(), &options).unwrap();
+    let points_count: usize = std::fs::read_dir(LocalShard::segments_path(copy_dir.path()))
+        .unwrap()
+        .filter_map(|entry| load_segment(&entry.unwrap().path(), &AtomicBool::new(false)).unwrap())
+        .map(|segment| segment.available_point_count())
+        .sum();
+    assert_eq!(points_count, 5);
+
+    // Dummy shard has nothing to flush
+    let shard = Shard::Dummy(DummyShard::new("test"));
+    shard.flush_and_wait().await.unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_snapshot_cleans_up_temp_files() {
+    let collection_dir = Builder::new().prefix("test_collection").tempdir().unwrap();
+    let temp_dir = Builder::new().prefix("snapshot_temp").tempdir().unwrap();
+    let target_dir = Builder::new().prefix("snapshot_target").tempdir().unwrap();
+
+    let is_empty_dir = |path: &std::path::Path| std::fs::read_dir(path).unwrap().next().is_none();
+
+    // Successful snapshot keeps the target, but no intermediary files
+    let shard = Shard::Local(build_local_shard(&collection_dir).await);
+    shard
+        .local()
+        .unwrap()
+        .update(upsert_operation().into(), true)
+        .await
+        .unwrap();
+    shard
+        .create_snapshot(temp_dir.path(), target_dir.path(), true)
+        .await
+        .unwrap();
+    assert!(is_empty_dir(temp_dir.path()));
+    assert!(target_dir.path().join("segments").is_dir());
+    drop(shard);
+
+    // Failed snapshot leaves no intermediary files, but keeps the caller's target directory
+    let failed_target_path = target_dir.path().join("failed");
+    std::fs::create_dir_all(&failed_target_path).unwrap();
+    std::fs::write(failed_target_path.join("existing"), b"data").unwrap();
+    let shard = Shard::Dummy(DummyShard::new("test"));
+    assert!(shard
+        .create_snapshot(temp_dir.path(), &failed_target_path, true)
+        .await
+        .is_err());
+    assert!(is_empty_dir(temp_dir.path()));
+    assert!(failed_target_path.is_dir());
+    let target_entries: Vec<_> = std::fs::read_dir(&failed_target_path)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(target_entries, vec![std::ffi::OsString::from("existing")]);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_snapshot_recovery_point() {
+    let collection_dir = Builder::new().prefix("test_collection").tempdir().unwrap();
+    let temp_dir = Builder::new().prefix("snapshot_temp").tempdir().unwrap();
+    let target_dir = Builder::new().prefix("snapshot_target").tempdir().unwrap();
+
+    let shard = Shard::Local(build_local_shard(&collection_dir).await);
+    let operation = OperationWithClockTag::new(upsert_operation(), Some(ClockTag::new(1, 0, 0)));
+    shard
+        .local()
+        .unwrap()
+        .update(operation, true)
+        .await
+        .unwrap();
+
+    let recovery_point = shard.shard_recovery_point().await.unwrap();
+    shard
+        .create_snapshot(temp_dir.path(), target_dir.path(), true)
+        .await
+        .unwrap();
+    let snapshot_recovery_point = Shard::snapshot_recovery_point(target_dir.path()).unwrap();
+
+    assert_eq!(recovery_point.len(), 1);
+    assert_eq!(
+        sorted_clock_tags(&snapshot_recovery_point),
+        sorted_clock_tags(&recovery_point),
+    );
+
+    // Snapshot without embedded recovery point
+    assert!(Shard::snapshot_recovery_point(temp_dir.path()).is_err());
+
+    // Restored shard doesn't keep the embedded recovery point
+    LocalShard::restore_snapshot(target_dir.path()).unwrap();
+    assert!(!target_dir
+        .path()
+        .join(SNAPSHOT_RECOVERY_POINT_FILE)
+        .exists());
+}
+
+fn sorted_clock_tags(recovery_point: &RecoveryPoint) -> Vec<(PeerId, u32, u64, ClockToken)> {
+    recovery_point
+        .iter_as_clock_tags()
+        .map(|tag| (tag.peer_id, tag.clock_id, tag.clock_tick, tag.token))
+        .sorted()
+        .collect()
+}
+
+fn dir_size(path: &std::path::Path) -> u64 {
+    fs_extra::dir::get_size(path).unwrap()
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn test_shard_incremental_snapshot() {
+    let collection_dir = Builder::new().prefix("test_collection").tempdir().unwrap();
+    let temp_dir = Builder::new().prefix("snapshot_temp").tempdir().unwrap();
+    let full_dir = Builder::new().prefix("snapshot_full").tempdir().unwrap();
+    let incremental_dir = Builder::new().prefix("snapshot_incr").tempdir().unwrap();
+    let restore_dir = Builder::new().prefix("snapshot_restore").tempdir().unwrap();
+
+    // Two segments, so that a single upsert changes only one of them
+    let mut config = create_collection_config();
+    config.optimizer_config.default_segment_number = 2;
+    let config = Arc::new(RwLock::new(config));
+
+    let shard = Shard::Local(
+        LocalShard::build(
+            0,
+            "test".to_string(),
+            collection_dir.path(),
+            config.clone(),
+            Arc::new(Default::default()),
+            Handle::current(),
+            CpuBudget::default(),
+        )
+        .await
+        .unwrap(),
+    );
+    let local_shard = shard.local().unwrap();
+
+    let operation = OperationWithClockTag::new(upsert_operation(), Some(ClockTag::new(1, 0, 0)));
+    local_shard.update(operation, true).await.unwrap();
+
+    shard
+        .create_snapshot(temp_dir.path(), full_dir.path(), true)
+        .await
+        .unwrap();
+    let since = shard.shard_recovery_point().await.unwrap();
+
+    let operation = OperationWithClockTag::new(
+        CollectionUpdateOperations::PointOperation(
+            vec![PointStruct {
+                id: 6.into(),
+                vector: VectorStruct::from(vec![6.0, 2.0, 3.0, 4.0]).into(),
+                payload: None,
+            }]
+            .into(),
+        ),
+        Some(ClockTag::new(1, 0, 1)),
+    );
+    local_shard.update(operation, true).await.unwrap();
+
+    let recovery_point = shard.shard_recovery_point().await.unwrap();
+    shard
+        .create_incremental_snapshot(&since, temp_dir.path(), incremental_dir.path())
+        .await
+        .unwrap();
+
+    // Only the changed segment is included
+    let segments_path = LocalShard::segments_path;
+    assert_eq!(
+        std::fs::read_dir(segments_path(full_dir.path()))
+            .unwrap()
+            .count(),
+        2,
+    );
+    assert_eq!(
+        std::fs::read_dir(segments_path(incremental_dir.path()))
+            .unwrap()
+            .count(),
+        1,
+    );
+    assert!(
+        dir_size(&segments_path(incremental_dir.path()))
+            < dir_size(&segments_path(full_dir.path())),
+    );
+
+    // Restore full snapshot with the incremental one on top
+    let options = fs_extra::dir::CopyOptions::new().content_only(true);
+    fs_extra::dir::copy(full_dir.path(), restore_dir.path(), &options).unwrap();
+    LocalShard::apply_incremental_snapshot(restore_dir.path(), incremental_dir.path())
+        .await
+        .unwrap();
+
+    // Replication resumes from the recovery point of the incremental snapshot
+    let snapshot_recovery_point = Shard::snapshot_recovery_point(restore_dir.path()).unwrap();
+    assert_eq!(
+        sorted_clock_tags(&snapshot_recovery_point),
+        sorted_clock_tags(&recovery_point),
+    );
+    assert_ne!(
+        sorted_clock_tags(&snapshot_recovery_point),
+        sorted_clock_tags(&since),
+    );
+
+    LocalShard::restore_snapshot(restore_dir.path()).unwrap();
+
+    let restored_shard = LocalShard::load(
+        0,
+        "test".to_string(),
+        restore_dir.path(),
+        config,
+        Arc::new(Default::default()),
+        Handle::current(),
+        CpuBudget::default(),
+    )
+    .await
+    .unwrap();
+
+    let info = local_shard.info().await.unwrap();
+    let restored_info = restored_shard.info().await.unwrap();
+    assert_eq!(info.points_count, 6);
+    assert_eq!(restored_info.points_count, info.points_count);
+
+    // Non-local shards don't support incremental snapshots
+    let shard = Shard::Dummy(DummyShard::new("test"));
+    assert!(shard
+        .create_incremental_snapshot(&since, temp_dir.path(), incremental_dir.path())
+        .await
+        .is_err());
+}
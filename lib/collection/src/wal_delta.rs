@@ -1593,6 +1593,58 @@ mod tests {
         assert_eq!(resolve_result.unwrap_err(), WalDeltaError::NotFound);
     }
 
+    /// A recovery point diff sent over gRPC resolves to exactly the lagging ticks.
+    #[test]
+    fn test_resolve_wal_delta_from_recovery_point_diff() {
+        // Clock (1, 0) has ticks 1..=10, clock (2, 0) has ticks 1..=5 on every other record
+        let mut tags = Vec::new();
+        for tick in 1..=10 {
+            tags.push(ClockTag::new(1, 0, tick));
+            if tick % 2 == 0 {
+                tags.push(ClockTag::new(2, 0, tick / 2));
+            }
+        }
+        let operations = || {
+            tags.iter()
+                .enumerate()
+                .map(|(op_num, &tag)| (op_num as u64, Some(tag)))
+        };
+
+        let mut newest_clocks = RecoveryPoint::default();
+        newest_clocks.insert(1, 0, 10);
+        newest_clocks.insert(2, 0, 5);
+
+        // Lagging on clock (1, 0) only
+        let mut behind = RecoveryPoint::default();
+        behind.insert(1, 0, 6);
+        behind.insert(2, 0, 5);
+
+        let diff = newest_clocks.diff(&behind);
+        assert_eq!(diff.len(), 1);
+        let recovery_point =
+            RecoveryPoint::try_from(api::grpc::qdrant::RecoveryPoint::from(&diff)).unwrap();
+
+        // Records of clock (1, 0) are replayed from tick 7, none of clock (2, 0) before that
+        let first_missing = tags
+            .iter()
+            .position(|tag| (tag.peer_id, tag.clock_id, tag.clock_tick) == (1, 0, 7))
+            .unwrap() as u64;
+        let resolve_result = resolve_wal_delta(
+            operations(),
+            recovery_point.clone(),
+            newest_clocks.clone(),
+            RecoveryPoint::default(),
+        );
+        assert_eq!(resolve_result.unwrap(), Some(first_missing));
+
+        // Truncated history of the clock that isn't lagging doesn't matter
+        let mut oldest_clocks = RecoveryPoint::default();
+        oldest_clocks.insert(2, 0, 3);
+        let resolve_result =
+            resolve_wal_delta(operations(), recovery_point, newest_clocks, oldest_clocks);
+        assert_eq!(resolve_result.unwrap(), Some(first_missing));
+    }
+
     /// Assert that we `check_clock_tag_ordering_property` on the WAL.
     async fn assert_wal_ordering_property(wal: &RecoverableWal, allow_gaps: bool) {
         // Grab list of clock tags from WAL records, skip non-existent or below cutoff tags
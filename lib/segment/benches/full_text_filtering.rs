@@ -0,0 +1,143 @@
+#[cfg(not(target_os = "windows"))]
+mod prof;
+
+use std::sync::Arc;
+
+use atomic_refcell::AtomicRefCell;
+use common::types::PointOffsetType;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use itertools::Itertools;
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use segment::fixtures::payload_context_fixture::FixtureIdTracker;
+use segment::fixtures::payload_fixtures::TEXT_KEY;
+use segment::index::struct_payload_index::StructPayloadIndex;
+use segment::index::PayloadIndex;
+use segment::payload_storage::in_memory_payload_storage::InMemoryPayloadStorage;
+use segment::payload_storage::PayloadStorage;
+use segment::types::{Condition, FieldCondition, Filter, Match, PayloadSchemaType};
+use serde_json::json;
+use tempfile::Builder;
+
+const NUM_POINTS: usize = 100_000;
+const VOCAB_SIZE: usize = 1_000;
+const TOKENS_PER_DOCUMENT: usize = 10;
+
+fn random_words<R: Rng + ?Sized>(rng: &mut R, count: usize) -> String {
+    (0..count)
+        .map(|_| format!("word{}", rng.gen_range(0..VOCAB_SIZE)))
+        .join(" ")
+}
+
+fn random_text_filter<R: Rng + ?Sized>(rng: &mut R, tokens: usize) -> Filter {
+    Filter::new_must(Condition::Field(FieldCondition::new_match(
+        TEXT_KEY.parse().unwrap(),
+        Match::new_text(&random_words(rng, tokens)),
+    )))
+}
+
+fn full_text_filtering(c: &mut Criterion) {
+    let mut group = c.benchmark_group("full-text-filtering-group");
+
+    let seed = 42;
+
+    let mut rng = StdRng::seed_from_u64(seed);
+
+    let dir = Builder::new().prefix("storage_dir").tempdir().unwrap();
+
+    // generate points with payload
+    let mut payload_storage = InMemoryPayloadStorage::default();
+    for id in 0..NUM_POINTS {
+        let payload = json!({
+            TEXT_KEY: random_words(&mut rng, TOKENS_PER_DOCUMENT),
+        })
+        .into();
+        payload_storage
+            .assign(id as PointOffsetType, &payload)
+            .unwrap();
+    }
+
+    let payload_storage = Arc::new(AtomicRefCell::new(payload_storage.into()));
+    let id_tracker = Arc::new(AtomicRefCell::new(FixtureIdTracker::new(NUM_POINTS)));
+
+    let mut index = StructPayloadIndex::open(
+        payload_storage.clone(),
+        id_tracker.clone(),
+        dir.path(),
+        true,
+    )
+    .unwrap();
+
+    // add full text index
+    index
+        .set_indexed(&TEXT_KEY.parse().unwrap(), PayloadSchemaType::Text.into())
+        .unwrap();
+
+    // make sure all points are indexed
+    assert_eq!(index.indexed_points(&TEXT_KEY.parse().unwrap()), NUM_POINTS);
+
+    let mut result_size = 0;
+    let mut query_count = 0;
+
+    group.bench_function("single-token-mutable-index", |b| {
+        b.iter_batched(
+            || random_text_filter(&mut rng, 1),
+            |filter| {
+                result_size += index.query_points(&filter).len();
+                query_count += 1;
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("two-tokens-mutable-index", |b| {
+        b.iter_batched(
+            || random_text_filter(&mut rng, 2),
+            |filter| {
+                result_size += index.query_points(&filter).len();
+                query_count += 1;
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    // flush data
+    index.flusher()().unwrap();
+    drop(index);
+
+    // reload as IMMUTABLE index
+    let index = StructPayloadIndex::open(payload_storage, id_tracker, dir.path(), false).unwrap();
+
+    group.bench_function("single-token-immutable-index", |b| {
+        b.iter_batched(
+            || random_text_filter(&mut rng, 1),
+            |filter| {
+                result_size += index.query_points(&filter).len();
+                query_count += 1;
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("two-tokens-immutable-index", |b| {
+        b.iter_batched(
+            || random_text_filter(&mut rng, 2),
+            |filter| {
+                result_size += index.query_points(&filter).len();
+                query_count += 1;
+            },
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+#[cfg(not(target_os = "windows"))]
+criterion_group! {
+    name = benches;
+    config = Criterion::default().with_profiler(prof::FlamegraphProfiler::new(100));
+    targets = full_text_filtering
+}
+
+criterion_main!(benches);
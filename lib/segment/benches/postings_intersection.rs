@@ -0,0 +1,58 @@
+#[cfg(not(target_os = "windows"))]
+mod prof;
+
+use common::types::PointOffsetType;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use segment::index::field_index::full_text_index::{
+    merge_intersect_postings_iterator, probe_intersect_postings_iterator, PostingList,
+};
+
+const NUM_POINTS: PointOffsetType = 1_000_000;
+const NUM_POSTINGS: usize = 8;
+
+fn random_posting<R: Rng + ?Sized>(rng: &mut R, len: usize) -> PostingList {
+    let mut posting = PostingList::default();
+    for _ in 0..len {
+        posting.insert(rng.gen_range(0..NUM_POINTS));
+    }
+    posting
+}
+
+fn postings_intersection(c: &mut Criterion) {
+    let mut rng = StdRng::seed_from_u64(42);
+
+    // All lists of similar length
+    let balanced = (0..NUM_POSTINGS)
+        .map(|_| random_posting(&mut rng, 500_000))
+        .collect::<Vec<_>>();
+
+    // One short list, all others long
+    let skewed = std::iter::once(random_posting(&mut rng, 1_000))
+        .chain((1..NUM_POSTINGS).map(|_| random_posting(&mut rng, 500_000)))
+        .collect::<Vec<_>>();
+
+    let mut group = c.benchmark_group("postings-intersection-group");
+
+    for (name, postings) in [("balanced", &balanced), ("skewed", &skewed)] {
+        group.bench_function(format!("{name}-probe"), |b| {
+            b.iter(|| probe_intersect_postings_iterator(postings.iter().collect()).count())
+        });
+
+        group.bench_function(format!("{name}-merge"), |b| {
+            b.iter(|| merge_intersect_postings_iterator(postings.iter().collect()).count())
+        });
+    }
+
+    group.finish();
+}
+
+#[cfg(not(target_os = "windows"))]
+criterion_group! {
+    name = benches;
+    config = Criterion::default().with_profiler(prof::FlamegraphProfiler::new(100));
+    targets = postings_intersection
+}
+
+criterion_main!(benches);
@@ -56,6 +56,9 @@ pub enum OperationError {
     WrongMulti,
     #[error("Wrong key of payload")]
     WrongPayloadKey { description: String },
+    /// Operation requires a mutable index, e.g. adding values to an immutable text index.
+    #[error("Immutable index does not support operation: {operation}")]
+    ImmutableIndex { operation: &'static str },
 }
 
 impl OperationError {
@@ -188,6 +188,7 @@ impl BinaryIndex {
             points_count: self.memory.indexed_count(),
             points_values_count: self.memory.trues_count() + self.memory.falses_count(),
             histogram_bucket_size: None,
+            text_index: None,
         }
     }
 
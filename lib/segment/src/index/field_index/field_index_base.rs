@@ -1,6 +1,6 @@
 use std::fmt::Formatter;
 
-use common::types::PointOffsetType;
+use common::types::{PointOffsetType, TelemetryDetail};
 use serde_json::Value;
 use smol_str::SmolStr;
 
@@ -299,7 +299,7 @@ impl FieldIndex {
         }
     }
 
-    pub fn get_telemetry_data(&self) -> PayloadIndexTelemetry {
+    pub fn get_telemetry_data(&self, detail: TelemetryDetail) -> PayloadIndexTelemetry {
         match self {
             FieldIndex::IntIndex(index) => index.get_telemetry_data(),
             FieldIndex::DatetimeIndex(index) => index.get_telemetry_data(),
@@ -308,7 +308,7 @@ impl FieldIndex {
             FieldIndex::FloatIndex(index) => index.get_telemetry_data(),
             FieldIndex::GeoIndex(index) => index.get_telemetry_data(),
             FieldIndex::BinaryIndex(index) => index.get_telemetry_data(),
-            FieldIndex::FullTextIndex(index) => index.get_telemetry_data(),
+            FieldIndex::FullTextIndex(index) => index.get_telemetry_data(detail),
         }
     }
 
@@ -1,18 +1,60 @@
+use std::cmp::{Ordering, Reverse};
 use std::collections::{BTreeSet, HashMap};
+#[cfg(feature = "mmap-vocab")]
+use std::path::PathBuf;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
 
+use bitvec::prelude::BitVec;
+use common::fixed_length_priority_queue::FixedLengthPriorityQueue;
 use common::types::PointOffsetType;
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 
-use super::posting_list::{CompressedPostingList, PostingList};
+use super::posting_cache::{PostingCache, PostingCacheStats};
+use super::posting_list::{
+    CompressedPostingList, CompressedPostingListBuilder, CompressionOptions, PostingCompression,
+    PostingList,
+};
 use super::postings_iterator::{
-    intersect_compressed_postings_iterator, intersect_postings_iterator,
+    count_compressed_postings_intersection, count_postings_intersection,
+    intersect_compressed_postings_iterator, intersect_decompressed_postings_iterator,
+    intersect_postings_iterator, intersect_postings_iterator_desc,
 };
-use crate::common::operation_error::{OperationError, OperationResult};
+use super::vocab::Vocab;
+use crate::common::operation_error::{check_process_stopped, OperationError, OperationResult};
 use crate::index::field_index::{CardinalityEstimation, PayloadBlockCondition, PrimaryCondition};
+use crate::index::query_estimator::{combine_must_estimations, combine_should_estimations};
 use crate::types::{FieldCondition, Match, PayloadKeyType};
 
 pub type TokenId = u32;
 
+/// Document frequency and total term frequency of every token, see
+/// [`InvertedIndex::build_index_collecting`]
+pub type TokenStats = HashMap<TokenId, (usize, usize)>;
+
+/// Distribution of document lengths in distinct tokens, see
+/// [`InvertedIndex::document_length_stats`]
+///
+/// Percentiles use the nearest-rank method, so they are lengths of actual documents.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct DocLengthStats {
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    pub median: usize,
+    pub p95: usize,
+}
+
+/// Number of documents to index between checks for cancellation of a build
+const BUILD_CANCELLATION_CHECK_INTERVAL: usize = 1_000;
+
+/// Sorted token IDs of an indexed value
+///
+/// A document is a multiset: repeated tokens are kept, so that their term frequency is known,
+/// see [`Document::term_frequency`]. Documents built from tokenized text hold every token once.
+/// Matching and value counts only consider distinct tokens, see
+/// [`Document::distinct_token_count`].
 #[derive(Default, Serialize, Deserialize, Debug, Clone)]
 pub struct Document {
     tokens: Vec<TokenId>,
@@ -24,10 +66,31 @@ impl Document {
         Self { tokens }
     }
 
+    /// Number of tokens in this document, including repeated tokens
     pub fn len(&self) -> usize {
         self.tokens.len()
     }
 
+    /// Number of distinct tokens in this document
+    pub fn distinct_token_count(&self) -> usize {
+        // tokens are sorted, so repeated tokens are adjacent
+        self.tokens
+            .iter()
+            .enumerate()
+            .filter(|&(i, token)| i == 0 || self.tokens[i - 1] != *token)
+            .count()
+    }
+
+    /// Number of occurrences of `token` in this document
+    pub fn term_frequency(&self, token: TokenId) -> usize {
+        // tokens are sorted, so repeated tokens are adjacent
+        let start = self.tokens.partition_point(|&t| t < token);
+        self.tokens[start..]
+            .iter()
+            .take_while(|&&t| t == token)
+            .count()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.tokens.is_empty()
     }
@@ -39,16 +102,153 @@ impl Document {
     pub fn check(&self, token: TokenId) -> bool {
         self.tokens.binary_search(&token).is_ok()
     }
+
+    /// Remove all occurrences of `token`, keeping the remaining tokens sorted
+    pub fn remove_token(&mut self, token: TokenId) {
+        self.tokens.retain(|&t| t != token);
+    }
+
+    /// Jaccard similarity of the token sets of both documents, `|A ∩ B| / |A ∪ B|`
+    ///
+    /// Repeated tokens are counted once. Two empty documents are considered identical.
+    pub fn jaccard_similarity(&self, other: &Document) -> f32 {
+        // tokens are sorted, so both sets can be merged in a single pass
+        let (mut intersection, mut union) = (0usize, 0usize);
+        let mut left = self.tokens.iter().dedup().peekable();
+        let mut right = other.tokens.iter().dedup().peekable();
+        loop {
+            match (left.peek(), right.peek()) {
+                (Some(l), Some(r)) => {
+                    match l.cmp(r) {
+                        Ordering::Less => {
+                            left.next();
+                        }
+                        Ordering::Greater => {
+                            right.next();
+                        }
+                        Ordering::Equal => {
+                            intersection += 1;
+                            left.next();
+                            right.next();
+                        }
+                    }
+                    union += 1;
+                }
+                (Some(_), None) => {
+                    union += left.count();
+                    break;
+                }
+                (None, Some(_)) => {
+                    union += right.count();
+                    break;
+                }
+                (None, None) => break,
+            }
+        }
+
+        if union == 0 {
+            return 1.0;
+        }
+        intersection as f32 / union as f32
+    }
+
+    /// Whether every token of this document is also in `other`
+    ///
+    /// Repeated tokens are counted once. An empty document is a subset of any document.
+    pub fn is_subset_of(&self, other: &Document) -> bool {
+        // tokens are sorted, so a single merge pass is enough
+        let mut right = other.tokens.iter().peekable();
+        for token in self.tokens.iter().dedup() {
+            while right.next_if(|r| *r < token).is_some() {}
+            if right.next_if_eq(&token).is_none() {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Limits on how much of the vocabulary a query expansion may examine
+///
+/// Protects against queries which would otherwise scan the whole vocabulary of a large index.
+#[derive(Debug, Clone, Copy)]
+pub struct ExpansionBudget {
+    /// Stop once this many matching tokens are collected
+    pub max_terms: usize,
+    /// Stop once this many vocabulary entries are examined
+    pub max_scanned: usize,
+}
+
+impl Default for ExpansionBudget {
+    /// Unlimited budget
+    fn default() -> Self {
+        Self {
+            max_terms: usize::MAX,
+            max_scanned: usize::MAX,
+        }
+    }
 }
 
+/// Tokens which must all be in a document for it to match
+///
+/// An empty query matches nothing, consistently in [`ParsedQuery::check_match`] and
+/// [`InvertedIndex::filter`]. So does a query with any unseen (`None`) token.
 #[derive(Debug)]
 pub struct ParsedQuery {
     pub tokens: Vec<Option<TokenId>>,
 }
 
 impl ParsedQuery {
+    /// Create a query with sorted and deduplicated tokens, so that it can be matched with
+    /// [`ParsedQuery::check_match_merge`]
+    pub fn new(mut tokens: Vec<Option<TokenId>>) -> Self {
+        tokens.sort_unstable();
+        tokens.dedup();
+        Self { tokens }
+    }
+
     pub fn check_match(&self, document: &Document) -> bool {
-        if self.tokens.contains(&None) {
+        if self.tokens.is_empty() || self.tokens.contains(&None) {
+            return false;
+        }
+
+        // Binary search of every query token costs about `log2(document.len())` steps
+        let binary_search_cost =
+            self.tokens.len() * (usize::BITS - document.len().leading_zeros()) as usize;
+        let merge_cost = self.tokens.len() + document.len();
+        if merge_cost < binary_search_cost && self.is_sorted() {
+            self.check_match_merge(document)
+        } else {
+            self.check_match_binary_search(document)
+        }
+    }
+
+    /// Match against `document` by walking query and document tokens at once
+    ///
+    /// Takes `O(query.len() + document.len())`, but query tokens must be sorted, like in queries
+    /// created by [`ParsedQuery::new`].
+    pub fn check_match_merge(&self, document: &Document) -> bool {
+        debug_assert!(self.is_sorted(), "query tokens must be sorted");
+        if self.tokens.is_empty() {
+            return false;
+        }
+        let mut document_tokens = document.tokens().iter().peekable();
+        self.tokens.iter().all(|query_token| {
+            // `None` sorts first, an unseen token never matches
+            let Some(query_token) = query_token else {
+                return false;
+            };
+            while document_tokens
+                .next_if(|&token| token < query_token)
+                .is_some()
+            {}
+            document_tokens.peek() == Some(&query_token)
+        })
+    }
+
+    /// Match against `document` by binary searching every query token in the document
+    fn check_match_binary_search(&self, document: &Document) -> bool {
+        if self.tokens.is_empty() || self.tokens.contains(&None) {
             return false;
         }
         // Check that all tokens are in document
@@ -57,8 +257,106 @@ impl ParsedQuery {
             // unwrap crash safety: all tokens exist in the vocabulary if it passes the above check
             .all(|query_token| document.check(query_token.unwrap()))
     }
+
+    fn is_sorted(&self) -> bool {
+        self.tokens.windows(2).all(|pair| pair[0] <= pair[1])
+    }
+}
+
+/// Query with a boost per token, used to rank documents by relevance
+#[derive(Debug)]
+pub struct ScoredQuery {
+    pub tokens: Vec<ScoredToken>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScoredToken {
+    pub token: Option<TokenId>,
+    /// Multiplier of the token contribution to the score, `1.0` is neutral.
+    pub boost: f32,
+}
+
+impl ScoredQuery {
+    pub const DEFAULT_BOOST: f32 = 1.0;
+
+    /// Set boost of all occurrences of `token` in this query
+    pub fn with_boost(mut self, token: TokenId, boost: f32) -> Self {
+        for scored_token in &mut self.tokens {
+            if scored_token.token == Some(token) {
+                scored_token.boost = boost;
+            }
+        }
+        self
+    }
+}
+
+impl From<ParsedQuery> for ScoredQuery {
+    fn from(query: ParsedQuery) -> Self {
+        Self {
+            tokens: query
+                .tokens
+                .into_iter()
+                .map(|token| ScoredToken {
+                    token,
+                    boost: Self::DEFAULT_BOOST,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Rank documents by relevance across several fields, e.g. title and body
+///
+/// Every field is scored independently by IDF with [`InvertedIndex::score`], and the scores are
+/// summed per point, multiplied by the field weight. Points matching in any of the fields are ranked.
+pub struct MultiFieldScorer<'a> {
+    fields: Vec<(&'a InvertedIndex, f32)>,
+}
+
+impl<'a> MultiFieldScorer<'a> {
+    /// Score the given `(index, weight)` fields, which must share point offsets
+    pub fn new(fields: Vec<(&'a InvertedIndex, f32)>) -> Self {
+        Self { fields }
+    }
+
+    /// Rank all documents matching `queries`, one query per field, parsed by that field's index
+    ///
+    /// Returns `(point_id, score)` pairs ordered by descending score.
+    pub fn score(&self, queries: Vec<ScoredQuery>) -> OperationResult<Vec<(PointOffsetType, f32)>> {
+        if queries.len() != self.fields.len() {
+            return Err(OperationError::ValidationError {
+                description: format!(
+                    "Expected one query per field, got {} queries for {} fields",
+                    queries.len(),
+                    self.fields.len(),
+                ),
+            });
+        }
+
+        let mut scores: HashMap<PointOffsetType, f32> = HashMap::new();
+        for (&(index, weight), query) in self.fields.iter().zip(&queries) {
+            for (idx, score) in index.score(query) {
+                *scores.entry(idx).or_default() += weight * score;
+            }
+        }
+
+        let mut scores: Vec<_> = scores.into_iter().collect();
+        scores.sort_unstable_by(|(a_idx, a_score), (b_idx, b_score)| {
+            b_score.total_cmp(a_score).then(a_idx.cmp(b_idx))
+        });
+        Ok(scores)
+    }
+}
+
+/// Query in conjunctive normal form
+///
+/// A document matches if it contains at least one token of every clause.
+#[derive(Debug)]
+pub struct CnfQuery {
+    pub clauses: Vec<Vec<Option<TokenId>>>,
 }
 
+#[derive(Clone)]
 pub enum InvertedIndex {
     Mutable(MutableInvertedIndex),
     Immutable(ImmutableInvertedIndex),
@@ -73,7 +371,48 @@ impl InvertedIndex {
         }
     }
 
-    pub fn document_from_tokens(&mut self, tokens: &BTreeSet<String>) -> Document {
+    /// Whether documents can be added to this index, as passed to [`InvertedIndex::new`]
+    pub fn is_appendable(&self) -> bool {
+        matches!(self, InvertedIndex::Mutable(_))
+    }
+
+    /// Limit the vocabulary to `max` tokens
+    ///
+    /// Once the vocabulary is full, new tokens of documents are dropped, and counted in
+    /// [`InvertedIndex::dropped_token_count`]. Queries for dropped tokens match nothing, like
+    /// queries for unseen tokens. Tokens already in the vocabulary are kept.
+    pub fn with_max_vocab(mut self, max: usize) -> Self {
+        match &mut self {
+            InvertedIndex::Mutable(index) => index.vocab.set_max_len(max),
+            InvertedIndex::Immutable(index) => index.vocab.set_max_len(max),
+        }
+        self
+    }
+
+    /// Build a bloom filter of every posting list, to speed up [`InvertedIndex::check_match`]
+    ///
+    /// Only applies to an immutable index, its filters are built whenever the index is built.
+    /// Postings of a mutable index are not compressed, and checked without decompressing anyway.
+    pub fn with_bloom_filter(mut self) -> Self {
+        if let InvertedIndex::Immutable(index) = &mut self {
+            index.compression_options.bloom_filter = true;
+        }
+        self
+    }
+
+    /// Number of tokens dropped, because the vocabulary was full
+    pub fn dropped_token_count(&self) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => index.vocab.dropped_tokens(),
+            InvertedIndex::Immutable(index) => index.vocab.dropped_tokens(),
+        }
+    }
+
+    /// Convert tokens into a document, adding unseen tokens to the vocabulary
+    ///
+    /// Also returns IDs of the tokens that were not in the vocabulary yet and were added by this
+    /// call.
+    pub fn document_from_tokens(&mut self, tokens: &BTreeSet<String>) -> (Document, Vec<TokenId>) {
         let vocab = match self {
             InvertedIndex::Mutable(index) => &mut index.vocab,
             InvertedIndex::Immutable(index) => &mut index.vocab,
@@ -82,24 +421,28 @@ impl InvertedIndex {
     }
 
     fn document_from_tokens_impl(
-        vocab: &mut HashMap<String, TokenId>,
+        vocab: &mut Vocab,
         tokens: &BTreeSet<String>,
-    ) -> Document {
+    ) -> (Document, Vec<TokenId>) {
         let mut document_tokens = vec![];
+        let mut new_tokens = vec![];
         for token in tokens {
             // check if in vocab
             let vocab_idx = match vocab.get(token) {
-                Some(&idx) => idx,
-                None => {
-                    let next_token_id = vocab.len() as TokenId;
-                    vocab.insert(token.to_string(), next_token_id);
-                    next_token_id
-                }
+                Some(idx) => idx,
+                // tokens not fitting into the vocabulary are dropped, like they were never seen
+                None => match vocab.get_or_insert(token) {
+                    Some(idx) => {
+                        new_tokens.push(idx);
+                        idx
+                    }
+                    None => continue,
+                },
             };
             document_tokens.push(vocab_idx);
         }
 
-        Document::new(document_tokens)
+        (Document::new(document_tokens), new_tokens)
     }
 
     pub fn index_document(
@@ -109,9 +452,9 @@ impl InvertedIndex {
     ) -> OperationResult<()> {
         match self {
             InvertedIndex::Mutable(index) => index.index_document(idx, document),
-            InvertedIndex::Immutable(_index) => Err(OperationError::service_error(
-                "Can't add values to immutable text index",
-            )),
+            InvertedIndex::Immutable(_index) => Err(OperationError::ImmutableIndex {
+                operation: "add values",
+            }),
         }
     }
 
@@ -122,6 +465,33 @@ impl InvertedIndex {
         }
     }
 
+    /// Remove documents of all points for which `keep` returns false, returns how many were removed
+    ///
+    /// See [`MutableInvertedIndex::retain`]. Points of an immutable index are removed one by one,
+    /// which doesn't touch its postings anyway.
+    pub fn retain(&mut self, keep: impl Fn(PointOffsetType) -> bool) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => index.retain(keep),
+            InvertedIndex::Immutable(_) => {
+                let removed: Vec<_> = self.indexed_points().filter(|&idx| !keep(idx)).collect();
+                removed
+                    .into_iter()
+                    .filter(|&idx| self.remove_document(idx))
+                    .count()
+            }
+        }
+    }
+
+    /// Remove `token` from all documents, see [`MutableInvertedIndex::remove_token`]
+    pub fn remove_token(&mut self, token: &str) -> OperationResult<bool> {
+        match self {
+            InvertedIndex::Mutable(index) => Ok(index.remove_token(token)),
+            InvertedIndex::Immutable(_index) => Err(OperationError::ImmutableIndex {
+                operation: "remove tokens",
+            }),
+        }
+    }
+
     pub fn filter(&self, query: &ParsedQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
         match self {
             InvertedIndex::Mutable(index) => index.filter(query),
@@ -129,437 +499,4126 @@ impl InvertedIndex {
         }
     }
 
-    pub fn estimate_cardinality(
-        &self,
-        query: &ParsedQuery,
-        condition: &FieldCondition,
-    ) -> CardinalityEstimation {
-        let points_count = match self {
-            InvertedIndex::Mutable(index) => index.points_count,
-            InvertedIndex::Immutable(index) => index.points_count,
-        };
-        let posting_lengths: Option<Vec<usize>> = query
-            .tokens
+    /// Points matching a query in conjunctive normal form, in ascending order
+    ///
+    /// Each clause matches the union of postings of its tokens, unseen tokens contribute nothing.
+    /// Clauses are then intersected, by iterating the smallest union and probing the others.
+    pub fn filter_cnf(&self, query: &CnfQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        let mut clause_points: Vec<Vec<PointOffsetType>> = query
+            .clauses
             .iter()
-            .map(|&vocab_idx| match vocab_idx {
-                None => None,
-                // unwrap safety: same as in filter()
-                Some(idx) => match &self {
-                    Self::Mutable(index) => index
-                        .postings
-                        .get(idx as usize)
-                        .unwrap()
-                        .as_ref()
-                        .map(|p| p.len()),
-                    Self::Immutable(index) => index
-                        .postings
-                        .get(idx as usize)
-                        .unwrap()
-                        .as_ref()
-                        .map(|p| p.len()),
-                },
+            .map(|clause| {
+                clause
+                    .iter()
+                    .filter_map(|&token| self.live_postings(token?))
+                    .kmerge()
+                    .dedup()
+                    .collect()
             })
             .collect();
-        if posting_lengths.is_none() || points_count == 0 {
-            // There are unseen tokens -> no matches
-            return CardinalityEstimation {
-                primary_clauses: vec![PrimaryCondition::Condition(condition.clone())],
-                min: 0,
-                exp: 0,
-                max: 0,
-            };
-        }
-        let postings = posting_lengths.unwrap();
-        if postings.is_empty() {
+
+        let Some(smallest_idx) = clause_points
+            .iter()
+            .position_min_by_key(|points| points.len())
+        else {
             // Empty request -> no matches
-            return CardinalityEstimation {
-                primary_clauses: vec![PrimaryCondition::Condition(condition.clone())],
-                min: 0,
-                exp: 0,
-                max: 0,
-            };
-        }
-        // Smallest posting is the largest possible cardinality
-        let smallest_posting = postings.iter().min().copied().unwrap();
+            return Box::new(std::iter::empty());
+        };
+        let smallest_points = clause_points.swap_remove(smallest_idx);
 
-        return if postings.len() == 1 {
-            CardinalityEstimation {
-                primary_clauses: vec![PrimaryCondition::Condition(condition.clone())],
-                min: smallest_posting,
-                exp: smallest_posting,
-                max: smallest_posting,
-            }
-        } else {
-            let expected_frac: f64 = postings
+        Box::new(smallest_points.into_iter().filter(move |idx| {
+            clause_points
                 .iter()
-                .map(|posting| *posting as f64 / points_count as f64)
-                .product();
-            let exp = (expected_frac * points_count as f64) as usize;
-            CardinalityEstimation {
-                primary_clauses: vec![PrimaryCondition::Condition(condition.clone())],
-                min: 0, // ToDo: make better estimation
-                exp,
-                max: smallest_posting,
-            }
-        };
+                .all(|points| points.binary_search(idx).is_ok())
+        }))
     }
 
-    pub fn payload_blocks(
+    /// Points matching `query` in descending order, e.g. to paginate newest points first
+    pub fn filter_desc(
         &self,
-        threshold: usize,
-        key: PayloadKeyType,
-    ) -> Box<dyn Iterator<Item = PayloadBlockCondition> + '_> {
-        let map_filter_condition = move |(token, postings_len): (&str, usize)| {
-            if postings_len >= threshold {
-                Some(PayloadBlockCondition {
-                    condition: FieldCondition::new_match(key.clone(), Match::new_text(token)),
-                    cardinality: postings_len,
-                })
-            } else {
-                None
-            }
-        };
+        query: &ParsedQuery,
+    ) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        match self {
+            InvertedIndex::Mutable(index) => index.filter_desc(query),
+            InvertedIndex::Immutable(index) => index.filter_desc(query),
+        }
+    }
 
-        // It might be very hard to predict possible combinations of conditions,
-        // so we only build it for individual tokens
-        match &self {
-            InvertedIndex::Mutable(index) => Box::new(
-                index
-                    .vocab_with_positngs_len_iter()
-                    .filter_map(map_filter_condition),
-            ),
-            InvertedIndex::Immutable(index) => Box::new(
-                index
-                    .vocab_with_positngs_len_iter()
-                    .filter_map(map_filter_condition),
-            ),
+    /// Number of points matching `query`, same as `filter(query).count()` but without boxing
+    /// and yielding every matched point
+    pub fn count_matches(&self, query: &ParsedQuery) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => index.count_matches(query),
+            InvertedIndex::Immutable(index) => index.count_matches(query),
         }
     }
 
-    pub fn build_index(
-        &mut self,
-        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+    /// Check internal consistency of the index, e.g. after loading it
+    ///
+    /// Returns an error describing the first violated invariant.
+    pub fn validate(&self) -> OperationResult<()> {
+        match self {
+            InvertedIndex::Mutable(index) => index.validate(),
+            InvertedIndex::Immutable(index) => index.validate(),
+        }
+    }
+
+    /// Check that `points_count` matches the number of documents, and that every posting list is
+    /// sorted and only refers to points accepted by `is_known_point`
+    fn validate_postings<'a>(
+        points_count: usize,
+        documents_count: usize,
+        postings: impl Iterator<Item = (usize, Box<dyn Iterator<Item = PointOffsetType> + 'a>)>,
+        is_known_point: impl Fn(PointOffsetType) -> bool,
     ) -> OperationResult<()> {
-        let mut index = MutableInvertedIndex::default();
-        index.build_index(iter)?;
+        if points_count != documents_count {
+            return Err(OperationError::service_error(format!(
+                "Points count {points_count} doesn't match {documents_count} documents",
+            )));
+        }
 
-        match self {
-            InvertedIndex::Mutable(i) => {
-                *i = index;
-            }
-            InvertedIndex::Immutable(i) => {
-                *i = index.into();
+        for (token_id, posting) in postings {
+            let mut previous = None;
+            for idx in posting {
+                if previous.is_some_and(|previous| previous >= idx) {
+                    return Err(OperationError::service_error(format!(
+                        "Posting list of token {token_id} is not sorted at point {idx}",
+                    )));
+                }
+                if !is_known_point(idx) {
+                    return Err(OperationError::service_error(format!(
+                        "Posting list of token {token_id} refers to point {idx} without document",
+                    )));
+                }
+                previous = Some(idx);
             }
         }
 
         Ok(())
     }
 
-    pub fn check_match(&self, parsed_query: &ParsedQuery, point_id: PointOffsetType) -> bool {
-        match self {
-            InvertedIndex::Mutable(index) => index.check_match(parsed_query, point_id),
-            InvertedIndex::Immutable(index) => index.check_match(parsed_query, point_id),
+    /// Offsets of all points with a document in this index, in ascending order
+    pub fn indexed_points(&self) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        fn some_offsets<T>(points: &[Option<T>]) -> impl Iterator<Item = PointOffsetType> + '_ {
+            points
+                .iter()
+                .enumerate()
+                .filter(|(_, point)| point.is_some())
+                .map(|(idx, _)| idx as PointOffsetType)
         }
-    }
 
-    pub fn values_is_empty(&self, point_id: PointOffsetType) -> bool {
         match self {
-            InvertedIndex::Mutable(index) => index.values_is_empty(point_id),
-            InvertedIndex::Immutable(index) => index.values_is_empty(point_id),
+            InvertedIndex::Mutable(index) => Box::new(some_offsets(&index.point_to_docs)),
+            InvertedIndex::Immutable(index) => {
+                Box::new(some_offsets(&index.point_documents_tokens))
+            }
         }
     }
 
-    pub fn values_count(&self, point_id: PointOffsetType) -> usize {
+    /// Export the index as a sparse term-document matrix of `(token, point)` pairs
+    ///
+    /// Pairs are ordered by token, then by point offset. Removed points are skipped, see
+    /// [`ImmutableInvertedIndex::postings`].
+    pub fn export_sparse(&self) -> Box<dyn Iterator<Item = (TokenId, PointOffsetType)> + '_> {
         match self {
-            InvertedIndex::Mutable(index) => index.values_count(point_id),
-            InvertedIndex::Immutable(index) => index.values_count(point_id),
+            InvertedIndex::Mutable(index) => Box::new(
+                index
+                    .postings
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(token, posting)| Some((token as TokenId, posting.as_ref()?)))
+                    .flat_map(|(token, posting)| posting.iter().map(move |idx| (token, idx))),
+            ),
+            InvertedIndex::Immutable(index) => Box::new(
+                index
+                    .postings
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(token, posting)| Some((token as TokenId, posting.as_ref()?)))
+                    .flat_map(|(token, posting)| posting.iter().map(move |idx| (token, idx)))
+                    .filter(|&(_, idx)| !index.values_is_empty(idx)),
+            ),
         }
     }
 
-    pub fn points_count(&self) -> usize {
-        match self {
-            InvertedIndex::Mutable(index) => index.points_count,
-            InvertedIndex::Immutable(index) => index.points_count,
-        }
+    /// Points with any value in the field, regardless of tokens, in ascending order
+    ///
+    /// Same as [`InvertedIndex::indexed_points`], for use as a "field exists" filter. A value
+    /// without any tokens, such as an empty string, still counts.
+    pub fn filter_exists(&self) -> impl Iterator<Item = PointOffsetType> + '_ {
+        self.indexed_points()
     }
 
-    pub fn get_token(&self, token: &str) -> Option<TokenId> {
-        match self {
-            InvertedIndex::Mutable(index) => index.vocab.get(token).copied(),
-            InvertedIndex::Immutable(index) => index.vocab.get(token).copied(),
-        }
+    /// Exact cardinality of [`InvertedIndex::filter_exists`]
+    pub fn estimate_exists_cardinality(&self) -> CardinalityEstimation {
+        CardinalityEstimation::exact(self.points_count())
     }
-}
 
-#[derive(Default)]
-pub struct MutableInvertedIndex {
-    postings: Vec<Option<PostingList>>,
-    vocab: HashMap<String, TokenId>,
-    point_to_docs: Vec<Option<Document>>,
-    points_count: usize,
-}
+    /// Rank all documents containing at least one of the query tokens
+    ///
+    /// IDF scoring: every token present in a document contributes its inverse document
+    /// frequency `ln(1 + (N - df + 0.5) / (df + 0.5))`, multiplied by the token boost. `N` is
+    /// the number of points, and `df` the number of points containing the token. Term frequency
+    /// is not taken into account: a token contributes once, even if the document repeats it. The
+    /// immutable index doesn't keep documents to count repeats in. Document length doesn't affect
+    /// the score either. Unseen tokens contribute nothing.
+    ///
+    /// Returns `(point_id, score)` pairs ordered by descending score.
+    pub fn score(&self, query: &ScoredQuery) -> Vec<(PointOffsetType, f32)> {
+        if self.points_count() == 0 {
+            // Nothing to rank, and IDF is meaningless without documents
+            return Vec::new();
+        }
+        let points_count = self.points_count() as f32;
+        let mut scores: HashMap<PointOffsetType, f32> = HashMap::new();
 
-impl MutableInvertedIndex {
-    fn build_index(
-        &mut self,
-        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
-    ) -> OperationResult<()> {
-        self.points_count = 0;
-        self.vocab.clear();
-        self.postings.clear();
-        self.point_to_docs.clear();
+        for scored_token in &query.tokens {
+            let Some(token) = scored_token.token else {
+                continue;
+            };
 
-        // update point_to_docs
-        for i in iter {
-            self.points_count += 1;
-            let (idx, tokens) = i?;
+            let Some(postings) = self.live_postings(token) else {
+                continue;
+            };
+            // Count live points only, see `ImmutableInvertedIndex::postings`
+            let postings = postings.collect_vec();
 
-            if self.point_to_docs.len() <= idx as usize {
-                self.point_to_docs
-                    .resize_with(idx as usize + 1, Default::default);
-            }
+            let postings_len = postings.len() as f32;
+            let idf = (1.0 + (points_count - postings_len + 0.5) / (postings_len + 0.5)).ln();
+            let contribution = idf * scored_token.boost;
 
-            let document = InvertedIndex::document_from_tokens_impl(&mut self.vocab, &tokens);
-            self.point_to_docs[idx as usize] = Some(document);
+            for idx in postings {
+                *scores.entry(idx).or_default() += contribution;
+            }
         }
 
-        // build postings from point_to_docs
-        // build in order to increase document id
-        for (idx, doc) in self.point_to_docs.iter().enumerate() {
-            if let Some(doc) = doc {
-                for token_idx in doc.tokens() {
-                    if self.postings.len() <= *token_idx as usize {
-                        self.postings
-                            .resize_with(*token_idx as usize + 1, Default::default);
-                    }
-                    let posting = self
-                        .postings
-                        .get_mut(*token_idx as usize)
-                        .expect("posting must exist even if with None");
-                    match posting {
-                        None => *posting = Some(PostingList::new(idx as PointOffsetType)),
-                        Some(vec) => vec.insert(idx as PointOffsetType),
-                    }
-                }
+        let mut scores: Vec<_> = scores.into_iter().collect();
+        scores.sort_unstable_by(|(a_idx, a_score), (b_idx, b_score)| {
+            b_score.total_cmp(a_score).then(a_idx.cmp(b_idx))
+        });
+        scores
+    }
+
+    /// Iterate over the live documents in the posting list of `token`
+    fn live_postings(
+        &self,
+        token: TokenId,
+    ) -> Option<Box<dyn Iterator<Item = PointOffsetType> + '_>> {
+        match self {
+            Self::Mutable(index) => {
+                let posting = index.postings.get(token as usize)?.as_ref()?;
+                Some(Box::new(posting.iter()))
+            }
+            Self::Immutable(index) => {
+                let posting = index.postings.get(token as usize)?.as_ref()?;
+                let iter = posting.iter().filter(|&idx| !index.values_is_empty(idx));
+                Some(Box::new(iter))
             }
         }
-
-        Ok(())
     }
 
-    fn index_document(&mut self, idx: PointOffsetType, document: Document) -> OperationResult<()> {
-        self.points_count += 1;
-        if self.point_to_docs.len() <= idx as usize {
-            self.point_to_docs
-                .resize_with(idx as usize + 1, Default::default);
+    /// Number of tokens per posting length bucket, to inspect the shape of the index
+    ///
+    /// `buckets` are ascending, inclusive upper bounds of posting lengths: bucket `i` counts
+    /// tokens with a posting length in `(buckets[i - 1], buckets[i]]`. The last element of the
+    /// result counts tokens with postings longer than all bounds. Tokens without postings have
+    /// length 0, see [`ImmutableInvertedIndex::postings`] for removed points.
+    pub fn posting_length_histogram(&self, buckets: &[usize]) -> Vec<usize> {
+        let posting_lengths: Box<dyn Iterator<Item = usize> + '_> = match self {
+            InvertedIndex::Mutable(index) => Box::new((0..index.vocab.len()).map(|token| {
+                index
+                    .postings
+                    .get(token)
+                    .and_then(Option::as_ref)
+                    .map_or(0, PostingList::len)
+            })),
+            InvertedIndex::Immutable(index) => Box::new((0..index.vocab.len()).map(|token| {
+                index
+                    .postings
+                    .get(token)
+                    .and_then(Option::as_ref)
+                    .map_or(0, CompressedPostingList::len)
+            })),
+        };
+
+        let mut histogram = vec![0; buckets.len() + 1];
+        for posting_len in posting_lengths {
+            let bucket = buckets.partition_point(|&bound| bound < posting_len);
+            histogram[bucket] += 1;
         }
+        histogram
+    }
 
-        for token_idx in document.tokens() {
-            let token_idx_usize = *token_idx as usize;
-            if self.postings.len() <= token_idx_usize {
-                self.postings
-                    .resize_with(token_idx_usize + 1, Default::default);
-            }
-            let posting = self
-                .postings
-                .get_mut(token_idx_usize)
-                .expect("posting must exist even if with None");
-            match posting {
-                None => *posting = Some(PostingList::new(idx)),
-                Some(vec) => vec.insert(idx),
-            }
+    /// Token of `query` with the smallest posting list, together with its length
+    ///
+    /// This is the token that should drive the intersection of the query, the others only need to
+    /// be probed. Returns `None` if nothing can match, i.e. the query is empty or has a token
+    /// without postings.
+    pub fn best_primary_token(&self, query: &ParsedQuery) -> Option<(TokenId, usize)> {
+        let posting_lengths: Option<Vec<_>> = query
+            .tokens
+            .iter()
+            .map(|&vocab_idx| {
+                let token = vocab_idx?;
+                Some((token, self.posting_len(token)?))
+            })
+            .collect();
+        posting_lengths?
+            .into_iter()
+            .min_by_key(|&(_token, posting_len)| posting_len)
+    }
+
+    pub fn estimate_cardinality(
+        &self,
+        query: &ParsedQuery,
+        condition: &FieldCondition,
+    ) -> CardinalityEstimation {
+        let (min, exp, max) = self.estimate_matches(query);
+        CardinalityEstimation {
+            primary_clauses: vec![PrimaryCondition::Condition(condition.clone())],
+            min,
+            exp,
+            max,
         }
-        self.point_to_docs[idx as usize] = Some(document);
-        Ok(())
     }
 
-    fn remove_document(&mut self, idx: PointOffsetType) -> bool {
-        if self.point_to_docs.len() <= idx as usize {
-            return false; // Already removed or never actually existed
+    /// Expected fraction of indexed points matching `query`, in `[0, 1]`
+    ///
+    /// Based on the same expected number of matches as [`Self::estimate_cardinality`].
+    pub fn estimate_selectivity(&self, query: &ParsedQuery) -> f64 {
+        let points_count = self.points_count();
+        if points_count == 0 {
+            return 0.0;
         }
+        let (_min, exp, _max) = self.estimate_matches(query);
+        (exp as f64 / points_count as f64).clamp(0.0, 1.0)
+    }
 
-        let removed_doc = match std::mem::take(&mut self.point_to_docs[idx as usize]) {
-            Some(doc) => doc,
-            None => return false,
+    /// Bounds `(min, max)` on the number of points matching all tokens of `query`
+    ///
+    /// Only posting lengths are used, postings are not iterated. `max` is the shortest posting,
+    /// `min` follows from inclusion-exclusion: `k` postings over `N` points overlap in at least
+    /// `sum(len_i) - (k - 1) * N` points. Removed points of an immutable index loosen `min`, see
+    /// [`ImmutableInvertedIndex::postings`].
+    pub fn intersection_bounds(&self, query: &ParsedQuery) -> (usize, usize) {
+        let points_count = self.points_count();
+        let posting_lengths: Option<Vec<usize>> = query
+            .tokens
+            .iter()
+            .map(|&vocab_idx| self.posting_len(vocab_idx?))
+            .collect();
+        let Some(posting_lengths) = posting_lengths else {
+            // There are unseen tokens -> no matches
+            return (0, 0);
         };
+        if posting_lengths.is_empty() || points_count == 0 {
+            // Empty request or empty index -> no matches
+            return (0, 0);
+        }
 
-        self.points_count -= 1;
-
-        for removed_token in removed_doc.tokens() {
-            // unwrap safety: posting list exists and contains the document id
-            let posting = self.postings.get_mut(*removed_token as usize).unwrap();
-            if let Some(vec) = posting {
-                vec.remove(idx);
+        // Number of points postings may refer to, and how many of them may be removed
+        let (universe, removed) = match self {
+            InvertedIndex::Mutable(_) => (points_count, 0),
+            InvertedIndex::Immutable(index) => {
+                let universe = index.point_documents_tokens.len().max(points_count);
+                (universe, universe - points_count)
             }
-        }
-        true
+        };
+
+        let max = posting_lengths
+            .iter()
+            .min()
+            .copied()
+            .unwrap()
+            .min(points_count);
+        let total: usize = posting_lengths.iter().sum();
+        let overlap = total.saturating_sub((posting_lengths.len() - 1) * universe);
+        let min = overlap.saturating_sub(removed).min(max);
+        (min, max)
     }
 
-    fn filter(&self, query: &ParsedQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
-        let postings_opt: Option<Vec<_>> = query
+    /// Estimated `(min, exp, max)` number of points matching `query`
+    fn estimate_matches(&self, query: &ParsedQuery) -> (usize, usize, usize) {
+        let points_count = self.points_count();
+        // Lengths may exceed the count, see `ImmutableInvertedIndex::postings`
+        let posting_lengths: Option<Vec<usize>> = query
             .tokens
             .iter()
-            .map(|&vocab_idx| match vocab_idx {
-                None => None,
-                // if a ParsedQuery token was given an index, then it must exist in the vocabulary
-                // dictionary. Posting list entry can be None but it exists.
-                Some(idx) => self.postings.get(idx as usize).unwrap().as_ref(),
-            })
+            .map(|&vocab_idx| Some(self.posting_len(vocab_idx?)?.min(points_count)))
             .collect();
-        if postings_opt.is_none() {
+        if posting_lengths.is_none() || points_count == 0 {
             // There are unseen tokens -> no matches
-            return Box::new(vec![].into_iter());
+            return (0, 0, 0);
         }
-        let postings = postings_opt.unwrap();
+        let postings = posting_lengths.unwrap();
         if postings.is_empty() {
             // Empty request -> no matches
-            return Box::new(vec![].into_iter());
+            return (0, 0, 0);
         }
-        intersect_postings_iterator(postings)
-    }
+        // Smallest posting is the largest possible cardinality
+        let smallest_posting = postings.iter().min().copied().unwrap();
 
-    fn values_count(&self, point_id: PointOffsetType) -> usize {
-        // Maybe we want number of documents in the future?
-        self.get_doc(point_id).map(|x| x.len()).unwrap_or(0)
+        if postings.len() == 1 {
+            (smallest_posting, smallest_posting, smallest_posting)
+        } else {
+            let expected_frac: f64 = postings
+                .iter()
+                .map(|posting| *posting as f64 / points_count as f64)
+                .product();
+            let exp = (expected_frac * points_count as f64) as usize;
+            let (min, max) = self.intersection_bounds(query);
+            (min, exp.clamp(min, max), max)
+        }
     }
 
-    fn values_is_empty(&self, point_id: PointOffsetType) -> bool {
-        self.get_doc(point_id).map(|x| x.is_empty()).unwrap_or(true)
+    /// Estimate cardinality of a query in conjunctive normal form
+    ///
+    /// The size of each clause, being a union of postings, is estimated as
+    /// `N * (1 - product(1 - len_i / N))`, bounded by `[max(len_i), min(sum(len_i), N)]`.
+    /// Clause estimations are then combined as an intersection.
+    pub fn estimate_cnf_cardinality(
+        &self,
+        query: &CnfQuery,
+        condition: &FieldCondition,
+    ) -> CardinalityEstimation {
+        let points_count = self.points_count();
+        let primary_clause = PrimaryCondition::Condition(condition.clone());
+
+        if query.clauses.is_empty() || points_count == 0 {
+            // Empty request or empty index -> no matches
+            return CardinalityEstimation::exact(0).with_primary_clause(primary_clause);
+        }
+
+        let clause_estimations: Vec<_> = query
+            .clauses
+            .iter()
+            .map(|clause| {
+                let token_estimations: Vec<_> = clause
+                    .iter()
+                    .map(|&token| {
+                        // Unseen tokens match nothing. Lengths may exceed the count, see
+                        // `ImmutableInvertedIndex::postings`.
+                        let posting_len = token.and_then(|token| self.posting_len(token));
+                        CardinalityEstimation::exact(posting_len.unwrap_or(0).min(points_count))
+                            .with_primary_clause(primary_clause.clone())
+                    })
+                    .collect();
+                combine_should_estimations(&token_estimations, points_count)
+            })
+            .collect();
+
+        let estimation = combine_must_estimations(&clause_estimations, points_count);
+        CardinalityEstimation {
+            primary_clauses: vec![primary_clause],
+            ..estimation
+        }
     }
 
-    fn check_match(&self, parsed_query: &ParsedQuery, point_id: PointOffsetType) -> bool {
-        if let Some(doc) = self.get_doc(point_id) {
-            parsed_query.check_match(doc)
-        } else {
-            false
+    /// Length of the posting list of `token`, `None` if it has no postings
+    fn posting_len(&self, token: TokenId) -> Option<usize> {
+        match self {
+            Self::Mutable(index) => index
+                .postings
+                .get(token as usize)?
+                .as_ref()
+                .map(|p| p.len()),
+            Self::Immutable(index) => index
+                .postings
+                .get(token as usize)?
+                .as_ref()
+                .map(|p| p.len()),
         }
     }
 
-    fn get_doc(&self, idx: PointOffsetType) -> Option<&Document> {
-        self.point_to_docs.get(idx as usize)?.as_ref()
+    /// Whether any point in the inclusive range `[lo, hi]` contains `token`
+    ///
+    /// Decompresses at most one chunk of the posting list, instead of iterating it. Removed
+    /// points are not excluded, see [`ImmutableInvertedIndex::postings`].
+    pub fn contains_token_in_range(
+        &self,
+        token: TokenId,
+        lo: PointOffsetType,
+        hi: PointOffsetType,
+    ) -> bool {
+        match self {
+            Self::Mutable(index) => index
+                .postings
+                .get(token as usize)
+                .and_then(Option::as_ref)
+                .is_some_and(|posting| posting.contains_range(lo, hi)),
+            Self::Immutable(index) => index
+                .postings
+                .get(token as usize)
+                .and_then(Option::as_ref)
+                .is_some_and(|posting| posting.contains_range(lo, hi)),
+        }
     }
 
-    fn vocab_with_positngs_len_iter(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
-        self.vocab.iter().filter_map(|(token, &posting_idx)| {
-            if let Some(Some(postings)) = self.postings.get(posting_idx as usize) {
-                Some((token.as_str(), postings.len()))
+    pub fn payload_blocks(
+        &self,
+        threshold: usize,
+        key: PayloadKeyType,
+    ) -> Box<dyn Iterator<Item = PayloadBlockCondition> + '_> {
+        let map_filter_condition = move |(token, postings_len): (&str, usize)| {
+            if postings_len >= threshold {
+                Some(PayloadBlockCondition {
+                    condition: FieldCondition::new_match(key.clone(), Match::new_text(token)),
+                    cardinality: postings_len,
+                })
             } else {
                 None
             }
-        })
+        };
+
+        // It might be very hard to predict possible combinations of conditions,
+        // so we only build it for individual tokens
+        match &self {
+            InvertedIndex::Mutable(index) => Box::new(
+                index
+                    .vocab_with_positngs_len_iter()
+                    .filter_map(map_filter_condition),
+            ),
+            InvertedIndex::Immutable(index) => Box::new(
+                index
+                    .vocab_with_positngs_len_iter()
+                    .filter_map(map_filter_condition),
+            ),
+        }
     }
-}
 
-#[derive(Default)]
-pub struct ImmutableInvertedIndex {
-    postings: Vec<Option<CompressedPostingList>>,
-    vocab: HashMap<String, TokenId>,
-    point_documents_tokens: Vec<Option<usize>>,
-    points_count: usize,
-}
+    /// Distribution of the number of distinct tokens per document, over all indexed points
+    ///
+    /// The immutable index stores document lengths directly, so it doesn't touch any documents.
+    /// Returns all zeros for an empty index.
+    pub fn document_length_stats(&self) -> DocLengthStats {
+        let mut lengths: Vec<usize> = match self {
+            InvertedIndex::Mutable(index) => index
+                .point_to_docs
+                .iter()
+                .flatten()
+                .map(Document::distinct_token_count)
+                .collect(),
+            InvertedIndex::Immutable(index) => index
+                .point_documents_tokens
+                .iter()
+                .flatten()
+                .copied()
+                .collect(),
+        };
+        if lengths.is_empty() {
+            return DocLengthStats::default();
+        }
 
-impl ImmutableInvertedIndex {
-    fn remove_document(&mut self, idx: PointOffsetType) -> bool {
-        if self.values_is_empty(idx) {
-            return false; // Already removed or never actually existed
+        lengths.sort_unstable();
+        let percentile = |p: f64| {
+            let rank = (p * lengths.len() as f64).ceil() as usize;
+            lengths[rank.clamp(1, lengths.len()) - 1]
+        };
+        DocLengthStats {
+            min: lengths[0],
+            max: lengths[lengths.len() - 1],
+            mean: lengths.iter().sum::<usize>() as f64 / lengths.len() as f64,
+            median: percentile(0.5),
+            p95: percentile(0.95),
         }
-        self.point_documents_tokens[idx as usize] = None;
-        self.points_count -= 1;
-        true
     }
 
-    fn filter(&self, query: &ParsedQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
-        let postings_opt: Option<Vec<_>> = query
-            .tokens
-            .iter()
-            .map(|&vocab_idx| match vocab_idx {
-                None => None,
-                // if a ParsedQuery token was given an index, then it must exist in the vocabulary
-                // dictionary. Posting list entry can be None but it exists.
-                Some(idx) => self.postings.get(idx as usize).unwrap().as_ref(),
-            })
-            .collect();
-        if postings_opt.is_none() {
-            // There are unseen tokens -> no matches
-            return Box::new(vec![].into_iter());
-        }
-        let postings = postings_opt.unwrap();
-        if postings.is_empty() {
-            // Empty request -> no matches
-            return Box::new(vec![].into_iter());
+    /// The `n` tokens with the highest document frequency, most frequent first
+    ///
+    /// Ties are broken by token, in ascending order. Document frequencies are posting lengths,
+    /// see [`ImmutableInvertedIndex::postings`].
+    pub fn top_terms(&self, n: usize) -> Vec<(String, usize)> {
+        if n == 0 {
+            return Vec::new();
         }
 
-        // in case of immutable index, deleted documents are still in the postings
-        let filter =
-            move |idx| matches!(self.point_documents_tokens.get(idx as usize), Some(Some(_)));
-        intersect_compressed_postings_iterator(postings, filter)
-    }
+        let vocab: Box<dyn Iterator<Item = (&str, usize)> + '_> = match &self {
+            InvertedIndex::Mutable(index) => Box::new(index.vocab_with_positngs_len_iter()),
+            InvertedIndex::Immutable(index) => Box::new(index.vocab_with_positngs_len_iter()),
+        };
 
-    fn values_is_empty(&self, point_id: PointOffsetType) -> bool {
-        if self.point_documents_tokens.len() <= point_id as usize {
-            return true;
+        // Bounded min-heap, keeps the `n` greatest entries seen so far
+        let mut top = FixedLengthPriorityQueue::new(n);
+        for (token, frequency) in vocab {
+            top.push((frequency, Reverse(token)));
         }
-        self.point_documents_tokens[point_id as usize].is_none()
+        top.into_vec()
+            .into_iter()
+            .map(|(frequency, Reverse(token))| (token.to_string(), frequency))
+            .collect()
     }
 
-    fn values_count(&self, point_id: PointOffsetType) -> usize {
-        if self.point_documents_tokens.len() <= point_id as usize {
-            return 0;
-        }
-        self.point_documents_tokens[point_id as usize].unwrap_or(0)
+    /// `(token, posting_len)` of all non-empty posting lists, shortest first
+    ///
+    /// The front holds the most selective tokens, the back holds near-universal ones. Ties are
+    /// broken by token ID. For posting lengths of an immutable index, see
+    /// [`ImmutableInvertedIndex::postings`].
+    pub fn postings_by_length(&self) -> Vec<(TokenId, usize)> {
+        let posting_lengths: Box<dyn Iterator<Item = Option<usize>> + '_> = match self {
+            InvertedIndex::Mutable(index) => Box::new(
+                index
+                    .postings
+                    .iter()
+                    .map(|posting| posting.as_ref().map(PostingList::len)),
+            ),
+            InvertedIndex::Immutable(index) => Box::new(
+                index
+                    .postings
+                    .iter()
+                    .map(|posting| posting.as_ref().map(CompressedPostingList::len)),
+            ),
+        };
+
+        let mut postings: Vec<_> = posting_lengths
+            .enumerate()
+            .filter_map(|(token, posting_len)| Some((token as TokenId, posting_len?)))
+            .filter(|&(_token, posting_len)| posting_len > 0)
+            .collect();
+        postings.sort_unstable_by_key(|&(token, posting_len)| (posting_len, token));
+        postings
     }
 
-    fn check_match(&self, parsed_query: &ParsedQuery, point_id: PointOffsetType) -> bool {
-        if parsed_query.tokens.contains(&None) {
-            return false;
-        }
-        // check presence of the document
-        if self.values_is_empty(point_id) {
-            return false;
-        }
-        // Check that all tokens are in document
-        parsed_query
-            .tokens
-            .iter()
-            // unwrap crash safety: all tokens exist in the vocabulary if it passes the above check
-            .all(|query_token| {
-                if let Some(posting_list) = &self.postings[query_token.unwrap() as usize] {
-                    posting_list.contains(&point_id)
-                } else {
-                    false
-                }
-            })
+    /// Suggest indexed tokens similar to `term`, e.g. for a "did you mean" hint on unseen tokens
+    ///
+    /// Returns tokens within Levenshtein distance `max_distance` of `term`, with their distance.
+    /// Closest tokens come first, ties are broken by document frequency, most frequent first.
+    pub fn suggest(&self, term: &str, max_distance: u8, limit: usize) -> Vec<(String, u8)> {
+        let (suggestions, _truncated) =
+            self.suggest_with_budget(term, max_distance, limit, ExpansionBudget::default());
+        suggestions
     }
 
-    fn vocab_with_positngs_len_iter(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
-        self.vocab.iter().filter_map(|(token, &posting_idx)| {
-            if let Some(Some(postings)) = self.postings.get(posting_idx as usize) {
-                Some((token.as_str(), postings.len()))
-            } else {
-                None
+    /// Same as [`InvertedIndex::suggest`], but stops scanning the vocabulary once `budget` is
+    /// exhausted
+    ///
+    /// Also returns whether the scan was truncated. The vocabulary is scanned in arbitrary order,
+    /// so truncated suggestions are not necessarily the closest tokens.
+    pub fn suggest_with_budget(
+        &self,
+        term: &str,
+        max_distance: u8,
+        limit: usize,
+        budget: ExpansionBudget,
+    ) -> (Vec<(String, u8)>, bool) {
+        let term: Vec<char> = term.chars().collect();
+        let max_distance = usize::from(max_distance);
+
+        let map_suggestion = |(token, postings_len): (&str, usize)| {
+            // Distance is at least the difference in length, skip tokens that are too far off
+            let token_len = token.chars().count();
+            if postings_len == 0 || token_len.abs_diff(term.len()) > max_distance {
+                return None;
             }
-        })
-    }
-}
+            let distance = bounded_edit_distance(&term, token, max_distance)?;
+            Some((token, distance, postings_len))
+        };
 
-impl From<MutableInvertedIndex> for ImmutableInvertedIndex {
-    fn from(mut index: MutableInvertedIndex) -> Self {
-        let postings: Vec<Option<CompressedPostingList>> = index
-            .postings
+        let vocab: Box<dyn Iterator<Item = (&str, usize)> + '_> = match &self {
+            InvertedIndex::Mutable(index) => Box::new(index.vocab_with_positngs_len_iter()),
+            InvertedIndex::Immutable(index) => Box::new(index.vocab_with_positngs_len_iter()),
+        };
+
+        let mut suggestions = Vec::new();
+        let mut truncated = false;
+        for (scanned, entry) in vocab.enumerate() {
+            if scanned >= budget.max_scanned || suggestions.len() >= budget.max_terms {
+                truncated = true;
+                break;
+            }
+            suggestions.extend(map_suggestion(entry));
+        }
+
+        suggestions.sort_unstable_by(
+            |(token_a, distance_a, len_a), (token_b, distance_b, len_b)| {
+                distance_a
+                    .cmp(distance_b)
+                    .then(len_b.cmp(len_a))
+                    .then(token_a.cmp(token_b))
+            },
+        );
+
+        let suggestions = suggestions
             .into_iter()
-            .map(|x| x.map(CompressedPostingList::new))
+            .take(limit)
+            .map(|(token, distance, _)| (token.to_string(), distance as u8))
             .collect();
-        index.vocab.shrink_to_fit();
+        (suggestions, truncated)
+    }
 
-        ImmutableInvertedIndex {
-            postings,
-            vocab: index.vocab,
-            point_documents_tokens: index
-                .point_to_docs
-                .iter()
-                .map(|doc| doc.as_ref().map(|doc| doc.len()))
-                .collect(),
-            points_count: index.points_count,
+    /// Reserve capacity of posting lists, given the expected number of documents per token
+    ///
+    /// Only has effect on a mutable index, an immutable index can't grow.
+    pub fn reserve_postings(&mut self, hints: HashMap<TokenId, usize>) {
+        match self {
+            InvertedIndex::Mutable(index) => index.reserve_postings(hints),
+            InvertedIndex::Immutable(_) => {}
         }
     }
+
+    pub fn build_index(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+    ) -> OperationResult<()> {
+        self.build_index_filtered(iter, 0)
+    }
+
+    /// Build the index, checking `stopped` every [`BUILD_CANCELLATION_CHECK_INTERVAL`] documents
+    ///
+    /// Returns [`OperationError::Cancelled`] once `stopped` is set. The index is only replaced
+    /// after a complete build, so a cancelled build leaves the previous state untouched.
+    pub fn build_index_cancellable(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+        stopped: &AtomicBool,
+    ) -> OperationResult<()> {
+        let iter = iter.enumerate().map(|(i, document)| {
+            if i % BUILD_CANCELLATION_CHECK_INTERVAL == 0 {
+                check_process_stopped(stopped)?;
+            }
+            document
+        });
+        self.build_index(iter)
+    }
+
+    /// Build the index, but drop tokens that occur in less than `min_df` documents
+    ///
+    /// Document frequencies are only known once all documents are seen, so rare tokens are removed
+    /// from the vocabulary, postings and documents after the build. Queries for them match
+    /// nothing, like queries for unseen tokens.
+    pub fn build_index_filtered(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+        min_df: usize,
+    ) -> OperationResult<()> {
+        self.build_index_impl(iter, min_df, |_| {})
+    }
+
+    /// Build the index, and collect [`TokenStats`] of the indexed documents on the way
+    ///
+    /// Saves a second scan over the index for callers which need token statistics, e.g. to
+    /// precompute IDF.
+    pub fn build_index_collecting(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+    ) -> OperationResult<TokenStats> {
+        let mut stats = TokenStats::new();
+        self.build_index_impl(iter, 0, |document| {
+            for (term_frequency, &token) in document.tokens().iter().dedup_with_count() {
+                let (df, tf) = stats.entry(token).or_default();
+                *df += 1;
+                *tf += term_frequency;
+            }
+        })?;
+        Ok(stats)
+    }
+
+    /// Build the index, calling `on_document` for every document indexed
+    fn build_index_impl(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+        min_df: usize,
+        on_document: impl FnMut(&Document),
+    ) -> OperationResult<()> {
+        let mut index = MutableInvertedIndex::default();
+        match self {
+            InvertedIndex::Mutable(i) => {
+                if let Some(max_vocab) = i.vocab.max_len() {
+                    index.vocab.set_max_len(max_vocab);
+                }
+                // Keep capacity hints given before the build
+                index.posting_capacity_hints = std::mem::take(&mut i.posting_capacity_hints);
+            }
+            InvertedIndex::Immutable(i) => index.vocab = i.initial_vocab()?,
+        }
+        index.build_index_with_vocab(iter, on_document)?;
+        // Every token of a built index occurs in at least one document
+        if min_df > 1 {
+            index.drop_rare_tokens(min_df);
+        }
+
+        self.replace_with(index)
+    }
+
+    /// Replace this index with `index`, converted into an immutable index if this one is
+    ///
+    /// An immutable index keeps its configuration, see [`ImmutableInvertedIndex::replace_with`].
+    pub fn replace_with(&mut self, index: MutableInvertedIndex) -> OperationResult<()> {
+        match self {
+            InvertedIndex::Mutable(i) => {
+                *i = index;
+            }
+            InvertedIndex::Immutable(i) => {
+                let built = ImmutableInvertedIndex::compress(index, i.compression_options)?;
+                i.replace_with(built)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Path of the memory-mapped dictionary this index keeps its vocabulary in, if any
+    #[cfg(feature = "mmap-vocab")]
+    pub fn vocab_path(&self) -> Option<&std::path::Path> {
+        match self {
+            InvertedIndex::Mutable(_) => None,
+            InvertedIndex::Immutable(index) => index.vocab_path.as_deref(),
+        }
+    }
+
+    /// Build the index from batches of `batch_size` documents, to bound peak memory usage
+    ///
+    /// An immutable index is built by merging every batch into the compressed postings as soon
+    /// as it is read, see [`ImmutableInvertedIndex::index_batch`]. So only the compressed index
+    /// and one batch of documents are held at once. A mutable index holds all postings
+    /// uncompressed anyway, it is built at once.
+    ///
+    /// Every point must only be yielded once by `iter`.
+    pub fn build_index_batched(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+        batch_size: usize,
+    ) -> OperationResult<()> {
+        let InvertedIndex::Immutable(index) = self else {
+            return self.build_index(iter);
+        };
+
+        let mut merged = ImmutableInvertedIndex {
+            vocab: index.initial_vocab()?,
+            ..Default::default()
+        };
+        let batch_size = batch_size.max(1);
+        let mut iter = iter.peekable();
+        while iter.peek().is_some() {
+            merged.index_batch(iter.by_ref().take(batch_size))?;
+        }
+        merged.vocab.shrink_to_fit();
+        // Filters are sized for the complete postings, so they are only built once all are merged
+        if index.compression_options.bloom_filter {
+            merged
+                .postings
+                .iter_mut()
+                .flatten()
+                .for_each(CompressedPostingList::build_bloom_filter);
+        }
+
+        index.replace_with(merged)
+    }
+
+    pub fn check_match(&self, parsed_query: &ParsedQuery, point_id: PointOffsetType) -> bool {
+        match self {
+            InvertedIndex::Mutable(index) => index.check_match(parsed_query, point_id),
+            InvertedIndex::Immutable(index) => index.check_match(parsed_query, point_id),
+        }
+    }
+
+    pub fn values_is_empty(&self, point_id: PointOffsetType) -> bool {
+        match self {
+            InvertedIndex::Mutable(index) => index.values_is_empty(point_id),
+            InvertedIndex::Immutable(index) => index.values_is_empty(point_id),
+        }
+    }
+
+    pub fn values_count(&self, point_id: PointOffsetType) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => index.values_count(point_id),
+            InvertedIndex::Immutable(index) => index.values_count(point_id),
+        }
+    }
+
+    pub fn points_count(&self) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => index.points_count,
+            InvertedIndex::Immutable(index) => index.points_count,
+        }
+    }
+
+    /// Hit and miss counts of the posting cache, if this is an immutable index with cache
+    pub fn posting_cache_stats(&self) -> Option<PostingCacheStats> {
+        match self {
+            InvertedIndex::Mutable(_) => None,
+            InvertedIndex::Immutable(index) => index.cache_stats(),
+        }
+    }
+
+    /// Build a query from `tokens`, without adding them to the vocabulary
+    ///
+    /// Unlike [`InvertedIndex::document_from_tokens`], unseen tokens are not assigned an ID, but
+    /// resolved to `None`, so that the query matches nothing.
+    pub fn parse_query(&self, tokens: &BTreeSet<String>) -> ParsedQuery {
+        ParsedQuery::new(tokens.iter().map(|token| self.get_token(token)).collect())
+    }
+
+    /// Number of tokens in the vocabulary
+    pub fn vocabulary_size(&self) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => index.vocab.len(),
+            InvertedIndex::Immutable(index) => index.vocab.len(),
+        }
+    }
+
+    /// Total number of postings over all tokens, see [`ImmutableInvertedIndex::postings`]
+    pub fn postings_count(&self) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => {
+                index.postings.iter().flatten().map(PostingList::len).sum()
+            }
+            InvertedIndex::Immutable(index) => index
+                .postings
+                .iter()
+                .flatten()
+                .map(CompressedPostingList::len)
+                .sum(),
+        }
+    }
+
+    /// Approximate heap memory held by postings, vocabulary and documents, in bytes
+    ///
+    /// The posting cache of an immutable index is not included.
+    pub fn memory_usage(&self) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => {
+                let postings_size: usize = index
+                    .postings
+                    .iter()
+                    .flatten()
+                    .map(PostingList::memory_usage)
+                    .sum();
+                let documents_size: usize = index
+                    .point_to_docs
+                    .iter()
+                    .flatten()
+                    .map(|document| document.tokens().len() * std::mem::size_of::<TokenId>())
+                    .sum();
+                postings_size
+                    + index.postings.capacity() * std::mem::size_of::<Option<PostingList>>()
+                    + documents_size
+                    + index.point_to_docs.capacity() * std::mem::size_of::<Option<Document>>()
+                    + index.vocab.memory_usage()
+            }
+            InvertedIndex::Immutable(index) => {
+                let postings_size: usize = index
+                    .postings
+                    .iter()
+                    .flatten()
+                    .map(CompressedPostingList::memory_usage)
+                    .sum();
+                postings_size
+                    + index.postings.capacity()
+                        * std::mem::size_of::<Option<CompressedPostingList>>()
+                    + index.point_documents_tokens.capacity() * std::mem::size_of::<Option<usize>>()
+                    + index.vocab.memory_usage()
+            }
+        }
+    }
+
+    /// Projected memory usage after compacting the index, see
+    /// [`MutableInvertedIndex::estimate_memory_after_compaction`]
+    ///
+    /// An immutable index is already compacted when it is built, so it returns the current usage.
+    pub fn estimate_memory_after_compaction(&self) -> usize {
+        match self {
+            InvertedIndex::Mutable(index) => index.estimate_memory_after_compaction(),
+            InvertedIndex::Immutable(_) => self.memory_usage(),
+        }
+    }
+
+    pub fn get_token(&self, token: &str) -> Option<TokenId> {
+        match self {
+            InvertedIndex::Mutable(index) => index.vocab.get(token),
+            InvertedIndex::Immutable(index) => index.vocab.get(token),
+        }
+    }
+
+    /// Reverse of [`InvertedIndex::get_token`], get the token string of a token ID
+    pub fn get_token_text(&self, token_id: TokenId) -> Option<&str> {
+        match self {
+            InvertedIndex::Mutable(index) => index.vocab.get_text(token_id),
+            InvertedIndex::Immutable(index) => index.vocab.get_text(token_id),
+        }
+    }
+
+    /// Iterate over the documents of all indexed points, see [`MutableInvertedIndex::documents`]
+    ///
+    /// Only the mutable index keeps documents, the immutable index only stores token counts.
+    pub fn documents(
+        &self,
+    ) -> OperationResult<Box<dyn Iterator<Item = (PointOffsetType, &[TokenId])> + '_>> {
+        match self {
+            InvertedIndex::Mutable(index) => Ok(Box::new(index.documents())),
+            InvertedIndex::Immutable(_index) => Err(OperationError::ImmutableIndex {
+                operation: "iterate documents",
+            }),
+        }
+    }
+
+    /// Build a new index with `normalize` applied to every term, see
+    /// [`MutableInvertedIndex::reindex_with`]
+    ///
+    /// Only the mutable index keeps documents, which are needed to reindex.
+    pub fn reindex_with(&self, normalize: impl Fn(&str) -> String) -> OperationResult<Self> {
+        match self {
+            InvertedIndex::Mutable(index) => {
+                Ok(InvertedIndex::Mutable(index.reindex_with(normalize)))
+            }
+            InvertedIndex::Immutable(_index) => Err(OperationError::ImmutableIndex {
+                operation: "reindex",
+            }),
+        }
+    }
+
+    /// Jaccard similarity of the token sets of two documents
+    ///
+    /// Only the mutable index keeps documents, the immutable index always returns `None`.
+    pub fn document_similarity(&self, a: PointOffsetType, b: PointOffsetType) -> Option<f32> {
+        match self {
+            InvertedIndex::Mutable(index) => index.document_similarity(a, b),
+            InvertedIndex::Immutable(_) => None,
+        }
+    }
+
+    /// Whether enough documents were removed from a mutable index, that rebuilding it is
+    /// worthwhile, see [`MutableInvertedIndex::should_compact`]
+    ///
+    /// An immutable index is rebuilt from storage on load, so it never needs compaction.
+    pub fn should_compact(&self, threshold: f32) -> bool {
+        match self {
+            InvertedIndex::Mutable(index) => index.should_compact(threshold),
+            InvertedIndex::Immutable(_) => false,
+        }
+    }
+
+    /// Whether all tokens of document `a` are also in document `b`
+    ///
+    /// Only the mutable index keeps documents, the immutable index always returns `None`.
+    pub fn is_subset(&self, a: PointOffsetType, b: PointOffsetType) -> Option<bool> {
+        match self {
+            InvertedIndex::Mutable(index) => index.is_subset(a, b),
+            InvertedIndex::Immutable(_) => None,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct MutableInvertedIndex {
+    postings: Vec<Option<PostingList>>,
+    vocab: Vocab,
+    point_to_docs: Vec<Option<Document>>,
+    points_count: usize,
+    /// Slots in `point_to_docs` emptied by removals, and not indexed again since
+    ///
+    /// Slots of points which never had a document are not marked, so they don't count as deleted.
+    deleted: BitVec,
+    /// Capacity to reserve for posting lists, which are not created yet
+    posting_capacity_hints: HashMap<TokenId, usize>,
+}
+
+impl MutableInvertedIndex {
+    /// Assemble an index from its raw components, for tests
+    ///
+    /// - `vocab` maps every token to its ID, IDs must be `0..vocab.len()`
+    /// - `postings` holds sorted point offsets of every token ID
+    /// - `point_to_docs` holds the document of every point offset
+    /// - `points_count` is the number of documents
+    #[cfg(test)]
+    pub fn from_parts(
+        vocab: HashMap<String, TokenId>,
+        postings: Vec<Option<Vec<PointOffsetType>>>,
+        point_to_docs: Vec<Option<Document>>,
+        points_count: usize,
+    ) -> Self {
+        let mut index_vocab = Vocab::default();
+        for (token, token_id) in vocab.iter().sorted_by_key(|(_, &token_id)| token_id) {
+            let inserted_id = index_vocab.get_or_insert(token);
+            debug_assert_eq!(inserted_id, Some(*token_id), "vocab IDs must be dense");
+        }
+        debug_assert!(
+            vocab
+                .values()
+                .all(|&token_id| (token_id as usize) < postings.len()),
+            "vocab IDs must be in range of postings",
+        );
+        debug_assert!(
+            point_to_docs
+                .iter()
+                .flatten()
+                .flat_map(Document::tokens)
+                .all(|&token_id| (token_id as usize) < postings.len()),
+            "document tokens must be in range of postings",
+        );
+        debug_assert_eq!(
+            point_to_docs.iter().flatten().count(),
+            points_count,
+            "points count must match documents",
+        );
+
+        let postings = postings
+            .into_iter()
+            .map(|posting| {
+                let posting = posting?;
+                debug_assert!(
+                    posting.windows(2).all(|pair| pair[0] < pair[1]),
+                    "postings must be sorted",
+                );
+                let mut posting_list = PostingList::default();
+                posting_list.reserve(posting.len());
+                for idx in posting {
+                    posting_list.insert(idx);
+                }
+                Some(posting_list)
+            })
+            .collect();
+
+        Self {
+            postings,
+            vocab: index_vocab,
+            point_to_docs,
+            points_count,
+            deleted: BitVec::new(),
+            posting_capacity_hints: HashMap::new(),
+        }
+    }
+
+    /// Import an index from a pre-built term dictionary, e.g. when migrating from another system
+    ///
+    /// Unlike [`InvertedIndex::build_index`], documents are not tokenized again. Returns an error
+    /// if the parts are inconsistent:
+    /// - `vocab` must map tokens to IDs `0..vocab.len()`, all within range of `postings`
+    /// - every posting must only refer to points whose document contains the token
+    /// - every token of a document must have a posting referring to the point
+    pub fn load_from_postings(
+        vocab: HashMap<String, TokenId>,
+        postings: Vec<Option<PostingList>>,
+        point_to_docs: Vec<Option<Document>>,
+    ) -> OperationResult<Self> {
+        let mut index_vocab = Vocab::default();
+        for (token, token_id) in vocab.into_iter().sorted_by_key(|(_, token_id)| *token_id) {
+            let expected_id = index_vocab.len() as TokenId;
+            if token_id != expected_id {
+                return Err(OperationError::service_error(format!(
+                    "Token {token:?} has ID {token_id}, expected ID {expected_id}",
+                )));
+            }
+            if token_id as usize >= postings.len() {
+                return Err(OperationError::service_error(format!(
+                    "Token {token:?} has ID {token_id}, out of range of {} postings",
+                    postings.len(),
+                )));
+            }
+            index_vocab.get_or_insert(&token);
+        }
+
+        for (idx, document) in point_to_docs.iter().enumerate() {
+            let Some(document) = document else {
+                continue;
+            };
+            for &token_id in document.tokens() {
+                let has_posting = postings
+                    .get(token_id as usize)
+                    .and_then(Option::as_ref)
+                    .is_some_and(|posting| posting.contains(&(idx as PointOffsetType)));
+                if !has_posting {
+                    return Err(OperationError::service_error(format!(
+                        "Token {token_id} of point {idx} is missing from its posting list",
+                    )));
+                }
+            }
+        }
+
+        for (token_id, posting) in postings.iter().enumerate() {
+            let Some(posting) = posting else {
+                continue;
+            };
+            for idx in posting.iter() {
+                let in_document = point_to_docs
+                    .get(idx as usize)
+                    .and_then(Option::as_ref)
+                    .is_some_and(|document| document.check(token_id as TokenId));
+                if !in_document {
+                    return Err(OperationError::service_error(format!(
+                        "Posting list of token {token_id} refers to point {idx}, \
+                         whose document doesn't contain the token",
+                    )));
+                }
+            }
+        }
+
+        let points_count = point_to_docs.iter().flatten().count();
+        let index = Self {
+            postings,
+            vocab: index_vocab,
+            point_to_docs,
+            points_count,
+            deleted: BitVec::new(),
+            posting_capacity_hints: HashMap::new(),
+        };
+        index.validate()?;
+        Ok(index)
+    }
+
+    /// Fraction of document slots, which were emptied by removing documents
+    ///
+    /// Slots of points which never had a document are not counted as deleted.
+    pub fn deleted_ratio(&self) -> f32 {
+        if self.point_to_docs.is_empty() {
+            return 0.0;
+        }
+        self.deleted.count_ones() as f32 / self.point_to_docs.len() as f32
+    }
+
+    /// Mark the document slot of `idx` as emptied by a removal
+    fn mark_deleted(deleted: &mut BitVec, idx: usize) {
+        if deleted.len() <= idx {
+            deleted.resize(idx + 1, false);
+        }
+        deleted.set(idx, true);
+    }
+
+    /// Remove all documents of points for which `keep` returns false
+    ///
+    /// Every posting list of a removed token is filtered once, instead of once per removed
+    /// document. Returns the number of removed documents.
+    pub fn retain(&mut self, keep: impl Fn(PointOffsetType) -> bool) -> usize {
+        let mut touched_tokens = vec![false; self.postings.len()];
+        let mut removed = 0;
+        for (idx, document) in self.point_to_docs.iter_mut().enumerate() {
+            if document.is_none() || keep(idx as PointOffsetType) {
+                continue;
+            }
+            // unwrap safety: checked above
+            for &token in document.take().unwrap().tokens() {
+                touched_tokens[token as usize] = true;
+            }
+            Self::mark_deleted(&mut self.deleted, idx);
+            removed += 1;
+        }
+
+        for (posting, touched) in self.postings.iter_mut().zip(touched_tokens) {
+            if let (Some(posting), true) = (posting, touched) {
+                posting.retain(|idx| matches!(self.point_to_docs.get(idx as usize), Some(Some(_))));
+            }
+        }
+
+        debug_assert!(
+            self.points_count >= removed,
+            "removed documents must be counted"
+        );
+        self.points_count = self.points_count.saturating_sub(removed);
+        removed
+    }
+
+    /// Iterate over the sorted token IDs of every indexed point, e.g. to reindex in place
+    ///
+    /// Tokens can be turned back into terms with [`InvertedIndex::get_token_text`].
+    pub fn documents(&self) -> impl Iterator<Item = (PointOffsetType, &[TokenId])> + '_ {
+        self.point_to_docs
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, document)| {
+                Some((idx as PointOffsetType, document.as_ref()?.tokens()))
+            })
+    }
+
+    /// Build a new index with `normalize` applied to every term, e.g. to migrate to
+    /// case-insensitive matching without reading the source documents again
+    ///
+    /// Postings of terms which collide after normalization are merged, and document token sets
+    /// are recomputed, so a document holds a merged term once, like a document indexed from
+    /// normalized tokens. Terms are normalized in order of their IDs, so IDs stay the same if
+    /// nothing collides.
+    pub fn reindex_with(&self, normalize: impl Fn(&str) -> String) -> MutableInvertedIndex {
+        let mut vocab = Vocab::default();
+        if let Some(max_len) = self.vocab.max_len() {
+            vocab.set_max_len(max_len);
+        }
+        let token_ids: Vec<Option<TokenId>> = (0..self.vocab.len())
+            .map(|token_id| {
+                let text = self.vocab.get_text(token_id as TokenId)?;
+                vocab.get_or_insert(&normalize(text))
+            })
+            .collect();
+
+        let mut postings: Vec<Option<PostingList>> = vec![None; vocab.len()];
+        for (token_id, posting) in self.postings.iter().enumerate() {
+            let (Some(posting), Some(Some(new_token_id))) = (posting, token_ids.get(token_id))
+            else {
+                continue;
+            };
+            let merged = &mut postings[*new_token_id as usize];
+            *merged = Some(match merged {
+                Some(merged) => merged.union(posting),
+                None => posting.clone(),
+            });
+        }
+
+        let mut point_to_docs = vec![None; self.point_to_docs.len()];
+        for (idx, tokens) in self.documents() {
+            let tokens = tokens
+                .iter()
+                .filter_map(|&token_id| token_ids.get(token_id as usize).copied().flatten())
+                .sorted_unstable()
+                .dedup()
+                .collect();
+            point_to_docs[idx as usize] = Some(Document::new(tokens));
+        }
+
+        MutableInvertedIndex {
+            postings,
+            vocab,
+            point_to_docs,
+            points_count: self.points_count,
+            deleted: self.deleted.clone(),
+            posting_capacity_hints: HashMap::new(),
+        }
+    }
+
+    /// Projected heap memory usage in bytes, if the index was compacted, without changing it
+    ///
+    /// Compaction drops empty posting lists and tokens without postings, trailing empty document
+    /// slots, and all spare capacity. Compare with [`InvertedIndex::memory_usage`] to get the
+    /// memory it would reclaim. This is a lower bound, hash maps keep some spare capacity anyway.
+    pub fn estimate_memory_after_compaction(&self) -> usize {
+        let live_postings: Vec<(usize, &PostingList)> = self
+            .postings
+            .iter()
+            .enumerate()
+            .filter_map(|(token_id, posting)| Some((token_id, posting.as_ref()?)))
+            .filter(|(_token_id, posting)| !posting.is_empty())
+            .collect();
+
+        let postings_size: usize = live_postings
+            .iter()
+            .map(|(_token_id, posting)| posting.len() * std::mem::size_of::<PointOffsetType>())
+            .sum::<usize>()
+            + live_postings.len() * std::mem::size_of::<Option<PostingList>>();
+
+        let documents_len = self
+            .point_to_docs
+            .iter()
+            .rposition(Option::is_some)
+            .map_or(0, |last| last + 1);
+        let documents_size: usize = self
+            .point_to_docs
+            .iter()
+            .flatten()
+            .map(|document| document.tokens().len() * std::mem::size_of::<TokenId>())
+            .sum::<usize>()
+            + documents_len * std::mem::size_of::<Option<Document>>();
+
+        let vocab_size: usize = live_postings
+            .iter()
+            .filter_map(|&(token_id, _posting)| self.vocab.get_text(token_id as TokenId))
+            .map(|text| {
+                text.len()
+                    + std::mem::size_of::<Arc<str>>()
+                    + std::mem::size_of::<(Arc<str>, TokenId)>()
+            })
+            .sum();
+
+        postings_size + documents_size + vocab_size
+    }
+
+    /// Whether at least `threshold` of document slots are deleted, so rebuilding is worthwhile
+    pub fn should_compact(&self, threshold: f32) -> bool {
+        self.deleted.any() && self.deleted_ratio() >= threshold
+    }
+
+    /// Reserve capacity of posting lists, given the expected number of documents per token
+    ///
+    /// Existing posting lists are grown right away. For tokens without a posting list, capacity
+    /// is reserved once the posting list is created.
+    pub fn reserve_postings(&mut self, hints: HashMap<TokenId, usize>) {
+        for (token, capacity) in hints {
+            match self.postings.get_mut(token as usize) {
+                Some(Some(posting)) => posting.reserve(capacity),
+                _ => {
+                    self.posting_capacity_hints.insert(token, capacity);
+                }
+            }
+        }
+    }
+
+    fn new_posting(
+        posting_capacity_hints: &mut HashMap<TokenId, usize>,
+        token: TokenId,
+        idx: PointOffsetType,
+    ) -> PostingList {
+        match posting_capacity_hints.remove(&token) {
+            Some(capacity) => PostingList::with_capacity(idx, capacity),
+            None => PostingList::new(idx),
+        }
+    }
+
+    fn build_index(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+    ) -> OperationResult<()> {
+        self.vocab.clear();
+        self.build_index_with_vocab(iter, |_| {})
+    }
+
+    /// Build the index, but keep tokens already in the vocabulary with their IDs
+    ///
+    /// Calls `on_document` for every document, as it is indexed.
+    fn build_index_with_vocab(
+        &mut self,
+        iter: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+        mut on_document: impl FnMut(&Document),
+    ) -> OperationResult<()> {
+        self.points_count = 0;
+        self.deleted.clear();
+        self.postings.clear();
+        self.point_to_docs.clear();
+
+        // update point_to_docs
+        for i in iter {
+            self.points_count += 1;
+            let (idx, tokens) = i?;
+
+            if self.point_to_docs.len() <= idx as usize {
+                self.point_to_docs
+                    .resize_with(idx as usize + 1, Default::default);
+            }
+
+            let (document, _) = InvertedIndex::document_from_tokens_impl(&mut self.vocab, &tokens);
+            on_document(&document);
+            self.point_to_docs[idx as usize] = Some(document);
+        }
+
+        // build postings from point_to_docs
+        // build in order to increase document id
+        for (idx, doc) in self.point_to_docs.iter().enumerate() {
+            if let Some(doc) = doc {
+                for token_idx in doc.tokens() {
+                    if self.postings.len() <= *token_idx as usize {
+                        self.postings
+                            .resize_with(*token_idx as usize + 1, Default::default);
+                    }
+                    let posting = self
+                        .postings
+                        .get_mut(*token_idx as usize)
+                        .expect("posting must exist even if with None");
+                    match posting {
+                        None => {
+                            *posting = Some(Self::new_posting(
+                                &mut self.posting_capacity_hints,
+                                *token_idx,
+                                idx as PointOffsetType,
+                            ))
+                        }
+                        Some(vec) => vec.insert(idx as PointOffsetType),
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove tokens with less than `min_df` postings, see [`InvertedIndex::build_index_filtered`]
+    fn drop_rare_tokens(&mut self, min_df: usize) {
+        let postings = &self.postings;
+        let token_ids = self.vocab.retain(|token_id| {
+            postings
+                .get(token_id as usize)
+                .and_then(Option::as_ref)
+                .map_or(0, PostingList::len)
+                >= min_df
+        });
+
+        let mut postings = std::mem::take(&mut self.postings);
+        self.postings = token_ids
+            .iter()
+            .zip(postings.iter_mut())
+            .filter(|(new_token_id, _)| new_token_id.is_some())
+            .map(|(_, posting)| posting.take())
+            .collect();
+
+        for document in self.point_to_docs.iter_mut().flatten() {
+            let tokens = document
+                .tokens()
+                .iter()
+                .filter_map(|&token_id| token_ids[token_id as usize])
+                .collect();
+            *document = Document::new(tokens);
+        }
+    }
+
+    fn index_document(&mut self, idx: PointOffsetType, document: Document) -> OperationResult<()> {
+        self.points_count += 1;
+        if self.point_to_docs.len() <= idx as usize {
+            self.point_to_docs
+                .resize_with(idx as usize + 1, Default::default);
+        }
+        // Reuses the slot, if it was emptied by a removal
+        if (idx as usize) < self.deleted.len() {
+            self.deleted.set(idx as usize, false);
+        }
+
+        for token_idx in document.tokens() {
+            let token_idx_usize = *token_idx as usize;
+            if self.postings.len() <= token_idx_usize {
+                self.postings
+                    .resize_with(token_idx_usize + 1, Default::default);
+            }
+            let posting = self
+                .postings
+                .get_mut(token_idx_usize)
+                .expect("posting must exist even if with None");
+            match posting {
+                None => {
+                    *posting = Some(Self::new_posting(
+                        &mut self.posting_capacity_hints,
+                        *token_idx,
+                        idx,
+                    ))
+                }
+                Some(vec) => vec.insert(idx),
+            }
+        }
+        self.point_to_docs[idx as usize] = Some(document);
+        Ok(())
+    }
+
+    fn validate(&self) -> OperationResult<()> {
+        for (idx, document) in self.point_to_docs.iter().enumerate() {
+            let Some(document) = document else {
+                continue;
+            };
+            for &token_id in document.tokens() {
+                if !matches!(self.postings.get(token_id as usize), Some(Some(_))) {
+                    return Err(OperationError::service_error(format!(
+                        "Token {token_id} of point {idx} has no posting list",
+                    )));
+                }
+            }
+        }
+
+        let postings = self
+            .postings
+            .iter()
+            .enumerate()
+            .filter_map(|(token_id, posting)| {
+                let posting = posting.as_ref()?;
+                let iter: Box<dyn Iterator<Item = PointOffsetType> + '_> = Box::new(posting.iter());
+                Some((token_id, iter))
+            });
+
+        InvertedIndex::validate_postings(
+            self.points_count,
+            self.point_to_docs.iter().flatten().count(),
+            postings,
+            |idx| self.get_doc(idx).is_some(),
+        )
+    }
+
+    /// Remove `token` from all documents, and drop its posting list
+    ///
+    /// The token keeps its ID in the vocabulary, so queries for it match nothing, like for unseen
+    /// tokens. Documents are kept, even if they don't have any tokens left.
+    ///
+    /// Returns whether the token had a posting list.
+    pub fn remove_token(&mut self, token: &str) -> bool {
+        let Some(token_id) = self.vocab.get(token) else {
+            return false;
+        };
+        let Some(posting) = self
+            .postings
+            .get_mut(token_id as usize)
+            .and_then(Option::take)
+        else {
+            return false;
+        };
+
+        for idx in posting.iter() {
+            if let Some(Some(document)) = self.point_to_docs.get_mut(idx as usize) {
+                document.remove_token(token_id);
+            }
+        }
+        true
+    }
+
+    fn remove_document(&mut self, idx: PointOffsetType) -> bool {
+        if self.point_to_docs.len() <= idx as usize {
+            return false; // Already removed or never actually existed
+        }
+
+        let removed_doc = match std::mem::take(&mut self.point_to_docs[idx as usize]) {
+            Some(doc) => doc,
+            None => return false,
+        };
+
+        debug_assert!(self.points_count > 0, "removed document must be counted");
+        self.points_count = self.points_count.saturating_sub(1);
+        Self::mark_deleted(&mut self.deleted, idx as usize);
+
+        for removed_token in removed_doc.tokens() {
+            // unwrap safety: posting list exists and contains the document id
+            let posting = self.postings.get_mut(*removed_token as usize).unwrap();
+            if let Some(vec) = posting {
+                vec.remove(idx);
+            }
+        }
+        true
+    }
+
+    /// Postings of every token of `query`
+    ///
+    /// Returns `None` if the query can't match anything, because it is empty or has a token
+    /// without postings.
+    fn query_postings(&self, query: &ParsedQuery) -> Option<Vec<&PostingList>> {
+        let postings: Vec<_> = query
+            .tokens
+            .iter()
+            .map(|&token| self.postings.get(token? as usize)?.as_ref())
+            .collect::<Option<_>>()?;
+        (!postings.is_empty()).then_some(postings)
+    }
+
+    fn filter(&self, query: &ParsedQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        let Some(postings) = self.query_postings(query) else {
+            return Box::new(std::iter::empty());
+        };
+        if let [posting] = postings[..] {
+            // Single token -> no need to intersect
+            return Box::new(posting.iter());
+        }
+        intersect_postings_iterator(postings)
+    }
+
+    fn filter_desc(&self, query: &ParsedQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        match self.query_postings(query) {
+            None => Box::new(std::iter::empty()),
+            Some(postings) => intersect_postings_iterator_desc(postings, |_| true),
+        }
+    }
+
+    fn count_matches(&self, query: &ParsedQuery) -> usize {
+        let Some(postings) = self.query_postings(query) else {
+            return 0;
+        };
+        if let [posting] = postings[..] {
+            // Single token -> postings of a mutable index only contain live points
+            return posting.len();
+        }
+        count_postings_intersection(postings)
+    }
+
+    /// Number of distinct tokens of the point
+    ///
+    /// Repeated tokens are counted once, because a document only matches on the presence of a
+    /// token.
+    fn values_count(&self, point_id: PointOffsetType) -> usize {
+        self.get_doc(point_id)
+            .map(|x| x.distinct_token_count())
+            .unwrap_or(0)
+    }
+
+    fn values_is_empty(&self, point_id: PointOffsetType) -> bool {
+        self.get_doc(point_id).map(|x| x.is_empty()).unwrap_or(true)
+    }
+
+    fn check_match(&self, parsed_query: &ParsedQuery, point_id: PointOffsetType) -> bool {
+        if let Some(doc) = self.get_doc(point_id) {
+            parsed_query.check_match(doc)
+        } else {
+            false
+        }
+    }
+
+    fn get_doc(&self, idx: PointOffsetType) -> Option<&Document> {
+        self.point_to_docs.get(idx as usize)?.as_ref()
+    }
+
+    /// Jaccard similarity of the token sets of two documents, `None` if either point is absent
+    pub fn document_similarity(&self, a: PointOffsetType, b: PointOffsetType) -> Option<f32> {
+        Some(self.get_doc(a)?.jaccard_similarity(self.get_doc(b)?))
+    }
+
+    /// Whether all tokens of document `a` are also in document `b`, `None` if either point is
+    /// absent
+    pub fn is_subset(&self, a: PointOffsetType, b: PointOffsetType) -> Option<bool> {
+        Some(self.get_doc(a)?.is_subset_of(self.get_doc(b)?))
+    }
+
+    fn vocab_with_positngs_len_iter(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
+        self.vocab.iter().filter_map(|(token, posting_idx)| {
+            if let Some(Some(postings)) = self.postings.get(posting_idx as usize) {
+                Some((token, postings.len()))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Levenshtein distance between `a` and `b` in characters, `None` if it exceeds `max_distance`
+fn bounded_edit_distance(a: &[char], b: &str, max_distance: usize) -> Option<usize> {
+    let b: Vec<char> = b.chars().collect();
+
+    // Distances from the prefix of `a` processed so far to all prefixes of `b`
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(a_char != b_char);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+        // Distances never decrease along the rows
+        if row.iter().min().is_some_and(|&min| min > max_distance) {
+            return None;
+        }
+    }
+
+    let distance = row[b.len()];
+    (distance <= max_distance).then_some(distance)
+}
+
+#[derive(Default, Clone)]
+pub struct ImmutableInvertedIndex {
+    /// Compressed postings of every token, by token ID
+    ///
+    /// Removing a point only clears its entry in `point_documents_tokens`, its postings are kept
+    /// until the index is built again. So postings and their lengths include removed points, and
+    /// postings must be checked against `point_documents_tokens` to yield live points only.
+    postings: Vec<Option<CompressedPostingList>>,
+    vocab: Vocab,
+    point_documents_tokens: Vec<Option<usize>>,
+    points_count: usize,
+    posting_cache: Option<PostingCache>,
+    /// Options to compress postings with on every build, see
+    /// [`InvertedIndex::with_bloom_filter`]
+    compression_options: CompressionOptions,
+    /// Memory-mapped dictionary to keep the vocabulary in, see
+    /// [`ImmutableInvertedIndex::with_mmap_vocab`]
+    #[cfg(feature = "mmap-vocab")]
+    vocab_path: Option<PathBuf>,
+}
+
+impl ImmutableInvertedIndex {
+    /// Create an empty index, caching up to `capacity` decompressed posting lists
+    pub fn with_cache(capacity: usize) -> Self {
+        Self {
+            posting_cache: Some(PostingCache::new(capacity)),
+            ..Default::default()
+        }
+    }
+
+    /// Create an empty index, which keeps its vocabulary in a memory-mapped dictionary at `path`
+    ///
+    /// Meant for large indexes, where only a few tokens are ever queried. Every build starts
+    /// from the dictionary, if it exists, so token texts are not read into memory on load, see
+    /// [`Vocab::open_dictionary`]. The dictionary is only written again if the documents contain
+    /// tokens which are not in it yet.
+    #[cfg(feature = "mmap-vocab")]
+    pub fn with_mmap_vocab(path: PathBuf) -> Self {
+        Self {
+            vocab_path: Some(path),
+            ..Default::default()
+        }
+    }
+
+    /// Empty vocabulary to build this index with, keeping token IDs of the memory-mapped
+    /// dictionary if there is one
+    fn initial_vocab(&self) -> OperationResult<Vocab> {
+        #[cfg(feature = "mmap-vocab")]
+        let mut vocab = match &self.vocab_path {
+            Some(path) if path.exists() => Vocab::open_dictionary(path)?,
+            _ => Vocab::default(),
+        };
+        #[cfg(not(feature = "mmap-vocab"))]
+        let mut vocab = Vocab::default();
+
+        if let Some(max_vocab) = self.vocab.max_len() {
+            vocab.set_max_len(max_vocab);
+        }
+        Ok(vocab)
+    }
+
+    /// Replace this index with a newly `built` one, but keep its configuration
+    ///
+    /// Postings cached for the old index are dropped. Tokens not in the memory-mapped dictionary
+    /// yet are written into it.
+    fn replace_with(&mut self, mut built: Self) -> OperationResult<()> {
+        built.posting_cache = self
+            .posting_cache
+            .as_ref()
+            .map(PostingCache::capacity)
+            .map(PostingCache::new);
+        built.compression_options = self.compression_options;
+
+        #[cfg(feature = "mmap-vocab")]
+        if let Some(path) = &self.vocab_path {
+            if built.vocab.resident_len() > 0 || !path.exists() {
+                built.vocab.offload(path)?;
+            }
+            built.vocab_path = Some(path.clone());
+        }
+
+        *self = built;
+        Ok(())
+    }
+
+    /// Index a batch of points, which are not in this index yet, see
+    /// [`InvertedIndex::build_index_batched`]
+    ///
+    /// Only postings of tokens in the batch are touched. They are appended to if the batch
+    /// follows all their points, and merged into a newly compressed list otherwise.
+    fn index_batch(
+        &mut self,
+        batch: impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+    ) -> OperationResult<()> {
+        let mut batch_postings: HashMap<TokenId, Vec<PointOffsetType>> = HashMap::new();
+        let mut documents = batch
+            .map(|document| {
+                let (idx, tokens) = document?;
+                let (document, _) =
+                    InvertedIndex::document_from_tokens_impl(&mut self.vocab, &tokens);
+                Ok((idx, document))
+            })
+            .collect::<OperationResult<Vec<_>>>()?;
+        // build postings in order of increasing document id
+        documents.sort_unstable_by_key(|(idx, _)| *idx);
+
+        for (idx, document) in &documents {
+            for &token in document.tokens().iter().dedup() {
+                batch_postings.entry(token).or_default().push(*idx);
+            }
+
+            let idx = *idx as usize;
+            if self.point_documents_tokens.len() <= idx {
+                self.point_documents_tokens.resize(idx + 1, None);
+            }
+            self.point_documents_tokens[idx] = Some(document.distinct_token_count());
+        }
+        self.points_count += documents.len();
+        drop(documents);
+
+        self.postings
+            .resize_with(self.vocab.len(), Default::default);
+        for (token, batch_posting) in batch_postings {
+            let posting = &mut self.postings[token as usize];
+            *posting = Some(Self::merge_posting(posting.take(), &batch_posting)?);
+        }
+
+        Ok(())
+    }
+
+    /// Add sorted `batch_posting` to `posting`, which must not share points with it
+    fn merge_posting(
+        posting: Option<CompressedPostingList>,
+        batch_posting: &[PointOffsetType],
+    ) -> OperationResult<CompressedPostingList> {
+        match posting {
+            Some(mut posting) if batch_posting.first().copied() > posting.last() => {
+                for &idx in batch_posting {
+                    posting.push(idx)?;
+                }
+                Ok(posting)
+            }
+            posting => {
+                // Compress merged postings directly, without collecting them uncompressed first
+                let capacity =
+                    posting.as_ref().map_or(0, CompressedPostingList::len) + batch_posting.len();
+                let mut builder =
+                    CompressedPostingListBuilder::with_capacity(capacity, PostingCompression::Auto);
+                let merged = posting
+                    .iter()
+                    .flat_map(CompressedPostingList::iter)
+                    .merge(batch_posting.iter().copied());
+                for idx in merged {
+                    builder.push(idx)?;
+                }
+                Ok(builder.build())
+            }
+        }
+    }
+
+    /// Hit and miss counts of the posting cache, if enabled
+    pub fn cache_stats(&self) -> Option<PostingCacheStats> {
+        self.posting_cache.as_ref().map(PostingCache::stats)
+    }
+
+    /// Get decompressed postings of `token` through the posting cache
+    fn cached_postings(
+        cache: &PostingCache,
+        token: TokenId,
+        posting: &CompressedPostingList,
+    ) -> Arc<[PointOffsetType]> {
+        cache.get_or_insert_with(token, || posting.iter().collect())
+    }
+
+    /// Postings are only checked to refer to points within range, as they may refer to removed
+    /// points, see [`ImmutableInvertedIndex::postings`].
+    fn validate(&self) -> OperationResult<()> {
+        let postings = self
+            .postings
+            .iter()
+            .enumerate()
+            .filter_map(|(token_id, posting)| {
+                let posting = posting.as_ref()?;
+                let iter: Box<dyn Iterator<Item = PointOffsetType> + '_> = Box::new(posting.iter());
+                Some((token_id, iter))
+            });
+
+        InvertedIndex::validate_postings(
+            self.points_count,
+            self.point_documents_tokens.iter().flatten().count(),
+            postings,
+            |idx| (idx as usize) < self.point_documents_tokens.len(),
+        )
+    }
+
+    fn remove_document(&mut self, idx: PointOffsetType) -> bool {
+        if self.values_is_empty(idx) {
+            return false; // Already removed or never actually existed
+        }
+        self.point_documents_tokens[idx as usize] = None;
+        debug_assert!(self.points_count > 0, "removed document must be counted");
+        self.points_count = self.points_count.saturating_sub(1);
+        true
+    }
+
+    /// Postings of every token of `query`
+    ///
+    /// Returns `None` if the query can't match anything, because it is empty or has a token
+    /// without postings.
+    fn query_postings(&self, query: &ParsedQuery) -> Option<Vec<&CompressedPostingList>> {
+        let postings: Vec<_> = query
+            .tokens
+            .iter()
+            .map(|&token| self.postings.get(token? as usize)?.as_ref())
+            .collect::<Option<_>>()?;
+        (!postings.is_empty()).then_some(postings)
+    }
+
+    fn filter(&self, query: &ParsedQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        let Some(postings) = self.query_postings(query) else {
+            return Box::new(std::iter::empty());
+        };
+
+        // in case of immutable index, deleted documents are still in the postings
+        let filter =
+            move |idx| matches!(self.point_documents_tokens.get(idx as usize), Some(Some(_)));
+
+        if let Some(cache) = &self.posting_cache {
+            // unwrap safety: all tokens are known if all postings were found above
+            let postings = query
+                .tokens
+                .iter()
+                .zip(postings)
+                .map(|(token, posting)| Self::cached_postings(cache, token.unwrap(), posting))
+                .collect();
+            return intersect_decompressed_postings_iterator(postings, filter);
+        }
+
+        if let [posting] = postings[..] {
+            // Single token -> no need to intersect
+            return Box::new(posting.iter().filter(move |idx| filter(*idx)));
+        }
+
+        intersect_compressed_postings_iterator(postings, filter)
+    }
+
+    fn filter_desc(&self, query: &ParsedQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        let Some(postings) = self.query_postings(query) else {
+            return Box::new(std::iter::empty());
+        };
+
+        intersect_postings_iterator_desc(postings, |idx| !self.values_is_empty(idx))
+    }
+
+    fn count_matches(&self, query: &ParsedQuery) -> usize {
+        let Some(postings) = self.query_postings(query) else {
+            return 0;
+        };
+
+        // Even a single posting list can't be counted by its length
+        count_compressed_postings_intersection(postings, |idx| !self.values_is_empty(idx))
+    }
+
+    fn values_is_empty(&self, point_id: PointOffsetType) -> bool {
+        if self.point_documents_tokens.len() <= point_id as usize {
+            return true;
+        }
+        self.point_documents_tokens[point_id as usize].is_none()
+    }
+
+    fn values_count(&self, point_id: PointOffsetType) -> usize {
+        if self.point_documents_tokens.len() <= point_id as usize {
+            return 0;
+        }
+        self.point_documents_tokens[point_id as usize].unwrap_or(0)
+    }
+
+    fn check_match(&self, parsed_query: &ParsedQuery, point_id: PointOffsetType) -> bool {
+        if parsed_query.tokens.is_empty() || parsed_query.tokens.contains(&None) {
+            return false;
+        }
+        // check presence of the document
+        if self.values_is_empty(point_id) {
+            return false;
+        }
+        // Check that all tokens are in document
+        parsed_query
+            .tokens
+            .iter()
+            // unwrap crash safety: all tokens exist in the vocabulary if it passes the above check
+            .all(|query_token| {
+                let token = query_token.unwrap();
+                match (&self.postings[token as usize], &self.posting_cache) {
+                    (Some(posting_list), Some(cache)) => {
+                        Self::cached_postings(cache, token, posting_list)
+                            .binary_search(&point_id)
+                            .is_ok()
+                    }
+                    (Some(posting_list), None) => posting_list.contains(&point_id),
+                    (None, _) => false,
+                }
+            })
+    }
+
+    fn vocab_with_positngs_len_iter(&self) -> impl Iterator<Item = (&str, usize)> + '_ {
+        self.vocab.iter().filter_map(|(token, posting_idx)| {
+            if let Some(Some(postings)) = self.postings.get(posting_idx as usize) {
+                Some((token, postings.len()))
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Compress postings with default [`CompressionOptions`]
+///
+/// Fails if verification is enabled, as in debug builds, and a compressed posting list doesn't
+/// match the original postings.
+impl TryFrom<MutableInvertedIndex> for ImmutableInvertedIndex {
+    type Error = OperationError;
+
+    fn try_from(index: MutableInvertedIndex) -> OperationResult<Self> {
+        Self::compress(index, CompressionOptions::default())
+    }
+}
+
+impl ImmutableInvertedIndex {
+    /// Convert a mutable `index`, compressing its postings with `options`
+    fn compress(
+        mut index: MutableInvertedIndex,
+        options: CompressionOptions,
+    ) -> OperationResult<Self> {
+        let postings: Vec<Option<CompressedPostingList>> = index
+            .postings
+            .into_iter()
+            .map(|posting| {
+                posting
+                    .map(|posting| {
+                        CompressedPostingList::with_options(
+                            posting,
+                            PostingCompression::Auto,
+                            options,
+                        )
+                    })
+                    .transpose()
+            })
+            .collect::<OperationResult<_>>()?;
+        index.vocab.shrink_to_fit();
+
+        Ok(ImmutableInvertedIndex {
+            postings,
+            vocab: index.vocab,
+            point_documents_tokens: index
+                .point_to_docs
+                .iter()
+                .map(|doc| doc.as_ref().map(|doc| doc.distinct_token_count()))
+                .collect(),
+            points_count: index.points_count,
+            posting_cache: None,
+            compression_options: options,
+            #[cfg(feature = "mmap-vocab")]
+            vocab_path: None,
+        })
+    }
+}
+
+/// Decompress postings, and reconstruct documents of points from them
+///
+/// Removed points are dropped from the postings, as a mutable index only keeps postings of
+/// existing points.
+impl From<ImmutableInvertedIndex> for MutableInvertedIndex {
+    fn from(index: ImmutableInvertedIndex) -> Self {
+        let is_live = |idx: PointOffsetType| {
+            matches!(
+                index.point_documents_tokens.get(idx as usize),
+                Some(Some(_))
+            )
+        };
+
+        let mut point_to_docs_tokens: Vec<Option<Vec<TokenId>>> = index
+            .point_documents_tokens
+            .iter()
+            .map(|tokens_count| tokens_count.map(Vec::with_capacity))
+            .collect();
+
+        // Tokens are visited in increasing order, so document tokens come out sorted
+        let postings = index
+            .postings
+            .iter()
+            .enumerate()
+            .map(|(token_id, posting)| {
+                let posting = posting.as_ref()?;
+                let mut posting_list = PostingList::default();
+                for idx in posting.iter().filter(|&idx| is_live(idx)) {
+                    posting_list.insert(idx);
+                    // unwrap safety: only points with a document are live
+                    point_to_docs_tokens[idx as usize]
+                        .as_mut()
+                        .unwrap()
+                        .push(token_id as TokenId);
+                }
+                Some(posting_list)
+            })
+            .collect();
+
+        MutableInvertedIndex {
+            postings,
+            vocab: index.vocab,
+            point_to_docs: point_to_docs_tokens
+                .into_iter()
+                .map(|tokens| tokens.map(Document::new))
+                .collect(),
+            points_count: index.points_count,
+            deleted: BitVec::new(),
+            posting_capacity_hints: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::rstest;
+
+    use super::*;
+
+    fn build_index(documents: &[&[&str]], immutable: bool) -> InvertedIndex {
+        let mut index = InvertedIndex::new(true);
+        for (idx, tokens) in documents.iter().enumerate() {
+            let tokens = tokens.iter().map(|token| token.to_string()).collect();
+            let (document, _) = index.document_from_tokens(&tokens);
+            index
+                .index_document(idx as PointOffsetType, document)
+                .unwrap();
+        }
+
+        if immutable {
+            let InvertedIndex::Mutable(mutable) = index else {
+                unreachable!()
+            };
+            index = InvertedIndex::Immutable(mutable.try_into().unwrap());
+        }
+
+        index
+    }
+
+    fn parse_query(index: &InvertedIndex, tokens: &[&str]) -> ParsedQuery {
+        ParsedQuery {
+            tokens: tokens.iter().map(|token| index.get_token(token)).collect(),
+        }
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_postings_count_and_memory_usage(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a", "b"], &["a"], &["b", "c"]];
+        let index = build_index(documents, immutable);
+        assert_eq!(index.vocabulary_size(), 3);
+        assert_eq!(index.postings_count(), 5);
+        assert!(index.memory_usage() > 0);
+
+        let empty = build_index(&[], immutable);
+        assert_eq!(empty.postings_count(), 0);
+    }
+
+    #[test]
+    fn test_is_appendable() {
+        assert!(InvertedIndex::new(true).is_appendable());
+        assert!(!InvertedIndex::new(false).is_appendable());
+        assert!(!build_index(&[&["a"]], true).is_appendable());
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_estimate_selectivity(#[case] immutable: bool) {
+        let condition = FieldCondition::new_match(
+            crate::json_path::path("text"),
+            Match::new_text("irrelevant"),
+        );
+        let documents: &[&[&str]] = &[
+            &["common", "medium", "rare"],
+            &["common", "medium"],
+            &["common", "medium"],
+            &["common"],
+        ];
+
+        let index = build_index(documents, immutable);
+        for tokens in [
+            &["common"][..],
+            &["common", "medium"],
+            &["medium", "rare"],
+            &["rare", "unseen"],
+            &[],
+        ] {
+            let query = parse_query(&index, tokens);
+            let estimation = index.estimate_cardinality(&query, &condition);
+            let selectivity = index.estimate_selectivity(&query);
+            assert_eq!(selectivity, estimation.exp as f64 / 4.0);
+            assert!((0.0..=1.0).contains(&selectivity));
+        }
+
+        let empty = build_index(&[], immutable);
+        assert_eq!(
+            empty.estimate_selectivity(&parse_query(&empty, &["a"])),
+            0.0
+        );
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_multi_field_scorer(#[case] immutable: bool) {
+        let title: &[&[&str]] = &[&["rust"], &["other"], &["other"], &[]];
+        let body: &[&[&str]] = &[&["other"], &["rust"], &["other"], &["rust", "guide"]];
+
+        let title = build_index(title, immutable);
+        let body = build_index(body, immutable);
+        let scorer = MultiFieldScorer::new(vec![(&title, 2.0), (&body, 1.0)]);
+
+        let queries = vec![
+            parse_query(&title, &["rust"]).into(),
+            parse_query(&body, &["rust"]).into(),
+        ];
+        let scores = scorer.score(queries).unwrap();
+
+        // Point 0 matches in the higher-weighted title, above the body matches of 1 and 3
+        let ranking: Vec<_> = scores.iter().map(|&(idx, _score)| idx).collect();
+        assert_eq!(ranking, vec![0, 1, 3]);
+
+        let title_scores = title.score(&parse_query(&title, &["rust"]).into());
+        let body_scores = body.score(&parse_query(&body, &["rust"]).into());
+        assert_eq!(scores[0].1, 2.0 * title_scores[0].1);
+        assert_eq!(scores[1].1, body_scores[0].1);
+
+        // A field with zero weight doesn't contribute
+        let scorer = MultiFieldScorer::new(vec![(&title, 0.0), (&body, 1.0)]);
+        let queries = vec![
+            parse_query(&title, &["rust"]).into(),
+            parse_query(&body, &["unseen"]).into(),
+        ];
+        assert!(scorer
+            .score(queries)
+            .unwrap()
+            .iter()
+            .all(|&(_idx, score)| score == 0.0));
+
+        // Every field needs a query
+        let queries = vec![parse_query(&title, &["rust"]).into()];
+        assert!(scorer.score(queries).is_err());
+    }
+
+    #[test]
+    fn test_documents() {
+        let documents: &[&[&str]] = &[&["b", "a", "c"], &[], &["c", "d"], &["a"]];
+
+        let mut index = build_index(documents, false);
+        assert!(index.remove_document(3));
+
+        let reconstructed: Vec<(PointOffsetType, BTreeSet<&str>)> = index
+            .documents()
+            .unwrap()
+            .map(|(idx, tokens)| {
+                assert!(tokens.windows(2).all(|pair| pair[0] < pair[1]));
+                let terms = tokens
+                    .iter()
+                    .map(|&token| index.get_token_text(token).unwrap())
+                    .collect();
+                (idx, terms)
+            })
+            .collect();
+        assert_eq!(
+            reconstructed,
+            vec![
+                (0, BTreeSet::from(["a", "b", "c"])),
+                (1, BTreeSet::new()),
+                (2, BTreeSet::from(["c", "d"])),
+            ],
+        );
+
+        let immutable = build_index(documents, true);
+        assert!(matches!(
+            immutable.documents(),
+            Err(OperationError::ImmutableIndex { .. }),
+        ));
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_emptied_index_estimations(#[case] immutable: bool) {
+        let condition = FieldCondition::new_match(
+            crate::json_path::path("text"),
+            Match::new_text("irrelevant"),
+        );
+        let documents: &[&[&str]] = &[&["a", "b", "c"], &["a", "b"], &["a"]];
+
+        let mut index = build_index(documents, immutable);
+        let queries: Vec<_> = [&["a"][..], &["a", "b"], &["a", "b", "c"], &["unseen"], &[]]
+            .into_iter()
+            .map(|tokens| parse_query(&index, tokens))
+            .collect();
+        let cnf_query = CnfQuery {
+            clauses: vec![
+                vec![index.get_token("a"), index.get_token("b")],
+                vec![index.get_token("c")],
+            ],
+        };
+
+        // Partially emptied index never estimates more than the remaining points
+        assert!(index.remove_document(0));
+        assert!(index.remove_document(1));
+        for query in &queries {
+            let estimation = index.estimate_cardinality(query, &condition);
+            assert!(estimation.max <= 1);
+            assert!((0.0..=1.0).contains(&index.estimate_selectivity(query)));
+        }
+        assert!(index.estimate_cnf_cardinality(&cnf_query, &condition).max <= 1);
+
+        assert!(index.remove_document(2));
+        assert_eq!(index.points_count(), 0);
+        for query in &queries {
+            let estimation = index.estimate_cardinality(query, &condition);
+            assert_eq!((estimation.min, estimation.exp, estimation.max), (0, 0, 0));
+            assert_eq!(index.estimate_selectivity(query), 0.0);
+        }
+        let estimation = index.estimate_cnf_cardinality(&cnf_query, &condition);
+        assert_eq!((estimation.min, estimation.exp, estimation.max), (0, 0, 0));
+
+        let scored: ScoredQuery = parse_query(&index, &["a", "b"]).into();
+        assert!(index.score(&scored).is_empty());
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_posting_length_histogram(#[case] immutable: bool) {
+        // Posting lengths: a = 1, b = 2, c = 3, d = 5, e = 10
+        let documents: Vec<Vec<&str>> = (0..10)
+            .map(|idx| {
+                [
+                    (idx < 1, "a"),
+                    (idx < 2, "b"),
+                    (idx < 3, "c"),
+                    (idx < 5, "d"),
+                    (true, "e"),
+                ]
+                .into_iter()
+                .filter_map(|(contains, token)| contains.then_some(token))
+                .collect()
+            })
+            .collect();
+        let documents: Vec<&[&str]> = documents.iter().map(Vec::as_slice).collect();
+
+        let index = build_index(&documents, immutable);
+        assert_eq!(index.posting_length_histogram(&[1, 3, 5]), vec![1, 2, 1, 1]);
+        assert_eq!(index.posting_length_histogram(&[0, 100]), vec![0, 5, 0]);
+        assert_eq!(index.posting_length_histogram(&[]), vec![5]);
+    }
+
+    #[test]
+    fn test_posting_length_histogram_after_removal() {
+        // Token without postings after its only document was removed
+        let mut index = build_index(&[&["a"], &["b"], &["b"]], false);
+        index.remove_document(0);
+        assert_eq!(index.posting_length_histogram(&[0, 1, 2]), vec![1, 0, 1, 0]);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_contains_token_in_range(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a"], &["b"], &["b"], &[], &["a", "b"]];
+
+        let index = build_index(documents, immutable);
+        let a = index.get_token("a").unwrap();
+        let b = index.get_token("b").unwrap();
+
+        assert!(index.contains_token_in_range(a, 0, 0));
+        assert!(!index.contains_token_in_range(a, 1, 3));
+        assert!(index.contains_token_in_range(a, 1, 4));
+        assert!(index.contains_token_in_range(b, 2, 10));
+        assert!(!index.contains_token_in_range(b, 5, 10));
+        // Empty range and unknown token
+        assert!(!index.contains_token_in_range(b, 2, 1));
+        assert!(!index.contains_token_in_range(100, 0, 10));
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_best_primary_token(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[
+            &["common", "medium", "rare"],
+            &["common", "medium"],
+            &["common", "medium"],
+            &["common"],
+        ];
+
+        let index = build_index(documents, immutable);
+        let rare = index.get_token("rare").unwrap();
+        let medium = index.get_token("medium").unwrap();
+
+        let query = parse_query(&index, &["common", "rare", "medium"]);
+        assert_eq!(index.best_primary_token(&query), Some((rare, 1)));
+
+        let query = parse_query(&index, &["common", "medium"]);
+        assert_eq!(index.best_primary_token(&query), Some((medium, 3)));
+
+        let query = parse_query(&index, &["common", "unseen"]);
+        assert_eq!(index.best_primary_token(&query), None);
+        assert_eq!(index.best_primary_token(&parse_query(&index, &[])), None);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_suggest(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[
+            &["quick", "brown"],
+            &["quack", "brown"],
+            &["quack"],
+            &["quirk"],
+            &["fox"],
+        ];
+
+        let index = build_index(documents, immutable);
+
+        // Closer token comes first, even though the other one is more frequent
+        assert_eq!(
+            index.suggest("quikc", 2, 10),
+            vec![("quick".to_string(), 2), ("quirk".to_string(), 2)],
+        );
+        assert_eq!(
+            index.suggest("quicl", 2, 10),
+            vec![
+                ("quick".to_string(), 1),
+                ("quack".to_string(), 2),
+                ("quirk".to_string(), 2),
+            ],
+        );
+
+        // Equal distance is ordered by document frequency, then limited
+        assert_eq!(index.suggest("quxck", 1, 1), vec![("quack".to_string(), 1)],);
+
+        assert!(index.suggest("zebra", 1, 10).is_empty());
+        assert_eq!(index.suggest("fox", 0, 10), vec![("fox".to_string(), 0)]);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_suggest_with_budget(#[case] immutable: bool) {
+        let documents: Vec<Vec<String>> = (0..20).map(|i| vec![format!("term{i:02}")]).collect();
+        let documents: Vec<Vec<&str>> = documents
+            .iter()
+            .map(|tokens| tokens.iter().map(String::as_str).collect())
+            .collect();
+        let documents: Vec<&[&str]> = documents.iter().map(Vec::as_slice).collect();
+
+        let index = build_index(&documents, immutable);
+
+        // Budget not exceeded
+        let budget = ExpansionBudget {
+            max_terms: 20,
+            max_scanned: 20,
+        };
+        let (suggestions, truncated) = index.suggest_with_budget("term1", 1, 100, budget);
+        assert!(!truncated);
+        // "term01" and "term10" to "term19"
+        assert_eq!(suggestions.len(), 11);
+        assert_eq!(index.suggest("term1", 1, 100), suggestions);
+
+        // Too many matching terms
+        let budget = ExpansionBudget {
+            max_terms: 5,
+            max_scanned: 100,
+        };
+        let (suggestions, truncated) = index.suggest_with_budget("term1", 1, 100, budget);
+        assert!(truncated);
+        assert_eq!(suggestions.len(), 5);
+
+        // Too many scanned entries, no matter if they match
+        let budget = ExpansionBudget {
+            max_terms: 100,
+            max_scanned: 3,
+        };
+        let (suggestions, truncated) = index.suggest_with_budget("zzzzzz", 1, 100, budget);
+        assert!(truncated);
+        assert!(suggestions.is_empty());
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_scored_query_boost(#[case] immutable: bool) {
+        let index = build_index(
+            &[
+                &["common", "rare"],
+                &["common"],
+                &["common", "other"],
+                &["other"],
+            ],
+            immutable,
+        );
+
+        // Without boost, the rare token dominates the score
+        let query: ScoredQuery = parse_query(&index, &["rare", "other", "unseen"]).into();
+        let ranked: Vec<_> = index
+            .score(&query)
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(ranked, vec![0, 2, 3]);
+
+        // Boosting the more frequent token puts documents with it first
+        let other = index.get_token("other").unwrap();
+        let query: ScoredQuery =
+            ScoredQuery::from(parse_query(&index, &["rare", "other"])).with_boost(other, 10.0);
+        let ranked: Vec<_> = index
+            .score(&query)
+            .into_iter()
+            .map(|(idx, _)| idx)
+            .collect();
+        assert_eq!(ranked, vec![2, 3, 0]);
+
+        // Unseen tokens contribute nothing
+        let query: ScoredQuery = parse_query(&index, &["unseen"]).into();
+        assert!(index.score(&query).is_empty());
+    }
+
+    #[test]
+    fn test_score_ignores_removed_points() {
+        let documents: &[&[&str]] = &[
+            &["common", "rare"],
+            &["common"],
+            &["common"],
+            &["common", "other"],
+        ];
+        let mut mutable_index = build_index(documents, false);
+        let mut immutable_index = build_index(documents, true);
+        for idx in [1, 2] {
+            assert!(mutable_index.remove_document(idx));
+            assert!(immutable_index.remove_document(idx));
+        }
+
+        // Removed points count neither as points nor in document frequencies
+        let query: ScoredQuery = parse_query(&mutable_index, &["common", "rare"]).into();
+        let expected = mutable_index.score(&query);
+        assert_eq!(
+            expected.iter().map(|(idx, _)| *idx).collect_vec(),
+            vec![0, 3]
+        );
+        assert!(expected.iter().all(|(_, score)| *score > 0.0));
+        assert_eq!(immutable_index.score(&query), expected);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_get_token_text(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["quick", "brown"], &["brown", "fox"]];
+
+        let index = build_index(documents, immutable);
+
+        for token in ["quick", "brown", "fox"] {
+            let token_id = index.get_token(token).unwrap();
+            assert_eq!(index.get_token_text(token_id), Some(token));
+        }
+
+        // Non-existent token ID
+        assert_eq!(index.get_token_text(3), None);
+    }
+
+    #[test]
+    fn test_document_similarity() {
+        let documents: &[&[&str]] = &[
+            &["quick", "brown", "fox"],
+            &["fox", "brown", "quick", "quick"],
+            &["lazy", "dog"],
+            &["quick", "red", "fox", "jumps"],
+        ];
+        let index = build_index(documents, false);
+        let InvertedIndex::Mutable(index) = &index else {
+            unreachable!();
+        };
+
+        // Identical token sets, repeated tokens are counted once
+        assert_eq!(index.document_similarity(0, 1), Some(1.0));
+        // Disjoint
+        assert_eq!(index.document_similarity(0, 2), Some(0.0));
+        // Partial overlap: {quick, fox} of {quick, brown, fox, red, jumps}
+        assert_eq!(index.document_similarity(0, 3), Some(2.0 / 5.0));
+        assert_eq!(index.document_similarity(3, 0), Some(2.0 / 5.0));
+        // Absent point
+        assert_eq!(index.document_similarity(0, 4), None);
+        assert_eq!(index.document_similarity(4, 0), None);
+    }
+
+    #[test]
+    fn test_is_subset() {
+        let documents: &[&[&str]] = &[
+            &["red", "car"],
+            &["red", "fast", "car"],
+            &["car", "red", "red"],
+            &["blue", "car"],
+        ];
+        let index = build_index(documents, false);
+        let InvertedIndex::Mutable(index) = &index else {
+            unreachable!();
+        };
+
+        // Strict subset, but not the other way around
+        assert_eq!(index.is_subset(0, 1), Some(true));
+        assert_eq!(index.is_subset(1, 0), Some(false));
+        // Equal token sets, repeated tokens are counted once
+        assert_eq!(index.is_subset(0, 2), Some(true));
+        assert_eq!(index.is_subset(2, 0), Some(true));
+        assert_eq!(index.is_subset(0, 0), Some(true));
+        // Overlapping, but not a subset
+        assert_eq!(index.is_subset(3, 1), Some(false));
+        // Absent point
+        assert_eq!(index.is_subset(0, 4), None);
+        assert_eq!(index.is_subset(4, 0), None);
+
+        // Empty document is a subset of anything
+        let empty = Document::new(vec![]);
+        assert!(empty.is_subset_of(&Document::new(vec![1, 2])));
+        assert!(!Document::new(vec![1, 1]).is_subset_of(&empty));
+    }
+
+    #[test]
+    fn test_indexed_points() {
+        let mut index = InvertedIndex::new(true);
+        let offsets = [0, 3, 4, 10, 42];
+        for idx in offsets {
+            let (document, _) = index.document_from_tokens(&BTreeSet::from(["token".to_string()]));
+            index.index_document(idx, document).unwrap();
+        }
+        assert_eq!(index.indexed_points().collect::<Vec<_>>(), offsets);
+
+        index.remove_document(4);
+        let expected = [0, 3, 10, 42];
+        assert_eq!(index.indexed_points().collect::<Vec<_>>(), expected);
+
+        let InvertedIndex::Mutable(mutable) = index else {
+            unreachable!()
+        };
+        let index = InvertedIndex::Immutable(mutable.try_into().unwrap());
+        assert_eq!(index.indexed_points().collect::<Vec<_>>(), expected);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_filter_exists(#[case] immutable: bool) {
+        let mut index = InvertedIndex::new(true);
+        let offsets = [2, 5, 6, 20, 100];
+        for (i, idx) in offsets.into_iter().enumerate() {
+            // Point 6 has a value without tokens
+            let tokens = if idx == 6 {
+                BTreeSet::new()
+            } else {
+                BTreeSet::from([format!("token{i}")])
+            };
+            let (document, _) = index.document_from_tokens(&tokens);
+            index.index_document(idx, document).unwrap();
+        }
+        index.remove_document(20);
+        let expected = vec![2, 5, 6, 100];
+
+        if immutable {
+            let InvertedIndex::Mutable(mutable) = index else {
+                unreachable!()
+            };
+            index = InvertedIndex::Immutable(mutable.try_into().unwrap());
+        }
+
+        assert_eq!(index.filter_exists().collect::<Vec<_>>(), expected);
+        let estimation = index.estimate_exists_cardinality();
+        assert_eq!(estimation.min, expected.len());
+        assert_eq!(estimation.exp, expected.len());
+        assert_eq!(estimation.max, expected.len());
+    }
+
+    #[test]
+    fn test_max_vocab() {
+        let mut index = InvertedIndex::new(true).with_max_vocab(3);
+        for idx in 0..10 {
+            let tokens = BTreeSet::from(["common".to_string(), format!("unique{idx}")]);
+            let (document, _) = index.document_from_tokens(&tokens);
+            index.index_document(idx, document).unwrap();
+        }
+
+        // Vocabulary stopped growing, "common" and first two unique tokens fit in
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!()
+        };
+        assert_eq!(mutable.vocab.len(), 3);
+        assert_eq!(index.dropped_token_count(), 8);
+
+        // Existing tokens still match
+        let query = parse_query(&index, &["common"]);
+        assert_eq!(index.filter(&query).count(), 10);
+        let query = parse_query(&index, &["unique1"]);
+        assert_eq!(index.filter(&query).collect::<Vec<_>>(), vec![1]);
+
+        // Dropped tokens behave like unseen tokens
+        let query = parse_query(&index, &["unique5"]);
+        assert_eq!(query.tokens, vec![None]);
+        assert_eq!(index.filter(&query).count(), 0);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_build_index_filtered(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[
+            &["common", "medium", "rare0"],
+            &["common", "medium"],
+            &["common", "rare2"],
+            &["common", "rare3"],
+        ];
+
+        let mut index = InvertedIndex::new(!immutable);
+        let iter = documents.iter().enumerate().map(|(idx, tokens)| {
+            let tokens = tokens.iter().map(|token| token.to_string()).collect();
+            Ok((idx as PointOffsetType, tokens))
+        });
+        index.build_index_filtered(iter, 2).unwrap();
+        index.validate().unwrap();
+
+        // Rare tokens are gone
+        for token in ["rare0", "rare2", "rare3"] {
+            assert_eq!(index.get_token(token), None);
+        }
+        assert_eq!(index.values_count(0), 2);
+        assert_eq!(index.values_count(2), 1);
+
+        // Frequent tokens remain queryable
+        let query = parse_query(&index, &["common"]);
+        assert_eq!(index.filter(&query).count(), 4);
+        let query = parse_query(&index, &["common", "medium"]);
+        assert_eq!(index.filter(&query).collect::<Vec<_>>(), vec![0, 1]);
+        let token = index.get_token("medium").unwrap();
+        assert_eq!(index.get_token_text(token), Some("medium"));
+    }
+
+    #[test]
+    fn test_build_index_batched() {
+        let documents: Vec<BTreeSet<String>> = (0..50)
+            .map(|idx| {
+                [format!("mod3_{}", idx % 3), format!("mod7_{}", idx % 7)]
+                    .into_iter()
+                    .chain((idx % 5 == 0).then(|| "common".to_string()))
+                    .chain((idx == 42).then(|| "rare".to_string()))
+                    .collect()
+            })
+            .collect();
+        // Skip a point, so that it has no document
+        let documents_iter = || {
+            documents
+                .iter()
+                .enumerate()
+                .filter(|(idx, _)| *idx != 13)
+                .map(|(idx, tokens)| Ok((idx as PointOffsetType, tokens.clone())))
+        };
+        let queries: &[&[&str]] = &[
+            &["mod3_0"],
+            &["mod3_1", "mod7_2"],
+            &["common"],
+            &["common", "mod3_2"],
+            &["rare"],
+            &["unseen"],
+        ];
+
+        let mut monolithic = InvertedIndex::new(false);
+        monolithic.build_index(documents_iter()).unwrap();
+
+        for batch_size in [1, 7, 16, 100] {
+            // Held memory only grows by the indexed documents, not by the number of batches
+            let mut index = ImmutableInvertedIndex::default();
+            let mut iter = documents_iter().peekable();
+            let mut indexed = Vec::new();
+            while iter.peek().is_some() {
+                let batch = iter.by_ref().take(batch_size).collect_vec();
+                indexed.extend(
+                    batch
+                        .iter()
+                        .map(|document| document.as_ref().unwrap().clone()),
+                );
+                index.index_batch(batch.into_iter()).unwrap();
+
+                let max_idx = indexed.iter().map(|(idx, _)| *idx).max().unwrap();
+                let postings_count: usize = indexed.iter().map(|(_, tokens)| tokens.len()).sum();
+                assert_eq!(index.postings.len(), index.vocab.len());
+                assert_eq!(index.point_documents_tokens.len(), max_idx as usize + 1);
+                assert_eq!(index.points_count, indexed.len());
+                let postings = InvertedIndex::Immutable(index.clone()).postings_count();
+                assert_eq!(postings, postings_count);
+            }
+
+            let mut batched = InvertedIndex::new(false);
+            batched
+                .build_index_batched(documents_iter(), batch_size)
+                .unwrap();
+            batched.validate().unwrap();
+
+            assert_eq!(batched.points_count(), monolithic.points_count());
+            for idx in 0..documents.len() as PointOffsetType {
+                assert_eq!(batched.values_count(idx), monolithic.values_count(idx));
+            }
+            for tokens in queries {
+                let expected = monolithic
+                    .filter(&parse_query(&monolithic, tokens))
+                    .collect_vec();
+                let query = parse_query(&batched, tokens);
+                assert_eq!(batched.filter(&query).collect_vec(), expected, "{tokens:?}");
+                for idx in 0..documents.len() as PointOffsetType {
+                    assert_eq!(
+                        batched.check_match(&query, idx),
+                        monolithic.check_match(&parse_query(&monolithic, tokens), idx),
+                    );
+                }
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_build_index_cancellable(#[case] immutable: bool) {
+        /// Documents which set `stopped` once document `stop_at` is reached
+        fn documents_iter(
+            stopped: &AtomicBool,
+            stop_at: usize,
+        ) -> impl Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>> + '_
+        {
+            (0..10 * BUILD_CANCELLATION_CHECK_INTERVAL).map(move |idx| {
+                if idx == stop_at {
+                    stopped.store(true, std::sync::atomic::Ordering::Relaxed);
+                }
+                let tokens = BTreeSet::from([format!("token{}", idx % 10), "all".to_string()]);
+                Ok((idx as PointOffsetType, tokens))
+            })
+        }
+
+        // Cancelled build of an empty index leaves it empty
+        let mut index = InvertedIndex::new(!immutable);
+        let stopped = AtomicBool::new(false);
+        let result = index.build_index_cancellable(documents_iter(&stopped, 2_500), &stopped);
+        assert!(matches!(result, Err(OperationError::Cancelled { .. })));
+        assert_eq!(index.points_count(), 0);
+        assert_eq!(index.get_token("all"), None);
+        index.validate().unwrap();
+
+        // Cancelled rebuild keeps the previous state
+        let mut index = build_index(&[&["a", "b"], &["b"]], immutable);
+        let stopped = AtomicBool::new(false);
+        let result = index.build_index_cancellable(documents_iter(&stopped, 2_500), &stopped);
+        assert!(matches!(result, Err(OperationError::Cancelled { .. })));
+        assert_eq!(index.points_count(), 2);
+        assert_eq!(index.get_token("all"), None);
+        let query = parse_query(&index, &["b"]);
+        assert_eq!(index.filter(&query).collect_vec(), vec![0, 1]);
+        index.validate().unwrap();
+
+        // Uncancelled build completes
+        let stopped = AtomicBool::new(false);
+        index
+            .build_index_cancellable(documents_iter(&stopped, usize::MAX), &stopped)
+            .unwrap();
+        assert_eq!(index.points_count(), 10 * BUILD_CANCELLATION_CHECK_INTERVAL);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_clone(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a", "b"], &["b", "c"], &["a", "c"], &["c"]];
+        let queries: &[&[&str]] = &[&["a"], &["b"], &["c"], &["a", "c"], &["d"]];
+
+        let index = build_index(documents, immutable);
+        let mut clone = index.clone();
+
+        for tokens in queries {
+            let query = parse_query(&index, tokens);
+            assert_eq!(
+                clone.filter(&query).collect::<Vec<_>>(),
+                index.filter(&query).collect::<Vec<_>>(),
+            );
+        }
+
+        // Changes to the clone don't affect the original
+        clone.remove_document(0);
+        let (document, _) = clone.document_from_tokens(&BTreeSet::from(["d".to_string()]));
+        if !immutable {
+            clone.index_document(4, document).unwrap();
+        }
+        assert_eq!(index.get_token("d"), None);
+        let query = parse_query(&index, &["a"]);
+        assert_eq!(index.filter(&query).collect::<Vec<_>>(), vec![0, 2]);
+        assert_eq!(clone.filter(&query).collect::<Vec<_>>(), vec![2]);
+        assert_eq!(index.points_count(), 4);
+    }
+
+    #[test]
+    fn test_immutable_to_mutable() {
+        let documents: &[&[&str]] = &[&["a", "b"], &["b", "c"], &[], &["a", "c", "d"], &["d"]];
+        let queries: &[&[&str]] = &[&["a"], &["b"], &["c"], &["a", "c"], &["d"], &["e"]];
+
+        let mut original = build_index(documents, false);
+        original.remove_document(4);
+
+        let InvertedIndex::Mutable(mutable) = original.clone() else {
+            unreachable!()
+        };
+        let immutable = ImmutableInvertedIndex::try_from(mutable).unwrap();
+        let mut round_trip = InvertedIndex::Mutable(MutableInvertedIndex::from(immutable));
+        round_trip.validate().unwrap();
+
+        assert_eq!(round_trip.points_count(), original.points_count());
+        for idx in 0..documents.len() as PointOffsetType {
+            assert_eq!(round_trip.values_count(idx), original.values_count(idx));
+            assert_eq!(
+                round_trip.values_is_empty(idx),
+                original.values_is_empty(idx)
+            );
+        }
+        for tokens in queries {
+            let query = parse_query(&original, tokens);
+            assert_eq!(
+                round_trip.filter(&query).collect::<Vec<_>>(),
+                original.filter(&query).collect::<Vec<_>>(),
+            );
+            for idx in 0..documents.len() as PointOffsetType {
+                assert_eq!(
+                    round_trip.check_match(&query, idx),
+                    original.check_match(&query, idx),
+                );
+            }
+        }
+
+        // Appendable again
+        let (document, _) = round_trip.document_from_tokens(&BTreeSet::from(["e".to_string()]));
+        round_trip.index_document(5, document).unwrap();
+        let query = parse_query(&round_trip, &["e"]);
+        assert_eq!(round_trip.filter(&query).collect::<Vec<_>>(), vec![5]);
+    }
+
+    #[test]
+    fn test_from_parts() {
+        let documents: &[&[&str]] = &[&["a", "b"], &["b", "c"], &[], &["a", "c", "d"]];
+        let built = build_index(documents, false);
+
+        let vocab: HashMap<String, TokenId> = ["a", "b", "c", "d"]
+            .into_iter()
+            .map(|token| (token.to_string(), built.get_token(token).unwrap()))
+            .collect();
+        let mut postings = vec![Some(vec![]); vocab.len()];
+        let mut point_to_docs = vec![];
+        for (idx, tokens) in documents.iter().enumerate() {
+            let token_ids: Vec<_> = tokens.iter().map(|token| vocab[*token]).collect();
+            for &token_id in &token_ids {
+                postings[token_id as usize]
+                    .as_mut()
+                    .unwrap()
+                    .push(idx as PointOffsetType);
+            }
+            point_to_docs.push(Some(Document::new(token_ids)));
+        }
+        let from_parts = InvertedIndex::Mutable(MutableInvertedIndex::from_parts(
+            vocab,
+            postings,
+            point_to_docs,
+            documents.len(),
+        ));
+
+        assert_eq!(from_parts.points_count(), built.points_count());
+        for query in [
+            &["a"][..],
+            &["b", "c"],
+            &["a", "c", "d"],
+            &["e"],
+            &["a", "b", "c"],
+        ] {
+            let query_built = parse_query(&built, query);
+            let query_from_parts = parse_query(&from_parts, query);
+            assert_eq!(query_built.tokens, query_from_parts.tokens);
+            assert_eq!(
+                from_parts.filter(&query_from_parts).collect::<Vec<_>>(),
+                built.filter(&query_built).collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_should_compact() {
+        let documents: Vec<Vec<String>> = (0..10).map(|idx| vec![format!("token{idx}")]).collect();
+        let documents: Vec<Vec<&str>> = documents
+            .iter()
+            .map(|tokens| tokens.iter().map(String::as_str).collect())
+            .collect();
+        let documents: Vec<&[&str]> = documents.iter().map(Vec::as_slice).collect();
+        let mut index = build_index(&documents, false);
+
+        assert!(!index.should_compact(0.0));
+        for idx in 0..3 {
+            assert!(index.remove_document(idx));
+        }
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!();
+        };
+        assert_eq!(mutable.deleted_ratio(), 0.3);
+        assert!(index.should_compact(0.3));
+        assert!(!index.should_compact(0.4));
+
+        // Indexing a removed point again reuses its slot
+        let (document, _) = index.document_from_tokens(&BTreeSet::from(["token0".to_string()]));
+        index.index_document(0, document).unwrap();
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!();
+        };
+        assert_eq!(mutable.deleted_ratio(), 0.2);
+        assert!(!index.should_compact(0.3));
+
+        // Holes of points without a document are not deleted slots
+        let (document, _) = index.document_from_tokens(&BTreeSet::from(["token0".to_string()]));
+        index.index_document(19, document).unwrap();
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!();
+        };
+        assert_eq!(mutable.deleted_ratio(), 0.1);
+
+        // Indexing a point into a hole doesn't reuse a deleted slot
+        let (document, _) = index.document_from_tokens(&BTreeSet::from(["token0".to_string()]));
+        index.index_document(15, document).unwrap();
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!();
+        };
+        assert_eq!(mutable.deleted_ratio(), 0.1);
+
+        // Rebuilding reclaims deleted slots
+        let points = documents.iter().enumerate().map(|(idx, tokens)| {
+            let tokens = tokens.iter().map(|token| token.to_string()).collect();
+            Ok((idx as PointOffsetType, tokens))
+        });
+        index.build_index(points).unwrap();
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!();
+        };
+        assert_eq!(mutable.deleted_ratio(), 0.0);
+        assert!(!index.should_compact(0.0));
+
+        assert!(!build_index(&documents, true).should_compact(0.0));
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_export_sparse(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["b", "a"], &["c"], &["a", "c", "d"], &["b"]];
+        let mut index = build_index(documents, immutable);
+        index.remove_document(3);
+
+        let expected: BTreeSet<_> = documents[..3]
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, tokens)| {
+                tokens
+                    .iter()
+                    .map(move |token| (token.to_string(), idx as PointOffsetType))
+            })
+            .collect();
+
+        let exported: Vec<_> = index.export_sparse().collect();
+        assert!(exported.windows(2).all(|pair| pair[0] < pair[1]));
+        let exported: BTreeSet<_> = exported
+            .into_iter()
+            .map(|(token, idx)| (index.get_token_text(token).unwrap().to_string(), idx))
+            .collect();
+        assert_eq!(exported, expected);
+    }
+
+    #[test]
+    fn test_check_match_merge() {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..1000 {
+            let mut random_tokens = |max_len: usize| {
+                let len = rng.gen_range(0..=max_len);
+                (0..len)
+                    .map(|_| rng.gen_range(0..20))
+                    .collect::<Vec<TokenId>>()
+            };
+            let document = Document::new(random_tokens(30));
+            let mut query_tokens: Vec<_> = random_tokens(8).into_iter().map(Some).collect();
+            if rng.gen_bool(0.1) {
+                query_tokens.push(None);
+            }
+            let query = ParsedQuery::new(query_tokens);
+
+            let expected = query.check_match_binary_search(&document);
+            assert_eq!(query.check_match_merge(&document), expected);
+            assert_eq!(query.check_match(&document), expected);
+        }
+
+        // Repeated tokens in documents and unsorted queries are supported by `check_match`
+        let document = Document::new(vec![3, 1, 1, 2]);
+        let query = ParsedQuery {
+            tokens: vec![Some(2), Some(1), Some(1)],
+        };
+        assert!(query.check_match(&document));
+        assert!(ParsedQuery::new(query.tokens).check_match_merge(&document));
+    }
+
+    #[test]
+    fn test_retain() {
+        let documents: &[&[&str]] = &[&["a", "b"], &["a"], &["b", "c"], &["a", "c"], &["c"]];
+        let mut index = build_index(documents, false);
+        let InvertedIndex::Mutable(mutable) = &mut index else {
+            unreachable!();
+        };
+
+        let live = [0, 3, 4, 42];
+        assert_eq!(mutable.retain(|idx| live.contains(&idx)), 2);
+        // Retaining the same set again is a no-op
+        assert_eq!(mutable.retain(|idx| live.contains(&idx)), 0);
+
+        assert_eq!(index.points_count(), 3);
+        assert_eq!(index.indexed_points().collect::<Vec<_>>(), vec![0, 3, 4]);
+        index.validate().unwrap();
+
+        let filter = |tokens: &[&str]| {
+            let query = parse_query(&index, tokens);
+            index.filter(&query).collect::<Vec<_>>()
+        };
+        assert_eq!(filter(&["a"]), vec![0, 3]);
+        assert_eq!(filter(&["b"]), vec![0]);
+        assert_eq!(filter(&["c"]), vec![3, 4]);
+        assert_eq!(filter(&["a", "c"]), vec![3]);
+
+        let mut index = build_index(documents, true);
+        assert_eq!(index.retain(|idx| live.contains(&idx)), 2);
+        assert_eq!(index.retain(|idx| live.contains(&idx)), 0);
+        assert_eq!(index.points_count(), 3);
+        let query = parse_query(&index, &["a"]);
+        assert_eq!(index.filter(&query).collect::<Vec<_>>(), vec![0, 3]);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_double_remove(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a"], &["a", "b"]];
+        let mut index = build_index(documents, immutable);
+
+        assert!(index.remove_document(0));
+        assert!(!index.remove_document(0));
+        assert_eq!(index.points_count(), 1);
+
+        assert!(index.remove_document(1));
+        assert!(!index.remove_document(1));
+        // Never indexed
+        assert!(!index.remove_document(2));
+        assert_eq!(index.points_count(), 0);
+        index.validate().unwrap();
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_build_index_collecting(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a", "b"], &["a"], &["b", "c"], &["a", "c"], &[]];
+        let mut index = InvertedIndex::new(!immutable);
+        let points = documents.iter().enumerate().map(|(idx, tokens)| {
+            let tokens = tokens.iter().map(|token| token.to_string()).collect();
+            Ok((idx as PointOffsetType, tokens))
+        });
+        let stats = index.build_index_collecting(points).unwrap();
+
+        // Count over the input documents, tokens of a document are a set
+        let mut expected: HashMap<&str, (usize, usize)> = HashMap::new();
+        for &token in documents.iter().flat_map(|tokens| tokens.iter()) {
+            let (df, tf) = expected.entry(token).or_default();
+            *df += 1;
+            *tf += 1;
+        }
+
+        let stats: HashMap<&str, (usize, usize)> = stats
+            .into_iter()
+            .map(|(token, stats)| (index.get_token_text(token).unwrap(), stats))
+            .collect();
+        assert_eq!(stats, expected);
+        assert_eq!(stats["a"], (3, 3));
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_empty_query(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a", "b"], &["a"], &[]];
+        let index = build_index(documents, immutable);
+        let query = ParsedQuery { tokens: vec![] };
+
+        assert_eq!(index.filter(&query).count(), 0);
+        assert_eq!(index.count_matches(&query), 0);
+        for idx in 0..documents.len() as PointOffsetType {
+            assert!(!index.check_match(&query, idx));
+        }
+    }
+
+    #[test]
+    fn test_empty_query_document_match() {
+        let document = Document::new(vec![1, 2]);
+        let query = ParsedQuery::new(vec![]);
+        assert!(!query.check_match(&document));
+        assert!(!query.check_match_merge(&document));
+        assert!(!query.check_match(&Document::new(vec![])));
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_top_terms(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[
+            &["a", "b", "c", "d"],
+            &["a", "c", "d"],
+            &["a", "b", "e"],
+            &["d"],
+        ];
+        let index = build_index(documents, immutable);
+
+        // Ties of `b` and `c`, and of `a` and `d`, are ordered by token
+        let expected: [(&str, usize); 5] = [("a", 3), ("d", 3), ("b", 2), ("c", 2), ("e", 1)];
+        for n in 0..=expected.len() + 1 {
+            let top = index.top_terms(n);
+            let top: Vec<_> = top
+                .iter()
+                .map(|(token, df)| (token.as_str(), *df))
+                .collect();
+            assert_eq!(top, expected[..n.min(expected.len())]);
+        }
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_postings_by_length(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a", "b", "c"], &["a", "c"], &["a"], &["d"]];
+
+        let index = build_index(&documents[..3], immutable);
+        let by_length: Vec<_> = index
+            .postings_by_length()
+            .into_iter()
+            .map(|(token, posting_len)| (index.get_token_text(token).unwrap(), posting_len))
+            .collect();
+        assert_eq!(by_length, vec![("b", 1), ("c", 2), ("a", 3)]);
+    }
+
+    #[test]
+    fn test_postings_by_length_after_removal() {
+        let documents: &[&[&str]] = &[&["a", "b", "c"], &["a", "c"], &["a"], &["d"]];
+
+        // Postings emptied by removals, and removed tokens, are excluded
+        let mut index = build_index(documents, false);
+        assert!(index.remove_document(3));
+        assert!(index.remove_token("b").unwrap());
+        let expected = vec![
+            (index.get_token("c").unwrap(), 2),
+            (index.get_token("a").unwrap(), 3),
+        ];
+        assert_eq!(index.postings_by_length(), expected);
+    }
+
+    #[test]
+    fn test_load_from_postings() {
+        let vocab = || HashMap::from([("a".to_string(), 0), ("b".to_string(), 1)]);
+        let posting = |points: &[PointOffsetType]| {
+            let mut posting = PostingList::default();
+            for &idx in points {
+                posting.insert(idx);
+            }
+            Some(posting)
+        };
+        let documents = || {
+            vec![
+                Some(Document::new(vec![0, 1])),
+                None,
+                Some(Document::new(vec![1])),
+            ]
+        };
+
+        let index = MutableInvertedIndex::load_from_postings(
+            vocab(),
+            vec![posting(&[0]), posting(&[0, 2])],
+            documents(),
+        )
+        .unwrap();
+        let index = InvertedIndex::Mutable(index);
+        index.validate().unwrap();
+        assert_eq!(index.points_count(), 2);
+        let filter = |tokens: &[&str]| index.filter(&parse_query(&index, tokens)).collect_vec();
+        assert_eq!(filter(&["a"]), vec![0]);
+        assert_eq!(filter(&["b"]), vec![0, 2]);
+        assert_eq!(filter(&["a", "b"]), vec![0]);
+        assert!(index.check_match(&parse_query(&index, &["b"]), 2));
+
+        // Vocabulary IDs must be dense
+        let sparse_vocab = HashMap::from([("a".to_string(), 0), ("b".to_string(), 2)]);
+        let result = MutableInvertedIndex::load_from_postings(
+            sparse_vocab,
+            vec![posting(&[0]), posting(&[0, 2]), None],
+            documents(),
+        );
+        assert!(result.is_err());
+
+        // Vocabulary IDs must be in range of postings
+        let result =
+            MutableInvertedIndex::load_from_postings(vocab(), vec![posting(&[0])], documents());
+        assert!(result.is_err());
+
+        // Document token missing from its posting list
+        let result = MutableInvertedIndex::load_from_postings(
+            vocab(),
+            vec![posting(&[0]), posting(&[0])],
+            documents(),
+        );
+        let err = result.err().unwrap().to_string();
+        assert!(err.contains("Token 1 of point 2"), "{err}");
+
+        // Posting referring to a point without the token
+        let result = MutableInvertedIndex::load_from_postings(
+            vocab(),
+            vec![posting(&[0, 2]), posting(&[0, 2])],
+            documents(),
+        );
+        let err = result.err().unwrap().to_string();
+        assert!(err.contains("token 0 refers to point 2"), "{err}");
+
+        // Posting referring to a point without a document
+        let result = MutableInvertedIndex::load_from_postings(
+            vocab(),
+            vec![posting(&[0]), posting(&[0, 1, 2])],
+            documents(),
+        );
+        assert!(result.is_err());
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_intersection_bounds(#[case] immutable: bool) {
+        use rand::rngs::StdRng;
+        use rand::{Rng, SeedableRng};
+
+        let mut rng = StdRng::seed_from_u64(42);
+        let tokens = ["a", "b", "c", "d"];
+        // Frequent tokens, so that postings must overlap
+        let documents: Vec<Vec<&str>> = (0..200)
+            .map(|_| {
+                tokens
+                    .iter()
+                    .copied()
+                    .filter(|_| rng.gen_bool(0.85))
+                    .collect()
+            })
+            .collect();
+        let documents: Vec<&[&str]> = documents.iter().map(Vec::as_slice).collect();
+
+        let queries: &[&[&str]] = &[
+            &["a"],
+            &["a", "b"],
+            &["a", "b", "c"],
+            &["a", "b", "c", "d"],
+            &["b", "d"],
+        ];
+
+        let mut index = build_index(&documents, false);
+        for idx in (0..200).step_by(7) {
+            index.remove_document(idx);
+        }
+        if immutable {
+            let InvertedIndex::Mutable(mutable) = index else {
+                unreachable!()
+            };
+            index = InvertedIndex::Immutable(mutable.try_into().unwrap());
+        }
+
+        for tokens in queries {
+            let query = parse_query(&index, tokens);
+            let (min, max) = index.intersection_bounds(&query);
+            let real = index.filter(&query).count();
+            assert!(
+                min <= real && real <= max,
+                "{tokens:?}: {min} <= {real} <= {max}"
+            );
+            assert!(min > 0, "{tokens:?}: lower bound should not be trivial");
+        }
+
+        let query = parse_query(&index, &["a", "unseen"]);
+        assert_eq!(index.intersection_bounds(&query), (0, 0));
+        assert_eq!(index.intersection_bounds(&parse_query(&index, &[])), (0, 0));
+
+        // Every document contains all tokens, so bounds are exact
+        let documents: Vec<&[&str]> = (0..50).map(|_| &["x", "y", "z"][..]).collect();
+        let index = build_index(&documents, immutable);
+        let query = parse_query(&index, &["x", "y", "z"]);
+        assert_eq!(index.intersection_bounds(&query), (50, 50));
+    }
+
+    #[test]
+    fn test_estimate_memory_after_compaction() {
+        let tokens: Vec<String> = (0..100).map(|i| format!("token{i}")).collect();
+        let documents: Vec<Vec<&str>> = (0..1000)
+            .map(|idx| vec![tokens[idx % 100].as_str(), tokens[idx % 7].as_str()])
+            .collect();
+        let documents: Vec<&[&str]> = documents.iter().map(Vec::as_slice).collect();
+
+        let mut index = build_index(&documents, false);
+        let before = index.memory_usage();
+        let estimate_before = index.estimate_memory_after_compaction();
+        assert!(estimate_before <= before);
+
+        // Remove most documents, including all documents of some tokens and the trailing ones
+        let is_live = |idx: usize| idx % 100 < 30 && idx < 900;
+        for idx in (0..documents.len()).filter(|&idx| !is_live(idx)) {
+            index.remove_document(idx as PointOffsetType);
+        }
+        let estimate = index.estimate_memory_after_compaction();
+        assert_eq!(
+            index.memory_usage(),
+            before,
+            "removing doesn't release memory"
+        );
+        assert!(estimate < estimate_before);
+        assert!(estimate < before);
+
+        // Rebuilding from live documents is a real compaction, which can't be smaller
+        let mut compacted = InvertedIndex::new(true);
+        for idx in (0..documents.len()).filter(|&idx| is_live(idx)) {
+            let tokens = documents[idx]
+                .iter()
+                .map(|token| token.to_string())
+                .collect();
+            let (document, _) = compacted.document_from_tokens(&tokens);
+            compacted
+                .index_document(idx as PointOffsetType, document)
+                .unwrap();
+        }
+        assert_eq!(compacted.points_count(), index.points_count());
+        assert!(estimate <= compacted.memory_usage());
+
+        let immutable = build_index(&documents, true);
+        assert_eq!(
+            immutable.estimate_memory_after_compaction(),
+            immutable.memory_usage(),
+        );
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_document_length_stats(#[case] immutable: bool) {
+        // Document `i` has `i + 1` distinct tokens
+        let tokens: Vec<String> = (0..20).map(|i| format!("t{i}")).collect();
+        let documents: Vec<Vec<&str>> = (0..20)
+            .map(|len| tokens[..=len].iter().map(String::as_str).collect())
+            .collect();
+        let documents: Vec<&[&str]> = documents.iter().map(Vec::as_slice).collect();
+
+        let index = build_index(&documents, immutable);
+        let stats = index.document_length_stats();
+        assert_eq!(
+            stats,
+            DocLengthStats {
+                min: 1,
+                max: 20,
+                mean: 10.5,
+                median: 10,
+                p95: 19,
+            },
+        );
+
+        // Removed documents are not counted
+        let mut index = index;
+        for idx in 10..20 {
+            index.remove_document(idx);
+        }
+        let stats = index.document_length_stats();
+        assert_eq!((stats.min, stats.max, stats.median), (1, 10, 5));
+        assert_eq!(stats.mean, 5.5);
+
+        // Single document, and empty index
+        let index = build_index(&[&["a", "b", "c"]], immutable);
+        let stats = index.document_length_stats();
+        assert_eq!(
+            (stats.min, stats.max, stats.median, stats.p95),
+            (3, 3, 3, 3)
+        );
+        assert_eq!(
+            build_index(&[], immutable).document_length_stats(),
+            DocLengthStats::default(),
+        );
+    }
+
+    #[cfg(feature = "mmap-vocab")]
+    #[test]
+    fn test_mmap_vocab() {
+        fn build_with_mmap_vocab(path: &std::path::Path, documents: &[&[&str]]) -> InvertedIndex {
+            let mut index =
+                InvertedIndex::Immutable(ImmutableInvertedIndex::with_mmap_vocab(path.into()));
+            let documents = documents.iter().enumerate().map(|(idx, tokens)| {
+                let tokens = tokens.iter().map(|token| token.to_string()).collect();
+                Ok((idx as PointOffsetType, tokens))
+            });
+            index.build_index(documents).unwrap();
+            index
+        }
+
+        fn resident_len(index: &InvertedIndex) -> usize {
+            let InvertedIndex::Immutable(index) = index else {
+                unreachable!()
+            };
+            index.vocab.resident_len()
+        }
+
+        let dir = tempfile::Builder::new().prefix("vocab").tempdir().unwrap();
+        let path = dir.path().join("vocab.dat");
+        let documents: &[&[&str]] = &[&["a", "b", "c"], &["b", "c"], &["c", "d"]];
+
+        let expected = build_index(documents, true);
+        let index = build_with_mmap_vocab(&path, documents);
+
+        // Token texts are not resident
+        assert!(path.exists());
+        assert_eq!(resident_len(&index), 0);
+        assert_eq!(index.vocabulary_size(), 4);
+
+        let queries: &[&[&str]] = &[&["c"], &["b", "c"], &["a", "d"], &["unseen"]];
+        for tokens in queries {
+            let query = parse_query(&index, tokens);
+            assert_eq!(
+                index.filter(&query).collect_vec(),
+                expected
+                    .filter(&parse_query(&expected, tokens))
+                    .collect_vec(),
+            );
+            for idx in 0..documents.len() as PointOffsetType {
+                assert_eq!(
+                    index.check_match(&query, idx),
+                    expected.check_match(&parse_query(&expected, tokens), idx),
+                );
+            }
+        }
+        assert_eq!(
+            index.get_token_text(index.get_token("d").unwrap()),
+            Some("d")
+        );
+
+        // Loading again starts from the dictionary and keeps its token IDs
+        let reloaded = build_with_mmap_vocab(&path, documents);
+        assert_eq!(resident_len(&reloaded), 0);
+        for token in ["a", "b", "c", "d"] {
+            assert_eq!(reloaded.get_token(token), index.get_token(token));
+        }
+
+        // New tokens are added to the dictionary
+        let extended = build_with_mmap_vocab(&path, &[&["a", "e"]]);
+        assert_eq!(resident_len(&extended), 0);
+        assert_eq!(extended.get_token("a"), index.get_token("a"));
+        assert_eq!(extended.get_token("e"), Some(4));
+        assert_eq!(Vocab::open_dictionary(&path).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_bloom_filter() {
+        let documents: &[&[&str]] = &[&["a", "b", "c"], &["b", "c"], &["c", "d"], &["a", "d"]];
+        let build = |index: &mut InvertedIndex, batch_size: Option<usize>| {
+            let documents = documents.iter().enumerate().map(|(idx, tokens)| {
+                let tokens = tokens.iter().map(|token| token.to_string()).collect();
+                Ok((idx as PointOffsetType, tokens))
+            });
+            match batch_size {
+                None => index.build_index(documents).unwrap(),
+                Some(batch_size) => index.build_index_batched(documents, batch_size).unwrap(),
+            }
+        };
+
+        let queries: &[&[&str]] = &[&["a"], &["b", "c"], &["a", "d"], &["c", "d"]];
+        for batch_size in [None, Some(1), Some(3)] {
+            let mut expected = InvertedIndex::new(false);
+            build(&mut expected, batch_size);
+            let mut index = InvertedIndex::new(false).with_bloom_filter();
+            build(&mut index, batch_size);
+
+            // Every posting list has a filter of a single word
+            let filters_size = 4 * std::mem::size_of::<u64>();
+            assert_eq!(index.memory_usage(), expected.memory_usage() + filters_size);
+
+            for tokens in queries {
+                let query = parse_query(&index, tokens);
+                let expected_query = parse_query(&expected, tokens);
+                for idx in 0..documents.len() as PointOffsetType + 1 {
+                    assert_eq!(
+                        index.check_match(&query, idx),
+                        expected.check_match(&expected_query, idx),
+                    );
+                }
+            }
+
+            // The option is kept across builds
+            build(&mut index, batch_size);
+            assert_eq!(index.memory_usage(), expected.memory_usage() + filters_size);
+        }
+    }
+
+    #[test]
+    fn test_reindex_with() {
+        let documents: &[&[&str]] = &[
+            &["Apple", "pie"],
+            &["apple", "juice"],
+            &["APPLE", "apple", "Pie"],
+            &["banana"],
+        ];
+        let InvertedIndex::Mutable(mut mutable) = build_index(documents, false) else {
+            unreachable!()
+        };
+        mutable.remove_document(3);
+
+        let case_sensitive = InvertedIndex::Mutable(mutable.clone());
+        let query = parse_query(&case_sensitive, &["Apple"]);
+        assert_eq!(case_sensitive.filter(&query).collect_vec(), vec![0]);
+        let query = parse_query(&case_sensitive, &["apple"]);
+        assert_eq!(case_sensitive.filter(&query).collect_vec(), vec![1, 2]);
+
+        let index = InvertedIndex::Mutable(mutable.reindex_with(|term| term.to_lowercase()));
+        assert_eq!(index.vocabulary_size(), 4);
+        assert_eq!(index.points_count(), 3);
+        assert!(index.get_token("Apple").is_none());
+        // Queries are normalized the same way as the indexed terms
+        for term in ["Apple", "apple", "APPLE"] {
+            let query = parse_query(&index, &[&term.to_lowercase()]);
+            assert_eq!(index.filter(&query).collect_vec(), vec![0, 1, 2]);
+        }
+        let query = parse_query(&index, &["apple", "pie"]);
+        assert_eq!(index.filter(&query).collect_vec(), vec![0, 2]);
+        let query = parse_query(&index, &["banana"]);
+        assert_eq!(index.filter(&query).count(), 0);
+
+        // Document token sets are recomputed, colliding terms are held once
+        let documents = index
+            .documents()
+            .unwrap()
+            .map(|(idx, tokens)| {
+                let terms = tokens
+                    .iter()
+                    .map(|&token| index.get_token_text(token).unwrap())
+                    .sorted()
+                    .collect_vec();
+                (idx, terms)
+            })
+            .collect_vec();
+        assert_eq!(
+            documents,
+            vec![
+                (0, vec!["apple", "pie"]),
+                (1, vec!["apple", "juice"]),
+                (2, vec!["apple", "pie"]),
+            ],
+        );
+        let apple = index.get_token("apple").unwrap();
+        assert!(index.check_match(&ParsedQuery::new(vec![Some(apple)]), 2));
+        index.validate().unwrap();
+
+        // Without collisions, token IDs don't change
+        let identity = mutable.reindex_with(|term| term.to_string());
+        for term in ["Apple", "apple", "APPLE", "pie", "Pie", "juice"] {
+            assert_eq!(identity.vocab.get(term), mutable.vocab.get(term));
+        }
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_validate(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a", "b"], &["b", "c"], &["a"]];
+
+        let mut index = build_index(documents, immutable);
+        index.validate().unwrap();
+        index.remove_document(1);
+        index.validate().unwrap();
+    }
+
+    #[test]
+    fn test_validate_inconsistent() {
+        let documents: &[&[&str]] = &[&["a", "b"], &["b", "c"], &["a"]];
+
+        // Document refers to token without posting list
+        let mut index = build_index(documents, false);
+        let InvertedIndex::Mutable(mutable) = &mut index else {
+            unreachable!()
+        };
+        mutable.postings[2] = None;
+        let err = index.validate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Token 2 of point 1 has no posting list"));
+
+        // Posting refers to point without document
+        let mut index = build_index(documents, false);
+        let InvertedIndex::Mutable(mutable) = &mut index else {
+            unreachable!()
+        };
+        mutable.point_to_docs[2] = None;
+        mutable.points_count -= 1;
+        let err = index.validate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Posting list of token 0 refers to point 2 without document"));
+
+        // Points count is off
+        let mut index = build_index(documents, true);
+        let InvertedIndex::Immutable(immutable) = &mut index else {
+            unreachable!()
+        };
+        immutable.points_count += 1;
+        let err = index.validate().unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Points count 4 doesn't match 3 documents"));
+    }
+
+    #[test]
+    fn test_remove_token() {
+        let documents: &[&[&str]] = &[&["quick", "brown"], &["brown", "fox"], &["lazy"]];
+        let mut index = build_index(documents, false);
+
+        assert!(index.remove_token("brown").unwrap());
+        // Already removed, or never existed
+        assert!(!index.remove_token("brown").unwrap());
+        assert!(!index.remove_token("dog").unwrap());
+
+        let query = parse_query(&index, &["brown"]);
+        assert_eq!(index.filter(&query).count(), 0);
+        assert!(!index.check_match(&query, 0));
+
+        // Other tokens of the same documents still match
+        let query = parse_query(&index, &["quick"]);
+        assert_eq!(index.filter(&query).collect::<Vec<_>>(), vec![0]);
+        let query = parse_query(&index, &["fox"]);
+        assert_eq!(index.filter(&query).collect::<Vec<_>>(), vec![1]);
+        assert_eq!(index.values_count(1), 1);
+        index.validate().unwrap();
+
+        let mut index = build_index(documents, true);
+        assert!(matches!(
+            index.remove_token("brown"),
+            Err(OperationError::ImmutableIndex { .. }),
+        ));
+    }
+
+    #[test]
+    fn test_index_document_immutable() {
+        let mut index = build_index(&[&["a"]], true);
+        let (document, _) = index.document_from_tokens(&BTreeSet::from(["a".to_string()]));
+        let err = index.index_document(1, document).unwrap_err();
+        assert!(
+            matches!(err, OperationError::ImmutableIndex { operation } if operation == "add values"),
+        );
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_filter_single_token(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[&["a", "b"], &["b"], &["a", "c"], &["c"], &["a"]];
+
+        let mut index = build_index(documents, immutable);
+        index.remove_document(4);
+
+        for token in ["a", "b", "c"] {
+            let expected: Vec<_> = documents
+                .iter()
+                .enumerate()
+                .filter(|(idx, tokens)| *idx != 4 && tokens.contains(&token))
+                .map(|(idx, _)| idx as PointOffsetType)
+                .collect();
+            let query = parse_query(&index, &[token]);
+            assert_eq!(index.filter(&query).collect::<Vec<_>>(), expected);
+        }
+
+        // Unseen token -> no matches
+        let query = parse_query(&index, &["unseen"]);
+        assert_eq!(index.filter(&query).count(), 0);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_filter_desc(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[
+            &["a", "b", "c"],
+            &["b"],
+            &["a", "c"],
+            &["a", "b"],
+            &["c"],
+            &["a", "b", "c"],
+            &["a"],
+        ];
+        let queries: &[&[&str]] = &[&[], &["a"], &["unseen"], &["a", "b"], &["a", "b", "c"]];
+
+        let mut index = build_index(documents, immutable);
+        index.remove_document(5);
+
+        for tokens in queries {
+            let query = parse_query(&index, tokens);
+            let mut expected: Vec<_> = index.filter(&query).collect();
+            expected.reverse();
+            assert_eq!(
+                index.filter_desc(&query).collect::<Vec<_>>(),
+                expected,
+                "query {tokens:?}, immutable: {immutable}",
+            );
+        }
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_count_matches(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[
+            &["a", "b", "c"],
+            &["b"],
+            &["a", "c"],
+            &["a", "b"],
+            &["c"],
+            &["a", "b", "c"],
+        ];
+        let queries: &[&[&str]] = &[
+            &[],
+            &["a"],
+            &["unseen"],
+            &["a", "b"],
+            &["a", "c"],
+            &["a", "b", "c"],
+            &["a", "unseen"],
+        ];
+
+        let mut index = build_index(documents, immutable);
+        index.remove_document(5);
+
+        for tokens in queries {
+            let query = parse_query(&index, tokens);
+            assert_eq!(
+                index.count_matches(&query),
+                index.filter(&query).count(),
+                "query {tokens:?}, immutable: {immutable}",
+            );
+        }
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_filter_cnf(#[case] immutable: bool) {
+        let documents: &[&[&str]] = &[
+            &["a", "x"],
+            &["b", "x"],
+            &["c", "y"],
+            &["a", "b", "y"],
+            &["c"],
+        ];
+        let cnf_query = |index: &InvertedIndex, clauses: &[&[&str]]| CnfQuery {
+            clauses: clauses
+                .iter()
+                .map(|clause| parse_query(index, clause).tokens)
+                .collect(),
+        };
+
+        let index = build_index(documents, immutable);
+
+        let query = cnf_query(&index, &[&["a", "b"], &["x", "y"]]);
+        assert_eq!(index.filter_cnf(&query).collect_vec(), vec![0, 1, 3]);
+
+        let query = cnf_query(&index, &[&["a", "c"], &["y"]]);
+        assert_eq!(index.filter_cnf(&query).collect_vec(), vec![2, 3]);
+
+        // Unseen tokens contribute nothing to their clause
+        let query = cnf_query(&index, &[&["unseen", "x"]]);
+        assert_eq!(index.filter_cnf(&query).collect_vec(), vec![0, 1]);
+        let query = cnf_query(&index, &[&["unseen"], &["x"]]);
+        assert_eq!(index.filter_cnf(&query).count(), 0);
+        assert_eq!(index.filter_cnf(&cnf_query(&index, &[])).count(), 0);
+    }
+
+    #[test]
+    fn test_estimate_cnf_cardinality() {
+        let condition = FieldCondition::new_match(
+            crate::json_path::path("text"),
+            Match::new_text("irrelevant"),
+        );
+
+        let brute_force_union = |index: &InvertedIndex, tokens: &[&str]| {
+            let mut union = BTreeSet::new();
+            for token in tokens {
+                union.extend(index.filter(&parse_query(index, &[*token])));
+            }
+            union.len()
+        };
+
+        // Disjoint postings: a0..a9 cover 10 documents each
+        let disjoint_tokens: Vec<_> = (0..10).map(|i| format!("a{i}")).collect();
+        let documents: Vec<Vec<&str>> = (0..100)
+            .map(|idx| vec![disjoint_tokens[idx / 10].as_str()])
+            .collect();
+        let documents: Vec<&[&str]> = documents.iter().map(Vec::as_slice).collect();
+        let index = build_index(&documents, false);
+
+        let tokens = ["a0", "a1", "a2"];
+        let query = CnfQuery {
+            clauses: vec![tokens.iter().map(|t| index.get_token(t)).collect()],
+        };
+        let estimation = index.estimate_cnf_cardinality(&query, &condition);
+        let real = brute_force_union(&index, &tokens);
+        assert_eq!(real, 30);
+        assert!(estimation.min <= real && real <= estimation.max);
+        assert_eq!((estimation.min, estimation.max), (10, 30));
+        assert!(estimation.exp.abs_diff(real) <= 3);
+
+        // Heavily overlapping postings: every document contains all tokens
+        let documents: Vec<&[&str]> = (0..100).map(|_| &["x", "y", "z"][..]).collect();
+        let index = build_index(&documents, true);
+
+        let tokens = ["x", "y", "z"];
+        let query = CnfQuery {
+            clauses: vec![tokens.iter().map(|t| index.get_token(t)).collect()],
+        };
+        let estimation = index.estimate_cnf_cardinality(&query, &condition);
+        let real = brute_force_union(&index, &tokens);
+        assert_eq!(real, 100);
+        assert!(estimation.min <= real && real <= estimation.max);
+        assert_eq!(estimation.exp, 100);
+
+        // Clauses are intersected, unseen tokens match nothing
+        let query = CnfQuery {
+            clauses: vec![
+                vec![index.get_token("x"), index.get_token("unseen")],
+                vec![index.get_token("unseen")],
+            ],
+        };
+        let estimation = index.estimate_cnf_cardinality(&query, &condition);
+        assert_eq!((estimation.min, estimation.exp, estimation.max), (0, 0, 0));
+    }
+
+    #[test]
+    fn test_values_count_distinct_tokens() {
+        let document = Document::new(vec![3, 1, 3, 2, 1, 3]);
+        assert_eq!(document.len(), 6);
+        assert_eq!(document.distinct_token_count(), 3);
+        assert_eq!(Document::new(vec![]).distinct_token_count(), 0);
+
+        let mut index = InvertedIndex::new(true);
+        for token in ["a", "b", "c", "d"] {
+            index.document_from_tokens(&BTreeSet::from([token.to_string()]));
+        }
+        index.index_document(0, document).unwrap();
+        assert_eq!(index.values_count(0), 3);
+        assert_eq!(index.values_count(1), 0);
+
+        let InvertedIndex::Mutable(mutable) = index else {
+            unreachable!()
+        };
+        let index = InvertedIndex::Immutable(mutable.try_into().unwrap());
+        assert_eq!(index.values_count(0), 3);
+    }
+
+    #[test]
+    fn test_document_term_frequency() {
+        // "the cat saw the other cat the end"
+        let document = Document::new(vec![0, 1, 2, 0, 3, 1, 0, 4]);
+        assert_eq!(document.tokens(), &[0, 0, 0, 1, 1, 2, 3, 4]);
+        assert_eq!(document.len(), 8);
+        assert_eq!(document.distinct_token_count(), 5);
+
+        assert_eq!(document.term_frequency(0), 3);
+        assert_eq!(document.term_frequency(1), 2);
+        assert_eq!(document.term_frequency(4), 1);
+        assert_eq!(document.term_frequency(5), 0);
+        assert!(document.check(0));
+
+        // Removing a token drops all of its occurrences
+        let mut document = document;
+        document.remove_token(0);
+        assert_eq!(document.term_frequency(0), 0);
+        assert_eq!(document.len(), 5);
+    }
+
+    #[rstest]
+    #[case(false)]
+    #[case(true)]
+    fn test_parse_query_keeps_vocabulary(#[case] immutable: bool) {
+        let tokens = |tokens: &[&str]| -> BTreeSet<String> {
+            tokens.iter().map(|token| token.to_string()).collect()
+        };
+
+        let index = build_index(&[&["a", "b"], &["b", "c"]], immutable);
+        assert_eq!(index.vocabulary_size(), 3);
+
+        let query = index.parse_query(&tokens(&["b", "unseen", "c"]));
+        assert_eq!(index.vocabulary_size(), 3);
+        assert_eq!(index.get_token("unseen"), None);
+
+        let mut query_tokens = query.tokens.clone();
+        query_tokens.sort();
+        let mut expected = vec![None, index.get_token("b"), index.get_token("c")];
+        expected.sort();
+        assert_eq!(query_tokens, expected);
+        assert_eq!(index.filter(&query).count(), 0);
+
+        let query = index.parse_query(&tokens(&["b", "c"]));
+        assert_eq!(index.filter(&query).collect_vec(), vec![1]);
+    }
+
+    #[test]
+    fn test_document_from_tokens_reports_new_tokens() {
+        let mut index = InvertedIndex::new(true);
+        let tokens = |tokens: &[&str]| -> BTreeSet<String> {
+            tokens.iter().map(|token| token.to_string()).collect()
+        };
+
+        let (document, new_tokens) = index.document_from_tokens(&tokens(&["a", "b", "c"]));
+        assert_eq!(document.len(), 3);
+        assert_eq!(new_tokens.len(), 3);
+
+        // Only tokens not seen in the first document are reported
+        let (document, new_tokens) = index.document_from_tokens(&tokens(&["b", "c", "d", "e"]));
+        assert_eq!(document.len(), 4);
+        let expected = vec![index.get_token("d").unwrap(), index.get_token("e").unwrap()];
+        assert_eq!(new_tokens, expected);
+
+        // Nothing new
+        let (_, new_tokens) = index.document_from_tokens(&tokens(&["a", "e"]));
+        assert!(new_tokens.is_empty());
+    }
+
+    #[test]
+    fn test_reserve_postings() {
+        let documents: &[&[&str]] = &[&["a", "b"], &["a"], &["b", "c"]];
+        let tokens = |documents: &[&[&str]]| {
+            documents
+                .iter()
+                .enumerate()
+                .map(|(idx, tokens)| {
+                    let tokens = tokens.iter().map(|token| token.to_string()).collect();
+                    Ok((idx as PointOffsetType, tokens))
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let mut index = InvertedIndex::new(true);
+        index.reserve_postings(HashMap::from([(0, 100), (2, 50)]));
+        index.build_index(tokens(documents).into_iter()).unwrap();
+
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!()
+        };
+        let posting_capacity = |token: &str| {
+            let token = index.get_token(token).unwrap();
+            mutable.postings[token as usize]
+                .as_ref()
+                .unwrap()
+                .capacity()
+        };
+        assert!(posting_capacity("a") >= 100);
+        assert!(posting_capacity("c") >= 50);
+
+        // Reserve capacity of an existing posting list
+        let token_b = index.get_token("b").unwrap();
+        index.reserve_postings(HashMap::from([(token_b, 200)]));
+        let InvertedIndex::Mutable(mutable) = &index else {
+            unreachable!()
+        };
+        assert!(
+            mutable.postings[token_b as usize]
+                .as_ref()
+                .unwrap()
+                .capacity()
+                >= 200
+        );
+
+        // Results are the same as without hints
+        let mut expected = InvertedIndex::new(true);
+        expected.build_index(tokens(documents).into_iter()).unwrap();
+        for token in ["a", "b", "c"] {
+            let query = parse_query(&index, &[token]);
+            assert_eq!(
+                index.filter(&query).collect::<Vec<_>>(),
+                expected.filter(&query).collect::<Vec<_>>(),
+            );
+        }
+    }
+
+    #[test]
+    fn test_immutable_posting_cache() {
+        let documents: &[&[&str]] = &[&["hot", "cold"], &["hot"], &["hot", "warm"], &["cold"]];
+
+        let mut index = InvertedIndex::Immutable(ImmutableInvertedIndex::with_cache(2));
+        index
+            .build_index(documents.iter().enumerate().map(|(idx, tokens)| {
+                let tokens = tokens.iter().map(|token| token.to_string()).collect();
+                Ok((idx as PointOffsetType, tokens))
+            }))
+            .unwrap();
+
+        let InvertedIndex::Immutable(immutable) = &index else {
+            unreachable!()
+        };
+        let cache_stats = || immutable.cache_stats().unwrap();
+        assert_eq!(cache_stats(), PostingCacheStats::default());
+
+        let query = parse_query(&index, &["hot"]);
+        let first: Vec<_> = index.filter(&query).collect();
+        assert_eq!(first, vec![0, 1, 2]);
+        assert_eq!(cache_stats(), PostingCacheStats { hits: 0, misses: 1 });
+
+        // Repeated queries for the same token hit the cache
+        for expected_hits in 1..=3 {
+            let repeated: Vec<_> = index.filter(&query).collect();
+            assert_eq!(repeated, first);
+            assert_eq!(cache_stats().hits, expected_hits);
+            assert_eq!(cache_stats().misses, 1);
+        }
+
+        assert!(index.check_match(&query, 1));
+        assert!(!index.check_match(&query, 3));
+        assert_eq!(cache_stats(), PostingCacheStats { hits: 5, misses: 1 });
+
+        // Results match an index without cache
+        let uncached = build_index(documents, true);
+        let query = parse_query(&uncached, &["hot", "warm"]);
+        let expected: Vec<_> = uncached.filter(&query).collect();
+        assert_eq!(index.filter(&query).collect::<Vec<_>>(), expected);
+    }
 }
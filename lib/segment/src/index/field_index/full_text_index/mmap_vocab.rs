@@ -0,0 +1,283 @@
+//! Sorted term dictionary of a [`Vocab`](super::vocab::Vocab), memory-mapped so that token
+//! texts don't have to be resident
+//!
+//! Layout, all integers are little-endian:
+//! - `u64` number of tokens `n`
+//! - `n + 1` `u64` offsets of the token texts, in sorted order
+//! - `n` `u32` token IDs, in sorted order of their texts
+//! - `n` `u32` positions in sorted order, indexed by token ID
+//! - token texts, concatenated in sorted order
+
+use std::io::Write;
+use std::mem::size_of;
+use std::path::Path;
+
+use atomicwrites::{AtomicFile, OverwriteBehavior};
+use itertools::Itertools;
+use memmap2::Mmap;
+use memory::mmap_ops;
+
+use super::inverted_index::TokenId;
+use crate::common::error_logging::LogError;
+use crate::common::operation_error::{OperationError, OperationResult};
+
+#[derive(Debug)]
+pub struct MmapVocab {
+    mmap: Mmap,
+    len: usize,
+}
+
+impl MmapVocab {
+    /// Write a dictionary of `tokens`, whose IDs must be `0..n`, to `path`
+    pub fn create<'a>(
+        path: &Path,
+        tokens: impl Iterator<Item = (&'a str, TokenId)>,
+    ) -> OperationResult<()> {
+        let mut tokens: Vec<_> = tokens.collect();
+        tokens.sort_unstable();
+        let len = tokens.len();
+        if let Some(((token, _), _)) = tokens.iter().tuple_windows().find(|(a, b)| a.0 == b.0) {
+            return Err(OperationError::service_error(format!(
+                "Token {token:?} is not unique",
+            )));
+        }
+
+        let mut positions = vec![None; len];
+        for (position, &(token, token_id)) in tokens.iter().enumerate() {
+            match positions.get_mut(token_id as usize) {
+                Some(slot @ None) => *slot = Some(position as u32),
+                _ => {
+                    return Err(OperationError::service_error(format!(
+                        "Token {token:?} has ID {token_id}, \
+                         IDs must be unique and in range 0..{len}",
+                    )))
+                }
+            }
+        }
+
+        let texts_len: usize = tokens.iter().map(|(token, _)| token.len()).sum();
+        let mut data = Vec::with_capacity(Self::texts_start(len) + texts_len);
+        data.extend_from_slice(&(len as u64).to_le_bytes());
+        let mut offset = 0u64;
+        data.extend_from_slice(&offset.to_le_bytes());
+        for (token, _) in &tokens {
+            offset += token.len() as u64;
+            data.extend_from_slice(&offset.to_le_bytes());
+        }
+        for (_, token_id) in &tokens {
+            data.extend_from_slice(&token_id.to_le_bytes());
+        }
+        for position in positions.into_iter().flatten() {
+            data.extend_from_slice(&position.to_le_bytes());
+        }
+        for (token, _) in &tokens {
+            data.extend_from_slice(token.as_bytes());
+        }
+
+        // Write to a temporary file first, so that an interrupted write can't corrupt the dictionary
+        AtomicFile::new(path, OverwriteBehavior::AllowOverwrite)
+            .write(|file| file.write_all(&data))
+            .describe("Write mmap vocab")?;
+        Ok(())
+    }
+
+    /// Open a dictionary written by [`MmapVocab::create`], without reading it into memory
+    ///
+    /// The whole file is validated once, so that lookups can rely on its structure.
+    pub fn open(path: &Path) -> OperationResult<Self> {
+        let mmap = mmap_ops::open_read_mmap(path).describe("Open mmap vocab for reading")?;
+        let corrupted =
+            || OperationError::service_error(format!("Mmap vocab {} is corrupted", path.display()));
+
+        let header = mmap.get(..size_of::<u64>()).ok_or_else(corrupted)?;
+        let len = u64::from_le_bytes(header.try_into().unwrap()) as usize;
+        let vocab = Self { mmap, len };
+        if !vocab.is_valid() {
+            return Err(corrupted());
+        }
+        Ok(vocab)
+    }
+
+    /// Check that offsets are ascending and end with the file, that texts are valid UTF-8 in
+    /// strictly ascending order, and that token IDs and positions are inverse permutations
+    fn is_valid(&self) -> bool {
+        // Every token, even an empty one, takes 16 bytes for its text offset, ID and position. So a
+        // valid length is below the file size, check first to not overflow on a corrupted length
+        if self.len > self.mmap.len() {
+            return false;
+        }
+        let texts_start = Self::texts_start(self.len);
+        if self.mmap.len() < texts_start || self.read_u64(Self::offset_at(0)) != 0 {
+            return false;
+        }
+
+        let offsets_ascending = (0..self.len).all(|position| {
+            self.read_u64(Self::offset_at(position)) <= self.read_u64(Self::offset_at(position + 1))
+        });
+        let texts_len = self.read_u64(Self::offset_at(self.len));
+        if !offsets_ascending || texts_len != (self.mmap.len() - texts_start) as u64 {
+            return false;
+        }
+
+        // Lookups binary search the texts, so they must be sorted and unique
+        let texts_valid = (0..self.len).all(|position| {
+            let text = self.text_bytes(position);
+            std::str::from_utf8(text).is_ok()
+                && (position == 0 || self.text_bytes(position - 1) < text)
+        });
+
+        // Every ID is below `len` and maps back to its position, so both are permutations
+        let ids_valid = (0..self.len).all(|position| {
+            let token_id = self.token_id(position) as usize;
+            token_id < self.len && self.position(token_id) as usize == position
+        });
+
+        texts_valid && ids_valid
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, token: &str) -> Option<TokenId> {
+        let token = token.as_bytes();
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.text_bytes(mid).cmp(token) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(self.token_id(mid)),
+            }
+        }
+        None
+    }
+
+    pub fn get_text(&self, token_id: TokenId) -> Option<&str> {
+        let token_id = token_id as usize;
+        (token_id < self.len).then(|| self.text(self.position(token_id) as usize))
+    }
+
+    /// Iterate over all tokens, in sorted order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, TokenId)> + '_ {
+        (0..self.len).map(|position| (self.text(position), self.token_id(position)))
+    }
+
+    fn token_id(&self, position: usize) -> TokenId {
+        self.read_u32(Self::ids_start(self.len) + position * size_of::<u32>())
+    }
+
+    fn position(&self, token_id: usize) -> u32 {
+        self.read_u32(Self::positions_start(self.len) + token_id * size_of::<u32>())
+    }
+
+    fn text(&self, position: usize) -> &str {
+        // Safety: texts are checked to be valid UTF-8 in `MmapVocab::open`, and the file is only
+        // ever replaced by renaming a new one over it, never modified in place
+        unsafe { std::str::from_utf8_unchecked(self.text_bytes(position)) }
+    }
+
+    fn text_bytes(&self, position: usize) -> &[u8] {
+        let start = self.read_u64(Self::offset_at(position)) as usize;
+        let end = self.read_u64(Self::offset_at(position + 1)) as usize;
+        let texts_start = Self::texts_start(self.len);
+        &self.mmap[texts_start + start..texts_start + end]
+    }
+
+    fn read_u64(&self, at: usize) -> u64 {
+        u64::from_le_bytes(self.mmap[at..at + size_of::<u64>()].try_into().unwrap())
+    }
+
+    fn read_u32(&self, at: usize) -> u32 {
+        u32::from_le_bytes(self.mmap[at..at + size_of::<u32>()].try_into().unwrap())
+    }
+
+    fn offset_at(position: usize) -> usize {
+        size_of::<u64>() * (1 + position)
+    }
+
+    fn ids_start(len: usize) -> usize {
+        Self::offset_at(len + 1)
+    }
+
+    fn positions_start(len: usize) -> usize {
+        Self::ids_start(len) + len * size_of::<u32>()
+    }
+
+    fn texts_start(len: usize) -> usize {
+        Self::positions_start(len) + len * size_of::<u32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::Builder;
+
+    use super::*;
+
+    #[test]
+    fn test_mmap_vocab() {
+        let dir = Builder::new().prefix("mmap_vocab").tempdir().unwrap();
+        let path = dir.path().join("vocab.dat");
+
+        let tokens = [("quick", 0), ("brown", 1), ("fox", 2), ("", 3), ("ёж", 4)];
+        MmapVocab::create(&path, tokens.iter().copied()).unwrap();
+        let vocab = MmapVocab::open(&path).unwrap();
+
+        assert_eq!(vocab.len(), tokens.len());
+        for (token, token_id) in tokens {
+            assert_eq!(vocab.get(token), Some(token_id));
+            assert_eq!(vocab.get_text(token_id), Some(token));
+        }
+        assert_eq!(vocab.get("dog"), None);
+        assert_eq!(vocab.get("quic"), None);
+        assert_eq!(vocab.get_text(5), None);
+
+        let sorted: Vec<_> = vocab.iter().collect();
+        assert_eq!(
+            sorted,
+            vec![("", 3), ("brown", 1), ("fox", 2), ("quick", 0), ("ёж", 4)],
+        );
+
+        // Empty dictionary
+        MmapVocab::create(&path, std::iter::empty()).unwrap();
+        let vocab = MmapVocab::open(&path).unwrap();
+        assert_eq!(vocab.len(), 0);
+        assert_eq!(vocab.get("quick"), None);
+    }
+
+    #[test]
+    fn test_mmap_vocab_invalid() {
+        let dir = Builder::new().prefix("mmap_vocab").tempdir().unwrap();
+        let path = dir.path().join("vocab.dat");
+
+        // Token IDs must be dense and unique
+        assert!(MmapVocab::create(&path, [("a", 0), ("b", 2)].into_iter()).is_err());
+        assert!(MmapVocab::create(&path, [("a", 0), ("b", 0)].into_iter()).is_err());
+
+        assert!(MmapVocab::create(&path, [("a", 0), ("a", 1)].into_iter()).is_err());
+
+        MmapVocab::create(&path, [("a", 0), ("b", 1)].into_iter()).unwrap();
+        let data = std::fs::read(&path).unwrap();
+        let texts_start = MmapVocab::texts_start(2);
+        let check_corrupted = |corrupt: &dyn Fn(&mut Vec<u8>)| {
+            let mut corrupted = data.clone();
+            corrupt(&mut corrupted);
+            std::fs::write(&path, &corrupted).unwrap();
+            assert!(MmapVocab::open(&path).is_err());
+        };
+
+        // Truncated file
+        check_corrupted(&|data| data.truncate(data.len() - 1));
+        // Offsets not ascending
+        check_corrupted(&|data| data[MmapVocab::offset_at(1)] = 3);
+        // Token ID out of range
+        check_corrupted(&|data| data[MmapVocab::ids_start(2)] = 5);
+        // Position not matching the token ID
+        check_corrupted(&|data| data[MmapVocab::positions_start(2)] = 1);
+        // Texts not valid UTF-8
+        check_corrupted(&|data| data[texts_start] = 0xff);
+        // Texts not sorted
+        check_corrupted(&|data| data[texts_start..].reverse());
+    }
+}
@@ -1,8 +1,17 @@
 mod inverted_index;
+#[cfg(feature = "mmap-vocab")]
+mod mmap_vocab;
+mod posting_cache;
 mod posting_list;
 mod postings_iterator;
 pub mod text_index;
 mod tokenizers;
+mod vocab;
+
+#[cfg(feature = "testing")]
+pub use posting_list::PostingList;
+#[cfg(feature = "testing")]
+pub use postings_iterator::{merge_intersect_postings_iterator, probe_intersect_postings_iterator};
 
 #[cfg(test)]
 mod tests;
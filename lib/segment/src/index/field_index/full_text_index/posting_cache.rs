@@ -0,0 +1,186 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use common::types::PointOffsetType;
+use parking_lot::Mutex;
+
+use super::inverted_index::TokenId;
+
+/// Least recently used cache of decompressed posting lists
+///
+/// Trades memory for query speed on skewed workloads, where a few hot tokens are queried over and
+/// over again and would otherwise be decompressed on every query.
+#[derive(Debug)]
+pub struct PostingCache {
+    capacity: usize,
+    entries: Mutex<CacheEntries>,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    entries: HashMap<TokenId, CacheEntry>,
+    /// Tokens by the time they were last used, least recently used first
+    recency: BTreeMap<u64, TokenId>,
+    /// Monotonic counter, used to order entries by recency
+    clock: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    postings: Arc<[PointOffsetType]>,
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PostingCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+/// A clone has the same capacity, but starts out empty, with fresh statistics
+impl Clone for PostingCache {
+    fn clone(&self) -> Self {
+        Self::new(self.capacity)
+    }
+}
+
+impl PostingCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Default::default(),
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get decompressed postings of `token`, or decompress them with `decompress` on a cache miss
+    ///
+    /// Postings are decompressed without holding the lock, so other queries are not blocked by
+    /// it. If the cache is full, the least recently used postings are evicted.
+    pub fn get_or_insert_with(
+        &self,
+        token: TokenId,
+        decompress: impl FnOnce() -> Vec<PointOffsetType>,
+    ) -> Arc<[PointOffsetType]> {
+        if let Some(postings) = self.entries.lock().get(token) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return postings;
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let postings: Arc<[PointOffsetType]> = decompress().into();
+
+        if self.capacity == 0 {
+            return postings;
+        }
+
+        let mut cache = self.entries.lock();
+        // Another query may have cached the same postings in the meantime
+        if let Some(cached) = cache.get(token) {
+            return cached;
+        }
+        cache.insert(token, postings.clone(), self.capacity);
+        postings
+    }
+
+    pub fn stats(&self) -> PostingCacheStats {
+        PostingCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl CacheEntries {
+    fn tick(&mut self) -> u64 {
+        self.clock += 1;
+        self.clock
+    }
+
+    /// Get postings of `token`, marking them as most recently used
+    fn get(&mut self, token: TokenId) -> Option<Arc<[PointOffsetType]>> {
+        let clock = self.tick();
+        let entry = self.entries.get_mut(&token)?;
+        self.recency.remove(&entry.last_used);
+        self.recency.insert(clock, token);
+        entry.last_used = clock;
+        Some(entry.postings.clone())
+    }
+
+    /// Insert postings of `token`, which is not cached yet, evicting the least recently used
+    /// entries beyond `capacity`
+    fn insert(&mut self, token: TokenId, postings: Arc<[PointOffsetType]>, capacity: usize) {
+        while self.entries.len() >= capacity {
+            let Some((_, evicted)) = self.recency.pop_first() else {
+                break;
+            };
+            self.entries.remove(&evicted);
+        }
+
+        let clock = self.tick();
+        self.recency.insert(clock, token);
+        self.entries.insert(
+            token,
+            CacheEntry {
+                postings,
+                last_used: clock,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_posting_cache_evicts_least_recently_used() {
+        let cache = PostingCache::new(2);
+
+        cache.get_or_insert_with(1, || vec![1]);
+        cache.get_or_insert_with(2, || vec![2]);
+        assert_eq!(cache.stats(), PostingCacheStats { hits: 0, misses: 2 });
+
+        // Use token 1, so token 2 becomes least recently used
+        assert_eq!(&*cache.get_or_insert_with(1, || unreachable!()), &[1]);
+        assert_eq!(cache.stats(), PostingCacheStats { hits: 1, misses: 2 });
+
+        // Evicts token 2
+        cache.get_or_insert_with(3, || vec![3]);
+        assert_eq!(&*cache.get_or_insert_with(1, || unreachable!()), &[1]);
+        assert_eq!(&*cache.get_or_insert_with(2, || vec![2, 2]), &[2, 2]);
+        assert_eq!(cache.stats(), PostingCacheStats { hits: 2, misses: 4 });
+
+        let entries = cache.entries.lock();
+        assert_eq!(entries.entries.len(), 2);
+        assert_eq!(
+            entries.recency.values().copied().collect::<Vec<_>>(),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn test_posting_cache_concurrent_misses() {
+        let cache = PostingCache::new(2);
+
+        // Decompression does not hold the lock, so the cache can be used meanwhile
+        let postings = cache.get_or_insert_with(1, || {
+            assert_eq!(&*cache.get_or_insert_with(1, || vec![1]), &[1]);
+            vec![1]
+        });
+        assert_eq!(&*postings, &[1]);
+        assert_eq!(cache.stats(), PostingCacheStats { hits: 0, misses: 2 });
+
+        let entries = cache.entries.lock();
+        assert_eq!(entries.entries.len(), 1);
+        assert_eq!(entries.recency.len(), 1);
+    }
+}
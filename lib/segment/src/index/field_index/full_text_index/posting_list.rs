@@ -1,7 +1,25 @@
-use bitpacking::BitPacker;
+use bitpacking::{BitPacker, BitPacker1x, BitPacker4x, BitPacker8x};
 use common::types::PointOffsetType;
+use itertools::{EitherOrBoth, Itertools};
 
-type BitPackerImpl = bitpacking::BitPacker4x;
+use crate::common::operation_error::{OperationError, OperationResult};
+
+/// Largest block length of all supported bit packers
+const MAX_BLOCK_LEN: usize = BitPacker8x::BLOCK_LEN;
+
+/// Posting lists shorter than this are compressed in blocks of 32 by [`PostingCompression::Auto`]
+const AUTO_SMALL_POSTINGS_LEN: usize = 1024;
+
+/// Bits of a posting list bloom filter per posting, gives a false positive rate of about 1%
+const BLOOM_FILTER_BITS_PER_POSTING: usize = 10;
+
+/// Number of hash functions of a posting list bloom filter, optimal for
+/// [`BLOOM_FILTER_BITS_PER_POSTING`]
+const BLOOM_FILTER_HASHES: u64 = 7;
+
+/// Posting lists of at least this length are compressed in blocks of 256 by
+/// [`PostingCompression::Auto`]
+const AUTO_LARGE_POSTINGS_LEN: usize = 65_536;
 
 #[derive(Clone, Debug, Default)]
 pub struct PostingList {
@@ -13,6 +31,26 @@ impl PostingList {
         Self { list: vec![idx] }
     }
 
+    /// Create a posting list with space reserved for at least `capacity` postings
+    pub fn with_capacity(idx: PointOffsetType, capacity: usize) -> Self {
+        let mut list = Vec::with_capacity(capacity.max(1));
+        list.push(idx);
+        Self { list }
+    }
+
+    /// Reserve space, so that this posting list can hold at least `capacity` postings in total
+    pub fn reserve(&mut self, capacity: usize) {
+        self.list.reserve(capacity.saturating_sub(self.list.len()));
+    }
+
+    #[cfg(test)]
+    pub fn capacity(&self) -> usize {
+        self.list.capacity()
+    }
+
+    /// Insert `idx`, keeping the list sorted and deduplicated
+    ///
+    /// Appending past the last posting is a plain push, only out of order inserts search and shift.
     pub fn insert(&mut self, idx: PointOffsetType) {
         if self.list.is_empty() || idx > *self.list.last().unwrap() {
             self.list.push(idx);
@@ -32,26 +70,302 @@ impl PostingList {
         }
     }
 
+    /// Keep only postings for which `keep` returns true, in a single pass
+    pub fn retain(&mut self, mut keep: impl FnMut(PointOffsetType) -> bool) {
+        self.list.retain(|&idx| keep(idx));
+    }
+
     pub fn len(&self) -> usize {
         self.list.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Approximate heap memory held by this list, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.list.capacity() * std::mem::size_of::<PointOffsetType>()
+    }
+
     pub fn contains(&self, val: &PointOffsetType) -> bool {
         self.list.binary_search(val).is_ok()
     }
 
+    /// Whether this list contains any posting in the inclusive range `[lo, hi]`
+    pub fn contains_range(&self, lo: PointOffsetType, hi: PointOffsetType) -> bool {
+        let first_idx = self.list.partition_point(|&posting| posting < lo);
+        lo <= hi
+            && self
+                .list
+                .get(first_idx)
+                .is_some_and(|&posting| posting <= hi)
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = PointOffsetType> + '_ {
         self.list.iter().copied()
     }
+
+    /// Iterate postings in descending order
+    pub fn iter_rev(&self) -> impl Iterator<Item = PointOffsetType> + '_ {
+        self.list.iter().rev().copied()
+    }
+
+    /// Postings in both lists
+    #[cfg(any(test, feature = "testing"))]
+    pub fn intersect(&self, other: &PostingList) -> PostingList {
+        self.merge_walk(other, |entry| entry.both().map(|(idx, _)| idx))
+    }
+
+    /// Postings in any of both lists
+    pub fn union(&self, other: &PostingList) -> PostingList {
+        self.merge_walk(other, |entry| Some(entry.reduce(|idx, _| idx)))
+    }
+
+    /// Postings in this list, but not in `other`
+    #[cfg(any(test, feature = "testing"))]
+    pub fn difference(&self, other: &PostingList) -> PostingList {
+        self.merge_walk(other, |entry| entry.just_left())
+    }
+
+    /// Walk both sorted lists at once, keeping postings selected by `select`
+    ///
+    /// Both lists are sorted and deduplicated, so the result is too.
+    fn merge_walk(
+        &self,
+        other: &PostingList,
+        select: impl FnMut(EitherOrBoth<PointOffsetType>) -> Option<PointOffsetType>,
+    ) -> PostingList {
+        let list = self
+            .iter()
+            .merge_join_by(other.iter(), PointOffsetType::cmp)
+            .filter_map(select)
+            .collect();
+        PostingList { list }
+    }
+}
+
+/// Block strategy to compress posting lists with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PostingCompression {
+    /// Select block length by the number of postings
+    #[default]
+    Auto,
+    /// Blocks of 32 postings, leaves less postings uncompressed in short lists
+    Block32,
+    /// Blocks of 128 postings
+    Block128,
+    /// Blocks of 256 postings, for best decompression throughput in dense lists
+    Block256,
+}
+
+impl PostingCompression {
+    /// Resolve [`PostingCompression::Auto`] into a concrete block length
+    fn resolve(self, postings_len: usize) -> Self {
+        match self {
+            Self::Auto if postings_len < AUTO_SMALL_POSTINGS_LEN => Self::Block32,
+            Self::Auto if postings_len < AUTO_LARGE_POSTINGS_LEN => Self::Block128,
+            Self::Auto => Self::Block256,
+            compression => compression,
+        }
+    }
+
+    fn block_len(self) -> usize {
+        match self {
+            Self::Block32 => BitPacker1x::BLOCK_LEN,
+            Self::Auto | Self::Block128 => BitPacker4x::BLOCK_LEN,
+            Self::Block256 => BitPacker8x::BLOCK_LEN,
+        }
+    }
+
+    fn num_bits_sorted(self, initial: PointOffsetType, block: &[PointOffsetType]) -> u8 {
+        match self {
+            Self::Block32 => BitPacker1x::new().num_bits_sorted(initial, block),
+            Self::Auto | Self::Block128 => BitPacker4x::new().num_bits_sorted(initial, block),
+            Self::Block256 => BitPacker8x::new().num_bits_sorted(initial, block),
+        }
+    }
+
+    fn compressed_block_size(self, num_bits: u8) -> usize {
+        match self {
+            Self::Block32 => BitPacker1x::compressed_block_size(num_bits),
+            Self::Auto | Self::Block128 => BitPacker4x::compressed_block_size(num_bits),
+            Self::Block256 => BitPacker8x::compressed_block_size(num_bits),
+        }
+    }
+
+    fn compress_sorted(
+        self,
+        initial: PointOffsetType,
+        decompressed: &[PointOffsetType],
+        compressed: &mut [u8],
+        num_bits: u8,
+    ) {
+        match self {
+            Self::Block32 => {
+                BitPacker1x::new().compress_sorted(initial, decompressed, compressed, num_bits)
+            }
+            Self::Auto | Self::Block128 => {
+                BitPacker4x::new().compress_sorted(initial, decompressed, compressed, num_bits)
+            }
+            Self::Block256 => {
+                BitPacker8x::new().compress_sorted(initial, decompressed, compressed, num_bits)
+            }
+        };
+    }
+
+    fn decompress_sorted(
+        self,
+        initial: PointOffsetType,
+        compressed: &[u8],
+        decompressed: &mut [PointOffsetType],
+        num_bits: u8,
+    ) {
+        match self {
+            Self::Block32 => {
+                BitPacker1x::new().decompress_sorted(initial, compressed, decompressed, num_bits)
+            }
+            Self::Auto | Self::Block128 => {
+                BitPacker4x::new().decompress_sorted(initial, compressed, decompressed, num_bits)
+            }
+            Self::Block256 => {
+                BitPacker8x::new().decompress_sorted(initial, compressed, decompressed, num_bits)
+            }
+        };
+    }
+}
+
+/// Options to build a [`CompressedPostingList`] with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CompressionOptions {
+    /// Decompress the built list and compare it with the original postings.
+    /// Enabled in debug builds by default.
+    pub verify: bool,
+    /// Build a bloom filter of the postings, so that [`CompressedPostingList::contains`] can
+    /// reject most absent values without decompressing a chunk. Costs about
+    /// [`BLOOM_FILTER_BITS_PER_POSTING`] bits per posting.
+    pub bloom_filter: bool,
+}
+
+impl Default for CompressionOptions {
+    fn default() -> Self {
+        Self {
+            verify: cfg!(debug_assertions),
+            bloom_filter: false,
+        }
+    }
+}
+
+/// Bloom filter over the postings of a [`CompressedPostingList`]
+///
+/// May report false positives, but never false negatives.
+#[derive(Clone, Debug)]
+struct PostingBloomFilter {
+    bits: Vec<u64>,
+}
+
+impl PostingBloomFilter {
+    /// Build a filter sized for `len` postings, of `postings`
+    fn new(len: usize, postings: impl IntoIterator<Item = PointOffsetType>) -> Self {
+        let num_bits = (len * BLOOM_FILTER_BITS_PER_POSTING).max(64);
+        let mut filter = Self {
+            bits: vec![0; num_bits.div_ceil(64)],
+        };
+        postings.into_iter().for_each(|idx| filter.insert(idx));
+        filter
+    }
+
+    fn insert(&mut self, idx: PointOffsetType) {
+        for bit in self.bit_positions(idx) {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    fn may_contain(&self, idx: PointOffsetType) -> bool {
+        self.bit_positions(idx)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+
+    /// Bits set for `idx`, by double hashing of a single 64 bit hash
+    fn bit_positions(&self, idx: PointOffsetType) -> impl Iterator<Item = usize> {
+        // splitmix64 finalizer
+        let mut hash = u64::from(idx).wrapping_add(0x9E37_79B9_7F4A_7C15);
+        hash = (hash ^ (hash >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        hash = (hash ^ (hash >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        hash ^= hash >> 31;
+
+        let (h1, h2) = (hash & u64::from(u32::MAX), hash >> 32);
+        let num_bits = self.bits.len() as u64 * 64;
+        (0..BLOOM_FILTER_HASHES)
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.bits.capacity() * std::mem::size_of::<u64>()
+    }
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct CompressedPostingList {
+    /// Block strategy the chunks were compressed with, resolved from [`PostingCompression::Auto`]
+    compression: PostingCompression,
     last_doc_id: PointOffsetType,
     data: Vec<u8>,
     chunks: Vec<CompressedPostingChunk>,
     // last postings that are not compressed because they are not aligned with the block size
     reminder_postings: Vec<PointOffsetType>,
+    /// Optional pre-check of [`CompressedPostingList::contains`], see [`CompressionOptions`]
+    bloom_filter: Option<PostingBloomFilter>,
+}
+
+/// Builds a [`CompressedPostingList`] from sorted postings, compressing every block as soon as
+/// it is full
+///
+/// Unlike [`CompressedPostingList::with_options`], postings don't have to be collected into a
+/// [`PostingList`] first. Only the compressed data and a single block of postings are held, so
+/// peak memory for very long posting lists stays close to their compressed size.
+pub struct CompressedPostingListBuilder {
+    list: CompressedPostingList,
+}
+
+impl CompressedPostingListBuilder {
+    /// Create a builder for about `capacity` postings
+    ///
+    /// [`PostingCompression::Auto`] is resolved by `capacity`. Chunk offsets and the block
+    /// buffer are allocated upfront, so they are never reallocated if `capacity` is exact.
+    pub fn with_capacity(capacity: usize, compression: PostingCompression) -> Self {
+        let compression = compression.resolve(capacity);
+        let block_len = compression.block_len();
+        Self {
+            list: CompressedPostingList {
+                compression,
+                chunks: Vec::with_capacity(capacity / block_len),
+                reminder_postings: Vec::with_capacity(block_len),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Append `idx`, which must be greater than all postings pushed before
+    pub fn push(&mut self, idx: PointOffsetType) -> OperationResult<()> {
+        self.list.push(idx)
+    }
+
+    pub fn build(self) -> CompressedPostingList {
+        self.list
+    }
+}
+
+/// Posting of a [`CompressedPostingList`], with the chunk it was decoded from
+///
+/// Postings that are not compressed into a chunk are reported with `chunk_index` equal to the
+/// number of chunks, and the full width of [`PointOffsetType`] as `chunk_bits`.
+#[cfg(test)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DecodedEntry {
+    pub chunk_index: usize,
+    pub chunk_bits: u8,
+    pub value: PointOffsetType,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -61,21 +375,76 @@ pub struct CompressedPostingChunk {
 }
 
 impl CompressedPostingList {
+    #[cfg(test)]
     pub fn new(posting_list: PostingList) -> Self {
+        Self::with_compression(posting_list, PostingCompression::Auto)
+    }
+
+    /// Compress `posting_list` without verification, see [`CompressedPostingList::with_options`]
+    #[cfg(test)]
+    pub fn with_compression(posting_list: PostingList, compression: PostingCompression) -> Self {
+        Self::compress(&posting_list, compression)
+    }
+
+    /// Compress `posting_list`, returning an error if verification is enabled in `options` and
+    /// the compressed list doesn't match the original postings
+    pub fn with_options(
+        posting_list: PostingList,
+        compression: PostingCompression,
+        options: CompressionOptions,
+    ) -> OperationResult<Self> {
+        let mut compressed = Self::compress(&posting_list, compression);
+        if options.bloom_filter {
+            compressed.bloom_filter = Some(PostingBloomFilter::new(
+                posting_list.len(),
+                posting_list.list.iter().copied(),
+            ));
+        }
+        if options.verify {
+            compressed.verify(&posting_list.list)?;
+        }
+        Ok(compressed)
+    }
+
+    /// Build a bloom filter of all postings, sized for their current number, see
+    /// [`CompressionOptions::bloom_filter`]
+    ///
+    /// Postings pushed afterwards are added to the filter, but raise its false positive rate.
+    pub fn build_bloom_filter(&mut self) {
+        self.bloom_filter = Some(PostingBloomFilter::new(self.len(), self.iter()));
+    }
+
+    /// Check that this list decompresses into exactly `expected` postings
+    pub fn verify(&self, expected: &[PointOffsetType]) -> OperationResult<()> {
+        if self.len() != expected.len() || !self.iter().eq(expected.iter().copied()) {
+            return Err(OperationError::service_error(format!(
+                "Compressed posting list of {} postings doesn't match {} original postings",
+                self.len(),
+                expected.len(),
+            )));
+        }
+        Ok(())
+    }
+
+    fn compress(posting_list: &PostingList, compression: PostingCompression) -> Self {
+        let compression = compression.resolve(posting_list.len());
         if posting_list.list.is_empty() {
-            return Self::default();
+            return Self {
+                compression,
+                ..Default::default()
+            };
         }
 
         // fill chunks data
-        let bitpacker = BitPackerImpl::new();
-        let mut chunks = Vec::with_capacity(posting_list.len() / BitPackerImpl::BLOCK_LEN);
+        let block_len = compression.block_len();
+        let mut chunks = Vec::with_capacity(posting_list.len() / block_len);
         let mut data_size = 0;
         let mut noncompressed_postings = Vec::new();
-        for chunk_data in posting_list.list.chunks(BitPackerImpl::BLOCK_LEN) {
-            if chunk_data.len() == BitPackerImpl::BLOCK_LEN {
+        for chunk_data in posting_list.list.chunks(block_len) {
+            if chunk_data.len() == block_len {
                 let initial = chunk_data[0];
-                let chunk_bits: u8 = bitpacker.num_bits_sorted(initial, chunk_data);
-                let chunk_size = BitPackerImpl::compressed_block_size(chunk_bits);
+                let chunk_bits: u8 = compression.num_bits_sorted(initial, chunk_data);
+                let chunk_size = compression.compressed_block_size(chunk_bits);
                 chunks.push(CompressedPostingChunk {
                     initial,
                     offset: data_size as u32,
@@ -89,15 +458,11 @@ impl CompressedPostingList {
 
         // compress data
         let mut data = vec![0u8; data_size];
-        for (chunk_index, chunk_data) in posting_list
-            .list
-            .chunks_exact(BitPackerImpl::BLOCK_LEN)
-            .enumerate()
-        {
+        for (chunk_index, chunk_data) in posting_list.list.chunks_exact(block_len).enumerate() {
             let chunk = &chunks[chunk_index];
             let chunk_size = Self::get_chunk_size(&chunks, &data, chunk_index);
-            let chunk_bits = (chunk_size * 8) / BitPackerImpl::BLOCK_LEN;
-            bitpacker.compress_sorted(
+            let chunk_bits = (chunk_size * 8) / block_len;
+            compression.compress_sorted(
                 chunk.initial,
                 chunk_data,
                 &mut data[chunk.offset as usize..chunk.offset as usize + chunk_size],
@@ -106,17 +471,69 @@ impl CompressedPostingList {
         }
 
         Self {
+            compression,
             last_doc_id: *posting_list.list.last().unwrap(),
             data,
             chunks,
             reminder_postings: noncompressed_postings,
+            bloom_filter: None,
+        }
+    }
+
+    /// Append `idx` to the end of this list, without recompressing existing chunks
+    ///
+    /// Appended postings are kept uncompressed until they fill a block, which is then compressed
+    /// into a new chunk. `idx` must be greater than all postings, to keep the list sorted.
+    pub fn push(&mut self, idx: PointOffsetType) -> OperationResult<()> {
+        if !self.is_empty() && idx <= self.last_doc_id {
+            return Err(OperationError::ValidationError {
+                description: format!(
+                    "Can't append {idx} to posting list, it must be greater than last posting {}",
+                    self.last_doc_id,
+                ),
+            });
+        }
+
+        self.reminder_postings.push(idx);
+        self.last_doc_id = idx;
+        if let Some(bloom_filter) = &mut self.bloom_filter {
+            bloom_filter.insert(idx);
         }
+
+        // compress postings once they fill a block
+        if self.reminder_postings.len() == self.compression.block_len() {
+            let initial = self.reminder_postings[0];
+            let chunk_bits = self
+                .compression
+                .num_bits_sorted(initial, &self.reminder_postings);
+            let chunk_size = self.compression.compressed_block_size(chunk_bits);
+            let offset = self.data.len();
+            self.data.resize(offset + chunk_size, 0);
+            self.compression.compress_sorted(
+                initial,
+                &self.reminder_postings,
+                &mut self.data[offset..],
+                chunk_bits,
+            );
+            self.chunks.push(CompressedPostingChunk {
+                initial,
+                offset: offset as u32,
+            });
+            self.reminder_postings.clear();
+        }
+
+        Ok(())
     }
 
     pub fn contains(&self, val: &PointOffsetType) -> bool {
         if !self.is_in_postings_range(*val) {
             return false;
         }
+        if let Some(bloom_filter) = &self.bloom_filter {
+            if !bloom_filter.may_contain(*val) {
+                return false;
+            }
+        }
 
         // find the chunk that may contain the value and check if the value is in the chunk
         let chunk_index = self.find_chunk(val, None);
@@ -125,29 +542,141 @@ impl CompressedPostingList {
                 return true;
             }
 
-            let mut decompressed = [0u32; BitPackerImpl::BLOCK_LEN];
-            self.decompress_chunk(&BitPackerImpl::new(), chunk_index, &mut decompressed);
+            let mut decompressed = [0u32; MAX_BLOCK_LEN];
+            let decompressed = &mut decompressed[..self.compression.block_len()];
+            self.decompress_chunk(chunk_index, decompressed);
             decompressed.binary_search(val).is_ok()
         } else {
             self.reminder_postings.binary_search(val).is_ok()
         }
     }
 
+    /// Whether this list contains any posting in the inclusive range `[lo, hi]`
+    ///
+    /// Chunk bounds are checked first, so that at most one chunk is decompressed.
+    pub fn contains_range(&self, lo: PointOffsetType, hi: PointOffsetType) -> bool {
+        if lo > hi || self.is_empty() || lo > self.last_doc_id {
+            return false;
+        }
+
+        let Some(chunk_index) = self.find_chunk(&lo, None) else {
+            // `lo` is either before the first chunk, or in the noncompressed postings range
+            let first_posting = match self.chunks.first() {
+                Some(chunk) if lo < chunk.initial => chunk.initial,
+                _ => {
+                    let first_idx = self.reminder_postings.partition_point(|&p| p < lo);
+                    self.reminder_postings[first_idx]
+                }
+            };
+            return first_posting <= hi;
+        };
+
+        if self.chunks[chunk_index].initial == lo {
+            return true;
+        }
+
+        // First posting after the chunk is greater than `lo`, so it decides without decompressing
+        let next_posting = match self.chunks.get(chunk_index + 1) {
+            Some(next_chunk) => Some(next_chunk.initial),
+            None => self.reminder_postings.first().copied(),
+        };
+        if next_posting.is_some_and(|posting| posting <= hi) {
+            return true;
+        }
+
+        let mut decompressed = [0u32; MAX_BLOCK_LEN];
+        let decompressed = &mut decompressed[..self.compression.block_len()];
+        self.decompress_chunk(chunk_index, decompressed);
+        let first_idx = decompressed.partition_point(|&posting| posting < lo);
+        decompressed
+            .get(first_idx)
+            .is_some_and(|&posting| posting <= hi)
+    }
+
+    /// Number of postings in this list
+    ///
+    /// Compressed chunks are never padded: only full blocks are compressed, and the remaining
+    /// postings are kept uncompressed in `reminder_postings`.
     pub fn len(&self) -> usize {
-        self.chunks.len() * BitPackerImpl::BLOCK_LEN + self.reminder_postings.len()
+        self.chunks.len() * self.compression.block_len() + self.reminder_postings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty() && self.reminder_postings.is_empty()
+    }
+
+    /// Greatest posting of this list
+    pub fn last(&self) -> Option<PointOffsetType> {
+        (!self.is_empty()).then_some(self.last_doc_id)
+    }
+
+    /// Approximate heap memory held by this list, in bytes
+    pub fn memory_usage(&self) -> usize {
+        self.data.capacity()
+            + self.chunks.capacity() * std::mem::size_of::<CompressedPostingChunk>()
+            + self.reminder_postings.capacity() * std::mem::size_of::<PointOffsetType>()
+            + self
+                .bloom_filter
+                .as_ref()
+                .map_or(0, PostingBloomFilter::memory_usage)
+    }
+
+    /// Block strategy this list was compressed with
+    #[cfg(test)]
+    pub fn compression(&self) -> PostingCompression {
+        self.compression
     }
 
     pub fn iter(&self) -> impl Iterator<Item = PointOffsetType> + '_ {
-        let bitpacker = BitPackerImpl::new();
+        let block_len = self.compression.block_len();
         (0..self.chunks.len())
             .flat_map(move |chunk_index| {
-                let mut decompressed = [0u32; BitPackerImpl::BLOCK_LEN];
-                self.decompress_chunk(&bitpacker, chunk_index, &mut decompressed);
-                decompressed.into_iter()
+                let mut decompressed = [0u32; MAX_BLOCK_LEN];
+                self.decompress_chunk(chunk_index, &mut decompressed[..block_len]);
+                decompressed.into_iter().take(block_len)
             })
             .chain(self.reminder_postings.iter().copied())
     }
 
+    /// Iterate postings along with the chunk they are stored in, to diagnose compression
+    #[cfg(test)]
+    pub fn iter_debug(&self) -> impl Iterator<Item = DecodedEntry> + '_ {
+        let block_len = self.compression.block_len();
+        let chunks = (0..self.chunks.len()).flat_map(move |chunk_index| {
+            let chunk_bits = self.chunk_bits(chunk_index);
+            let mut decompressed = [0u32; MAX_BLOCK_LEN];
+            self.decompress_chunk(chunk_index, &mut decompressed[..block_len]);
+            decompressed
+                .into_iter()
+                .take(block_len)
+                .map(move |value| DecodedEntry {
+                    chunk_index,
+                    chunk_bits,
+                    value,
+                })
+        });
+        let reminder = self.reminder_postings.iter().map(|&value| DecodedEntry {
+            chunk_index: self.chunks.len(),
+            chunk_bits: PointOffsetType::BITS as u8,
+            value,
+        });
+        chunks.chain(reminder)
+    }
+
+    /// Iterate postings in descending order, decompressing chunks back to front
+    pub fn iter_rev(&self) -> impl Iterator<Item = PointOffsetType> + '_ {
+        let block_len = self.compression.block_len();
+        self.reminder_postings
+            .iter()
+            .rev()
+            .copied()
+            .chain((0..self.chunks.len()).rev().flat_map(move |chunk_index| {
+                let mut decompressed = [0u32; MAX_BLOCK_LEN];
+                self.decompress_chunk(chunk_index, &mut decompressed[..block_len]);
+                decompressed.into_iter().take(block_len).rev()
+            }))
+    }
+
     fn get_chunk_size(chunks: &[CompressedPostingChunk], data: &[u8], chunk_index: usize) -> usize {
         assert!(chunk_index < chunks.len());
         if chunk_index + 1 < chunks.len() {
@@ -187,33 +716,84 @@ impl CompressedPostingList {
         in_chunks_range || in_noncompressed_range
     }
 
-    fn decompress_chunk(
-        &self,
-        bitpacker: &BitPackerImpl,
-        chunk_index: usize,
-        decompressed: &mut [PointOffsetType],
-    ) {
-        assert_eq!(decompressed.len(), BitPackerImpl::BLOCK_LEN);
+    /// Bit width of the deltas in chunk `chunk_index`
+    fn chunk_bits(&self, chunk_index: usize) -> u8 {
+        let chunk_size = Self::get_chunk_size(&self.chunks, &self.data, chunk_index);
+        ((chunk_size * 8) / self.compression.block_len()) as u8
+    }
+
+    fn decompress_chunk(&self, chunk_index: usize, decompressed: &mut [PointOffsetType]) {
+        assert_eq!(decompressed.len(), self.compression.block_len());
         let chunk = &self.chunks[chunk_index];
         let chunk_size = Self::get_chunk_size(&self.chunks, &self.data, chunk_index);
-        let chunk_bits = (chunk_size * 8) / BitPackerImpl::BLOCK_LEN;
-        bitpacker.decompress_sorted(
+        self.compression.decompress_sorted(
             chunk.initial,
             &self.data[chunk.offset as usize..chunk.offset as usize + chunk_size],
             decompressed,
-            chunk_bits as u8,
+            self.chunk_bits(chunk_index),
         );
     }
 }
 
+/// Posting list representation that can take part in an intersection, either as the list that is
+/// iterated or as one of the lists that are probed
+pub trait PostingIter {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn contains(&self, val: &PointOffsetType) -> bool;
+
+    fn iter(&self) -> Box<dyn Iterator<Item = PointOffsetType> + '_>;
+
+    fn iter_rev(&self) -> Box<dyn Iterator<Item = PointOffsetType> + '_>;
+}
+
+impl PostingIter for PostingList {
+    fn len(&self) -> usize {
+        PostingList::len(self)
+    }
+
+    fn contains(&self, val: &PointOffsetType) -> bool {
+        PostingList::contains(self, val)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        Box::new(PostingList::iter(self))
+    }
+
+    fn iter_rev(&self) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        Box::new(PostingList::iter_rev(self))
+    }
+}
+
+impl PostingIter for CompressedPostingList {
+    fn len(&self) -> usize {
+        CompressedPostingList::len(self)
+    }
+
+    fn contains(&self, val: &PointOffsetType) -> bool {
+        CompressedPostingList::contains(self, val)
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        Box::new(CompressedPostingList::iter(self))
+    }
+
+    fn iter_rev(&self) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        Box::new(CompressedPostingList::iter_rev(self))
+    }
+}
+
 // Help structure to find intersection of compressed postings and set of sorted values.
 // This help structure reuse the decompressed chunk to avoid unnecessary decompression.
 pub struct CompressedPostingVisitor<'a> {
-    bitpacker: BitPackerImpl,
     postings: &'a CompressedPostingList,
 
-    // Data for the decompressed chunk.
-    decompressed_chunk: [PointOffsetType; BitPackerImpl::BLOCK_LEN],
+    // Data for the decompressed chunk, only the first block length values are used.
+    decompressed_chunk: [PointOffsetType; MAX_BLOCK_LEN],
 
     // Index of the decompressed chunk.
     // It is used to shorten the search range of chunk index for the next value.
@@ -231,9 +811,8 @@ pub struct CompressedPostingVisitor<'a> {
 impl<'a> CompressedPostingVisitor<'a> {
     pub fn new(postings: &'a CompressedPostingList) -> CompressedPostingVisitor<'a> {
         CompressedPostingVisitor {
-            bitpacker: BitPackerImpl::new(),
             postings,
-            decompressed_chunk: [0; BitPackerImpl::BLOCK_LEN],
+            decompressed_chunk: [0; MAX_BLOCK_LEN],
             decompressed_chunk_idx: None,
             decompressed_chunk_start_index: 0,
             #[cfg(test)]
@@ -262,7 +841,8 @@ impl<'a> CompressedPostingVisitor<'a> {
         if self.decompressed_chunk_idx.is_some() {
             // check if value is in decompressed chunk range
             // check for max value in the chunk only because we already checked for min value while decompression
-            let last_decompressed = &self.decompressed_chunk[BitPackerImpl::BLOCK_LEN - 1];
+            let block_len = self.postings.compression.block_len();
+            let last_decompressed = &self.decompressed_chunk[block_len - 1];
             match val.cmp(last_decompressed) {
                 std::cmp::Ordering::Less => {
                     // value is less than the last decompressed value
@@ -292,8 +872,9 @@ impl<'a> CompressedPostingVisitor<'a> {
         }
 
         // second, decompress the chunk and check if the value is in the decompressed chunk
+        let block_len = self.postings.compression.block_len();
         self.postings
-            .decompress_chunk(&self.bitpacker, chunk_index, &mut self.decompressed_chunk);
+            .decompress_chunk(chunk_index, &mut self.decompressed_chunk[..block_len]);
         self.decompressed_chunk_idx = Some(chunk_index);
         self.decompressed_chunk_start_index = 0;
 
@@ -302,7 +883,10 @@ impl<'a> CompressedPostingVisitor<'a> {
     }
 
     fn find_in_decompressed_and_advance(&mut self, val: &PointOffsetType) -> bool {
-        match self.decompressed_chunk[self.decompressed_chunk_start_index..].binary_search(val) {
+        let block_len = self.postings.compression.block_len();
+        match self.decompressed_chunk[self.decompressed_chunk_start_index..block_len]
+            .binary_search(val)
+        {
             Ok(idx) => {
                 self.decompressed_chunk_start_index = idx;
                 true
@@ -317,7 +901,10 @@ impl<'a> CompressedPostingVisitor<'a> {
 
 #[cfg(test)]
 mod tests {
-    use std::collections::HashSet;
+    use std::collections::{BTreeSet, HashSet};
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
 
     use super::*;
 
@@ -344,6 +931,410 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_compressed_posting_builder() {
+        const LEN: usize = 1_000_000;
+        let postings = || (0..LEN as PointOffsetType).map(|i| i * 7 + i % 7);
+
+        let mut builder =
+            CompressedPostingListBuilder::with_capacity(LEN, PostingCompression::Auto);
+        for idx in postings() {
+            builder.push(idx).unwrap();
+        }
+        let compressed = builder.build();
+
+        assert_eq!(compressed.compression(), PostingCompression::Block256);
+        assert_eq!(compressed.len(), LEN);
+        assert!(compressed.iter().eq(postings()));
+
+        // Chunk offsets and the block buffer were allocated once, and never grown
+        assert_eq!(compressed.chunks.capacity(), compressed.chunks.len());
+        assert_eq!(compressed.reminder_postings.capacity(), 256);
+
+        // Same result as compressing a complete posting list
+        let mut posting_list = PostingList::default();
+        posting_list.reserve(LEN);
+        postings().for_each(|idx| posting_list.insert(idx));
+        let expected = CompressedPostingList::new(posting_list);
+        assert_eq!(compressed.data, expected.data);
+        assert_eq!(compressed.reminder_postings, expected.reminder_postings);
+        assert_eq!(compressed.last_doc_id, expected.last_doc_id);
+    }
+
+    #[test]
+    fn test_compressed_posting_bloom_filter() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut postings: Vec<PointOffsetType> =
+            (0..10_000).map(|_| rng.gen_range(0..1_000_000)).collect();
+        postings.sort_unstable();
+        postings.dedup();
+
+        let mut posting_list = PostingList::default();
+        postings.iter().for_each(|&idx| posting_list.insert(idx));
+        let options = CompressionOptions {
+            bloom_filter: true,
+            ..Default::default()
+        };
+        let compressed =
+            CompressedPostingList::with_options(posting_list, PostingCompression::Auto, options)
+                .unwrap();
+        let bloom_filter = compressed.bloom_filter.as_ref().unwrap();
+
+        // Building the filter of an already compressed list gives the same filter
+        let mut rebuilt = compressed.clone();
+        rebuilt.bloom_filter = None;
+        rebuilt.build_bloom_filter();
+        assert_eq!(rebuilt.bloom_filter.unwrap().bits, bloom_filter.bits);
+
+        // No false negatives
+        for idx in &postings {
+            assert!(bloom_filter.may_contain(*idx));
+            assert!(compressed.contains(idx));
+        }
+
+        // Few false positives, which are still rejected by the chunk lookup
+        let mut probes = 0;
+        let mut false_positives = 0;
+        while probes < 10_000 {
+            let idx = rng.gen_range(0..1_000_000);
+            if postings.binary_search(&idx).is_ok() {
+                continue;
+            }
+            probes += 1;
+            false_positives += usize::from(bloom_filter.may_contain(idx));
+            assert!(!compressed.contains(&idx));
+        }
+        let false_positive_rate = false_positives as f64 / probes as f64;
+        assert!(false_positive_rate < 0.03, "{false_positive_rate}");
+    }
+
+    #[test]
+    fn test_compressed_posting_iter_debug() {
+        // Chunk 0 with deltas of 1, chunk 1 with deltas of 1000, and 5 uncompressed postings
+        let mut posting_list = PostingList::default();
+        for i in 0..32 {
+            posting_list.insert(i);
+        }
+        for i in 1..=32 {
+            posting_list.insert(i * 1000);
+        }
+        for i in 1..=5 {
+            posting_list.insert(100_000 + i);
+        }
+
+        let compressed = CompressedPostingList::with_compression(
+            posting_list.clone(),
+            PostingCompression::Block32,
+        );
+        let entries: Vec<_> = compressed.iter_debug().collect();
+        assert_eq!(entries.len(), compressed.len());
+        assert!(entries
+            .iter()
+            .map(|entry| entry.value)
+            .eq(posting_list.iter()));
+
+        let chunks: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.chunk_index, entry.chunk_bits))
+            .collect();
+        let expected: Vec<_> = std::iter::repeat((0, 1))
+            .take(32)
+            .chain(std::iter::repeat((1, 10)).take(32))
+            .chain(std::iter::repeat((2, 32)).take(5))
+            .collect();
+        assert_eq!(chunks, expected);
+    }
+
+    #[test]
+    fn test_compression_verify() {
+        let mut posting_list = PostingList::default();
+        for i in 0..1000 {
+            posting_list.insert(i * 3);
+        }
+
+        for verify in [false, true] {
+            let options = CompressionOptions {
+                verify,
+                ..Default::default()
+            };
+            let compressed = CompressedPostingList::with_options(
+                posting_list.clone(),
+                PostingCompression::Auto,
+                options,
+            )
+            .unwrap();
+            assert!(compressed.iter().eq(posting_list.iter()));
+        }
+
+        // Force a mismatch by corrupting compressed data
+        let (mut compressed, _) = generate_compressed_posting_list(3);
+        let expected = compressed.iter().collect::<Vec<_>>();
+        assert!(compressed.verify(&expected).is_ok());
+        compressed.data[0] ^= 0xFF;
+        assert!(compressed.verify(&expected).is_err());
+        assert!(compressed.verify(&expected[1..]).is_err());
+    }
+
+    const COMPRESSIONS: [PostingCompression; 3] = [
+        PostingCompression::Block32,
+        PostingCompression::Block128,
+        PostingCompression::Block256,
+    ];
+
+    #[test]
+    fn test_compressed_posting_block_boundaries() {
+        for compression in COMPRESSIONS {
+            let block_len = compression.block_len();
+            for len in [1, block_len - 1, block_len, block_len + 1, 2 * block_len] {
+                check_block_boundaries(compression, len);
+            }
+        }
+    }
+
+    #[test]
+    fn test_posting_insert() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let sorted: BTreeSet<PointOffsetType> =
+            (0..1000).map(|_| rng.gen_range(0..10_000)).collect();
+
+        // Sequential inserts only take the append path
+        let mut sequential = PostingList::default();
+        for &idx in &sorted {
+            sequential.insert(idx);
+        }
+        assert!(sequential.iter().eq(sorted.iter().copied()));
+
+        // Shuffled inserts take the general path, and result in the same list
+        let mut shuffled: Vec<_> = sorted.iter().copied().collect();
+        shuffled.sort_unstable_by_key(|&idx| idx.wrapping_mul(2_654_435_761));
+        let mut general = PostingList::default();
+        for &idx in &shuffled {
+            general.insert(idx);
+        }
+        assert_eq!(general.list, sequential.list);
+
+        // Out of order insert lands in the right position, duplicates are ignored
+        let mut list = PostingList::default();
+        for idx in [1, 3, 7, 2, 0, 7, 3] {
+            list.insert(idx);
+        }
+        assert_eq!(list.list, vec![0, 1, 2, 3, 7]);
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let posting_list = |ids: &[PointOffsetType]| {
+            let mut posting_list = PostingList::default();
+            for &idx in ids {
+                posting_list.insert(idx);
+            }
+            posting_list
+        };
+        let check = |a: &[PointOffsetType], b: &[PointOffsetType]| {
+            let (a_set, b_set): (BTreeSet<_>, BTreeSet<_>) =
+                (a.iter().copied().collect(), b.iter().copied().collect());
+            let (a, b) = (posting_list(a), posting_list(b));
+
+            let intersection = a.intersect(&b).iter().collect::<Vec<_>>();
+            assert_eq!(
+                intersection,
+                a_set.intersection(&b_set).copied().collect::<Vec<_>>()
+            );
+            let union = a.union(&b).iter().collect::<Vec<_>>();
+            assert_eq!(union, a_set.union(&b_set).copied().collect::<Vec<_>>());
+            let difference = a.difference(&b).iter().collect::<Vec<_>>();
+            assert_eq!(
+                difference,
+                a_set.difference(&b_set).copied().collect::<Vec<_>>()
+            );
+        };
+
+        // Overlapping
+        check(&[1, 3, 5, 7, 9], &[3, 4, 5, 10]);
+        check(&[3, 4, 5, 10], &[1, 3, 5, 7, 9]);
+        // Disjoint
+        check(&[1, 2, 3], &[10, 20]);
+        check(&[2, 4, 6], &[1, 3, 5]);
+        // Identical
+        check(&[1, 2, 3], &[1, 2, 3]);
+        // Empty operands
+        check(&[], &[1, 2]);
+        check(&[1, 2], &[]);
+        check(&[], &[]);
+
+        let a = posting_list(&[1, 3, 5]);
+        let b = posting_list(&[2, 3, 4]);
+        assert_eq!(a.intersect(&b).iter().collect::<Vec<_>>(), vec![3]);
+        assert_eq!(a.union(&b).iter().collect::<Vec<_>>(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(a.difference(&b).iter().collect::<Vec<_>>(), vec![1, 5]);
+        assert_eq!(b.difference(&a).iter().collect::<Vec<_>>(), vec![2, 4]);
+    }
+
+    #[test]
+    fn test_contains_range() {
+        for compression in COMPRESSIONS {
+            let block_len = compression.block_len();
+            // Use a step, so that there are ranges between the postings that must not match
+            let values: Vec<PointOffsetType> = (0..2 * block_len as PointOffsetType + 5)
+                .map(|i| i * 3 + 10)
+                .collect();
+            let mut posting_list = PostingList::default();
+            for &value in &values {
+                posting_list.insert(value);
+            }
+            let compressed =
+                CompressedPostingList::with_compression(posting_list.clone(), compression);
+
+            let max_value = *values.last().unwrap();
+            for lo in 0..max_value + 5 {
+                for hi in lo.saturating_sub(1)..lo + 8 {
+                    let expected = values.iter().any(|value| (lo..=hi).contains(value));
+                    assert_eq!(
+                        posting_list.contains_range(lo, hi),
+                        expected,
+                        "[{lo}, {hi}]"
+                    );
+                    assert_eq!(compressed.contains_range(lo, hi), expected, "[{lo}, {hi}]");
+                }
+            }
+
+            // Ranges straddling chunk boundaries, with and without the next chunk's first posting
+            for boundary in [block_len, 2 * block_len] {
+                let (last, next) = (values[boundary - 1], values[boundary]);
+                assert!(compressed.contains_range(last + 1, next));
+                assert!(!compressed.contains_range(last + 1, next - 1));
+                assert!(compressed.contains_range(last, next));
+            }
+
+            // Ranges fully before and after the postings
+            assert!(!compressed.contains_range(0, values[0] - 1));
+            assert!(!compressed.contains_range(max_value + 1, PointOffsetType::MAX));
+            assert!(compressed.contains_range(0, PointOffsetType::MAX));
+        }
+
+        let empty = CompressedPostingList::default();
+        assert!(!empty.contains_range(0, PointOffsetType::MAX));
+        assert!(!PostingList::default().contains_range(0, PointOffsetType::MAX));
+    }
+
+    #[test]
+    fn test_compressed_posting_push() {
+        for compression in COMPRESSIONS {
+            let block_len = compression.block_len() as PointOffsetType;
+            let mut posting_list = PostingList::default();
+            let mut pushed =
+                CompressedPostingList::with_compression(PostingList::default(), compression);
+
+            for idx in (0..3 * block_len + 5).map(|i| i * 3 + i % 2) {
+                pushed.push(idx).unwrap();
+                posting_list.insert(idx);
+            }
+            let batch = CompressedPostingList::with_compression(posting_list.clone(), compression);
+
+            assert_eq!(pushed.len(), batch.len());
+            assert_eq!(pushed.data, batch.data);
+            assert_eq!(pushed.chunks.len(), batch.chunks.len());
+            assert_eq!(pushed.reminder_postings, batch.reminder_postings);
+            assert!(pushed.iter().eq(posting_list.iter()));
+            for idx in 0..posting_list.list.last().unwrap() + 2 {
+                assert_eq!(pushed.contains(&idx), posting_list.contains(&idx));
+            }
+
+            // Out of order and duplicate postings are rejected, and leave the list unchanged
+            let last = *posting_list.list.last().unwrap();
+            assert!(pushed.push(last).is_err());
+            assert!(pushed.push(last - 1).is_err());
+            assert_eq!(pushed.len(), posting_list.len());
+        }
+    }
+
+    #[test]
+    fn test_compressed_posting_round_trip() {
+        let cases = [
+            (PostingCompression::Auto, 0, PostingCompression::Block32),
+            (PostingCompression::Auto, 999, PostingCompression::Block32),
+            (
+                PostingCompression::Auto,
+                5_000,
+                PostingCompression::Block128,
+            ),
+            (
+                PostingCompression::Auto,
+                70_000,
+                PostingCompression::Block256,
+            ),
+            (
+                PostingCompression::Block32,
+                5_000,
+                PostingCompression::Block32,
+            ),
+            (
+                PostingCompression::Block128,
+                999,
+                PostingCompression::Block128,
+            ),
+            (
+                PostingCompression::Block256,
+                999,
+                PostingCompression::Block256,
+            ),
+        ];
+
+        for (compression, len, expected_compression) in cases {
+            let values: Vec<PointOffsetType> = (0..len as PointOffsetType).map(|i| i * 7).collect();
+            let mut posting_list = PostingList::default();
+            for &value in &values {
+                posting_list.insert(value);
+            }
+            let compressed_posting_list =
+                CompressedPostingList::with_compression(posting_list, compression);
+
+            assert_eq!(compressed_posting_list.compression(), expected_compression);
+            assert_eq!(compressed_posting_list.len(), len);
+            assert_eq!(compressed_posting_list.iter().collect::<Vec<_>>(), values);
+            assert!(compressed_posting_list
+                .iter_rev()
+                .eq(values.iter().rev().copied()));
+            assert!(values
+                .iter()
+                .all(|value| compressed_posting_list.contains(value)));
+        }
+    }
+
+    fn check_block_boundaries(compression: PostingCompression, len: usize) {
+        let block_len = compression.block_len();
+        // Use a step, so that there are values between the postings that must not be found
+        let values: Vec<PointOffsetType> = (0..len as PointOffsetType).map(|i| i * 3).collect();
+        let mut posting_list = PostingList::default();
+        for &value in &values {
+            posting_list.insert(value);
+        }
+        let compressed_posting_list =
+            CompressedPostingList::with_compression(posting_list, compression);
+
+        assert_eq!(compressed_posting_list.compression(), compression);
+        assert_eq!(compressed_posting_list.len(), len);
+        assert_eq!(compressed_posting_list.iter().collect::<Vec<_>>(), values);
+
+        let max_value = *values.last().unwrap();
+        for i in 0..=max_value + block_len as PointOffsetType {
+            assert_eq!(
+                compressed_posting_list.contains(&i),
+                i % 3 == 0 && i <= max_value,
+                "len {len}, value {i}",
+            );
+        }
+
+        let mut visitor = CompressedPostingVisitor::new(&compressed_posting_list);
+        for i in 0..=max_value + block_len as PointOffsetType {
+            assert_eq!(
+                visitor.contains_next_and_advance(&i),
+                i % 3 == 0 && i <= max_value,
+                "len {len}, value {i}",
+            );
+        }
+    }
+
     #[test]
     fn test_compressed_posting_visitor() {
         for build_step in 0..3 {
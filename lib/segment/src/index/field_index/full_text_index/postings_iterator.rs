@@ -1,9 +1,40 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
 use common::types::PointOffsetType;
 
-use super::posting_list::{CompressedPostingList, CompressedPostingVisitor, PostingList};
+use super::posting_list::{CompressedPostingList, CompressedPostingVisitor, PostingIter};
+
+/// Posting lists are merged rather than probed, if the longest one is at most this many times
+/// longer than the shortest one
+const BALANCED_POSTINGS_RATIO: usize = 4;
+
+/// Intersect posting lists of any representation, e.g. `Vec<&dyn PostingIter>` to mix compressed
+/// and uncompressed lists
+///
+/// Lists of similar length are intersected with a heap merge, otherwise the smallest list is
+/// iterated and all others are only probed.
+pub fn intersect_postings_iterator<'a, P: PostingIter + ?Sized>(
+    postings: Vec<&'a P>,
+) -> Box<dyn Iterator<Item = PointOffsetType> + 'a> {
+    if postings.iter().any(|posting| posting.is_empty()) {
+        return Box::new(std::iter::empty());
+    }
+
+    let min_len = postings.iter().map(|posting| posting.len()).min().unwrap();
+    let max_len = postings.iter().map(|posting| posting.len()).max().unwrap();
+
+    if max_len <= min_len.saturating_mul(BALANCED_POSTINGS_RATIO) {
+        merge_intersect_postings_iterator(postings)
+    } else {
+        probe_intersect_postings_iterator(postings)
+    }
+}
 
-pub fn intersect_postings_iterator<'a>(
-    mut postings: Vec<&'a PostingList>,
+/// Intersect posting lists by iterating the smallest list and probing all others
+pub fn probe_intersect_postings_iterator<'a, P: PostingIter + ?Sized>(
+    mut postings: Vec<&'a P>,
 ) -> Box<dyn Iterator<Item = PointOffsetType> + 'a> {
     let smallest_posting_idx = postings
         .iter()
@@ -20,6 +51,57 @@ pub fn intersect_postings_iterator<'a>(
     Box::new(and_iter)
 }
 
+/// Intersect posting lists by a k-way merge of all lists, keeping their cursors in a min-heap
+///
+/// A point is yielded once the smallest and the largest cursor point at the same value.
+pub fn merge_intersect_postings_iterator<'a, P: PostingIter + ?Sized>(
+    postings: Vec<&'a P>,
+) -> Box<dyn Iterator<Item = PointOffsetType> + 'a> {
+    let mut cursors = postings
+        .into_iter()
+        .map(|posting| posting.iter())
+        .collect::<Vec<_>>();
+    let mut heap = BinaryHeap::with_capacity(cursors.len());
+    let mut max = PointOffsetType::MIN;
+
+    for (idx, cursor) in cursors.iter_mut().enumerate() {
+        let Some(doc_id) = cursor.next() else {
+            return Box::new(std::iter::empty());
+        };
+        max = max.max(doc_id);
+        heap.push(Reverse((doc_id, idx)));
+    }
+
+    let and_iter = std::iter::from_fn(move || loop {
+        // Empty heap means one of the cursors is exhausted
+        let Reverse((min, idx)) = heap.pop()?;
+
+        if min == max {
+            // All cursors point at the same value, advance each of them
+            heap.clear();
+            for (idx, cursor) in cursors.iter_mut().enumerate() {
+                let Some(doc_id) = cursor.next() else {
+                    heap.clear();
+                    break;
+                };
+                max = max.max(doc_id);
+                heap.push(Reverse((doc_id, idx)));
+            }
+            return Some(min);
+        }
+
+        // Smallest cursor can't match anything below `max`, skip ahead
+        let Some(doc_id) = cursors[idx].find(|doc_id| *doc_id >= max) else {
+            heap.clear();
+            return None;
+        };
+        max = doc_id;
+        heap.push(Reverse((doc_id, idx)));
+    });
+
+    Box::new(and_iter)
+}
+
 pub fn intersect_compressed_postings_iterator<'a>(
     mut postings: Vec<&'a CompressedPostingList>,
     filter: impl Fn(PointOffsetType) -> bool + 'a,
@@ -49,9 +131,107 @@ pub fn intersect_compressed_postings_iterator<'a>(
     Box::new(and_iter)
 }
 
+/// Intersect posting lists of any representation, yielding points accepted by `filter` in
+/// descending order
+///
+/// The smallest list is iterated back to front, all others are only probed.
+pub fn intersect_postings_iterator_desc<'a, P: PostingIter + ?Sized>(
+    mut postings: Vec<&'a P>,
+    filter: impl Fn(PointOffsetType) -> bool + 'a,
+) -> Box<dyn Iterator<Item = PointOffsetType> + 'a> {
+    let smallest_posting_idx = postings
+        .iter()
+        .enumerate()
+        .min_by_key(|(_idx, posting)| posting.len())
+        .map(|(idx, _posting)| idx)
+        .unwrap();
+    let smallest_posting = postings.remove(smallest_posting_idx);
+
+    let and_iter = smallest_posting
+        .iter_rev()
+        .filter(move |doc_id| filter(*doc_id))
+        .filter(move |doc_id| postings.iter().all(|posting| posting.contains(doc_id)));
+
+    Box::new(and_iter)
+}
+
+/// Number of points in the intersection of `postings`, without yielding them
+pub fn count_postings_intersection<P: PostingIter + ?Sized>(mut postings: Vec<&P>) -> usize {
+    let smallest_posting_idx = postings
+        .iter()
+        .enumerate()
+        .min_by_key(|(_idx, posting)| posting.len())
+        .map(|(idx, _posting)| idx)
+        .unwrap();
+    let smallest_posting = postings.remove(smallest_posting_idx);
+
+    smallest_posting
+        .iter()
+        .filter(|doc_id| postings.iter().all(|posting| posting.contains(doc_id)))
+        .count()
+}
+
+/// Number of points in the intersection of compressed `postings` accepted by `filter`, without
+/// yielding them
+pub fn count_compressed_postings_intersection(
+    mut postings: Vec<&CompressedPostingList>,
+    filter: impl Fn(PointOffsetType) -> bool,
+) -> usize {
+    let smallest_posting_idx = postings
+        .iter()
+        .enumerate()
+        .min_by_key(|(_idx, posting)| posting.len())
+        .map(|(idx, _posting)| idx)
+        .unwrap();
+    let smallest_posting = postings.remove(smallest_posting_idx);
+
+    let mut posting_visitors = postings
+        .into_iter()
+        .map(CompressedPostingVisitor::new)
+        .collect::<Vec<_>>();
+
+    smallest_posting
+        .iter()
+        .filter(|doc_id| filter(*doc_id))
+        .filter(|doc_id| {
+            posting_visitors
+                .iter_mut()
+                .all(|posting_visitor| posting_visitor.contains_next_and_advance(doc_id))
+        })
+        .count()
+}
+
+pub fn intersect_decompressed_postings_iterator<'a>(
+    mut postings: Vec<Arc<[PointOffsetType]>>,
+    filter: impl Fn(PointOffsetType) -> bool + 'a,
+) -> Box<dyn Iterator<Item = PointOffsetType> + 'a> {
+    let smallest_posting_idx = postings
+        .iter()
+        .enumerate()
+        .min_by_key(|(_idx, posting)| posting.len())
+        .map(|(idx, _posting)| idx)
+        .unwrap();
+    let smallest_posting = postings.remove(smallest_posting_idx);
+
+    let and_iter = (0..smallest_posting.len())
+        .map(move |i| smallest_posting[i])
+        .filter(move |doc_id| filter(*doc_id))
+        .filter(move |doc_id| {
+            postings
+                .iter()
+                .all(|posting| posting.binary_search(doc_id).is_ok())
+        });
+
+    Box::new(and_iter)
+}
+
 #[cfg(test)]
 mod tests {
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
     use super::*;
+    use crate::index::field_index::full_text_index::posting_list::PostingList;
 
     #[test]
     fn test_postings_iterator() {
@@ -79,15 +259,111 @@ mod tests {
         let res = merged.collect::<Vec<_>>();
 
         assert_eq!(res, vec![2, 5]);
+        assert_eq!(count_postings_intersection(vec![&p1, &p2, &p3]), 2);
 
         let p1_compressed = CompressedPostingList::new(p1);
         let p2_compressed = CompressedPostingList::new(p2);
         let p3_compressed = CompressedPostingList::new(p3);
         let compressed_postings = vec![&p1_compressed, &p2_compressed, &p3_compressed];
-        let merged = intersect_compressed_postings_iterator(compressed_postings, |_| true);
+        let merged = intersect_compressed_postings_iterator(compressed_postings.clone(), |_| true);
+
+        let res = merged.collect::<Vec<_>>();
+
+        assert_eq!(res, vec![2, 5]);
+        assert_eq!(
+            count_compressed_postings_intersection(compressed_postings, |idx| idx != 5),
+            1,
+        );
+
+        let decompressed_postings = vec![
+            p1_compressed.iter().collect(),
+            p2_compressed.iter().collect(),
+            p3_compressed.iter().collect(),
+        ];
+        let merged = intersect_decompressed_postings_iterator(decompressed_postings, |_| true);
 
         let res = merged.collect::<Vec<_>>();
 
         assert_eq!(res, vec![2, 5]);
     }
+
+    fn posting_list(ids: impl IntoIterator<Item = PointOffsetType>) -> PostingList {
+        let mut posting_list = PostingList::default();
+        for idx in ids {
+            posting_list.insert(idx);
+        }
+        posting_list
+    }
+
+    #[test]
+    fn test_mixed_postings_iterator() {
+        let p1 = posting_list([1, 2, 3, 4, 5]);
+        let p2 = posting_list([2, 4, 5]);
+        // Long enough to have compressed chunks besides the uncompressed reminder
+        let p3 = posting_list((0..100).filter(|idx| idx % 2 == 0).chain([5]));
+        let p3_compressed = CompressedPostingList::new(p3.clone());
+
+        let postings: Vec<&dyn PostingIter> = vec![&p1, &p2, &p3_compressed];
+        let res = intersect_postings_iterator(postings.clone()).collect::<Vec<_>>();
+        assert_eq!(res, vec![2, 4, 5]);
+        assert_eq!(count_postings_intersection(postings), 3);
+
+        // Compressed list is the smallest one and drives the intersection
+        let p4 = posting_list(0..200);
+        let p2_compressed = CompressedPostingList::new(p2);
+        let postings: Vec<&dyn PostingIter> = vec![&p4, &p3, &p2_compressed];
+        let res = intersect_postings_iterator(postings).collect::<Vec<_>>();
+        assert_eq!(res, vec![2, 4]);
+    }
+
+    #[test]
+    fn test_intersection_strategies() {
+        let mut rng = StdRng::seed_from_u64(42);
+
+        let mut random_posting = |len: usize, range: PointOffsetType| {
+            posting_list((0..len).map(|_| rng.gen_range(0..range)))
+        };
+
+        // Balanced and skewed lengths, the latter also with a tiny list
+        let cases = [
+            vec![
+                random_posting(500, 1000),
+                random_posting(600, 1000),
+                random_posting(700, 1000),
+            ],
+            vec![
+                random_posting(20, 1000),
+                random_posting(900, 1000),
+                random_posting(800, 1000),
+            ],
+            vec![
+                random_posting(1, 10),
+                random_posting(5, 10),
+                random_posting(5, 10),
+            ],
+            vec![random_posting(300, 400), random_posting(300, 400)],
+        ];
+
+        for postings in &cases {
+            let expected = postings[0]
+                .iter()
+                .filter(|idx| postings.iter().all(|posting| posting.contains(idx)))
+                .collect::<Vec<_>>();
+
+            let postings = postings.iter().collect::<Vec<_>>();
+            let probed = probe_intersect_postings_iterator(postings.clone()).collect::<Vec<_>>();
+            let merged = merge_intersect_postings_iterator(postings.clone()).collect::<Vec<_>>();
+            let chosen = intersect_postings_iterator(postings).collect::<Vec<_>>();
+
+            assert_eq!(probed, expected);
+            assert_eq!(merged, expected);
+            assert_eq!(chosen, expected);
+        }
+
+        // Any empty list makes the intersection empty
+        let empty = PostingList::default();
+        let p1 = posting_list(0..10);
+        let postings: Vec<&PostingList> = vec![&p1, &empty, &p1];
+        assert_eq!(merge_intersect_postings_iterator(postings).count(), 0);
+    }
 }
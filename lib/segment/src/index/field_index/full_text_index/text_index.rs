@@ -1,7 +1,11 @@
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+#[cfg(feature = "mmap-vocab")]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
 
-use common::types::PointOffsetType;
+use common::types::{DetailsLevel, PointOffsetType, TelemetryDetail};
 use parking_lot::RwLock;
 use rocksdb::DB;
 use serde::{Deserialize, Serialize};
@@ -12,21 +16,32 @@ use crate::common::rocksdb_wrapper::DatabaseColumnWrapper;
 use crate::common::Flusher;
 use crate::data_types::text_index::TextIndexParams;
 use crate::index::field_index::full_text_index::inverted_index::{
-    Document, InvertedIndex, ParsedQuery,
+    CnfQuery, DocLengthStats, Document, ExpansionBudget, ImmutableInvertedIndex, InvertedIndex,
+    MultiFieldScorer, MutableInvertedIndex, ParsedQuery, ScoredQuery, TokenId, TokenStats,
 };
+use crate::index::field_index::full_text_index::posting_cache::PostingCacheStats;
+use crate::index::field_index::full_text_index::posting_list::PostingList;
 use crate::index::field_index::full_text_index::tokenizers::Tokenizer;
 use crate::index::field_index::{
     CardinalityEstimation, PayloadBlockCondition, PayloadFieldIndex, ValueIndexer,
 };
-use crate::telemetry::PayloadIndexTelemetry;
+use crate::telemetry::{PayloadIndexTelemetry, TextIndexTelemetry};
 use crate::types::{FieldCondition, Match, PayloadKeyType};
 
+/// Number of documents to compress at once, when loading an immutable index
+const LOAD_BATCH_SIZE: usize = 100_000;
+
 pub struct FullTextIndex {
     inverted_index: InvertedIndex,
     db_wrapper: DatabaseColumnWrapper,
     config: TextIndexParams,
+    /// Query-time synonyms of tokens, see [`FullTextIndex::parse_query_with_synonyms`]
+    synonyms: HashMap<String, Vec<String>>,
 }
 
+/// Byte ranges of tokens in the indexed text, see [`FullTextIndex::index_text_with_offsets`]
+type TokenOffsets = Vec<(String, Range<usize>)>;
+
 impl FullTextIndex {
     fn store_key(id: &PointOffsetType) -> Vec<u8> {
         bincode::serialize(&id).unwrap()
@@ -36,12 +51,18 @@ impl FullTextIndex {
         bincode::deserialize(data).unwrap()
     }
 
-    fn serialize_document_tokens(&self, tokens: BTreeSet<String>) -> OperationResult<Vec<u8>> {
+    fn serialize_document_tokens(
+        &self,
+        tokens: BTreeSet<String>,
+        offsets: TokenOffsets,
+    ) -> OperationResult<Vec<u8>> {
         #[derive(Serialize)]
         struct StoredDocument {
             tokens: BTreeSet<String>,
+            #[serde(skip_serializing_if = "Vec::is_empty")]
+            offsets: TokenOffsets,
         }
-        let doc = StoredDocument { tokens };
+        let doc = StoredDocument { tokens, offsets };
         serde_cbor::to_vec(&doc).map_err(|e| {
             OperationError::service_error(format!("Failed to serialize document: {e}"))
         })
@@ -59,10 +80,30 @@ impl FullTextIndex {
             .map(|doc| doc.tokens)
     }
 
+    fn deserialize_document_offsets(data: &[u8]) -> OperationResult<TokenOffsets> {
+        #[derive(Deserialize)]
+        struct StoredDocument {
+            #[serde(default)]
+            offsets: TokenOffsets,
+        }
+        serde_cbor::from_slice::<StoredDocument>(data)
+            .map_err(|e| {
+                OperationError::service_error(format!("Failed to deserialize document: {e}"))
+            })
+            .map(|doc| doc.offsets)
+    }
+
     fn storage_cf_name(field: &str) -> String {
         format!("{field}_fts")
     }
 
+    /// Path of the memory-mapped vocabulary of an index on `field`, in the payload index directory
+    /// `path`, see [`FullTextIndex::new_with_mmap_vocab`]
+    #[cfg(feature = "mmap-vocab")]
+    pub fn vocab_path(path: &Path, field: &str) -> PathBuf {
+        path.join(format!("{}.vocab", Self::storage_cf_name(field)))
+    }
+
     pub fn new(
         db: Arc<RwLock<DB>>,
         config: TextIndexParams,
@@ -75,15 +116,223 @@ impl FullTextIndex {
             inverted_index: InvertedIndex::new(is_appendable),
             db_wrapper,
             config,
+            synonyms: HashMap::new(),
+        }
+    }
+
+    /// Create a non-appendable index, which caches up to `capacity` decompressed posting lists
+    /// of the most recently queried tokens
+    pub fn new_with_posting_cache(
+        db: Arc<RwLock<DB>>,
+        config: TextIndexParams,
+        field: &str,
+        capacity: usize,
+    ) -> Self {
+        let store_cf_name = Self::storage_cf_name(field);
+        let db_wrapper = DatabaseColumnWrapper::new(db, &store_cf_name);
+        FullTextIndex {
+            inverted_index: InvertedIndex::Immutable(ImmutableInvertedIndex::with_cache(capacity)),
+            db_wrapper,
+            config,
+            synonyms: HashMap::new(),
+        }
+    }
+
+    /// Create a non-appendable index, which keeps its vocabulary in a memory-mapped dictionary at
+    /// `vocab_path`, see [`ImmutableInvertedIndex::with_mmap_vocab`]
+    ///
+    /// The dictionary is kept across reloads, so tokens are not read into memory on load.
+    #[cfg(feature = "mmap-vocab")]
+    pub fn new_with_mmap_vocab(
+        db: Arc<RwLock<DB>>,
+        config: TextIndexParams,
+        field: &str,
+        vocab_path: PathBuf,
+    ) -> Self {
+        let store_cf_name = Self::storage_cf_name(field);
+        let db_wrapper = DatabaseColumnWrapper::new(db, &store_cf_name);
+        FullTextIndex {
+            inverted_index: InvertedIndex::Immutable(ImmutableInvertedIndex::with_mmap_vocab(
+                vocab_path,
+            )),
+            db_wrapper,
+            config,
+            synonyms: HashMap::new(),
         }
     }
 
-    pub fn get_telemetry_data(&self) -> PayloadIndexTelemetry {
+    /// Create an index with a vocabulary of at most `max_vocab` tokens
+    ///
+    /// Protects against unbounded memory usage on high-cardinality text fields, tokens which don't
+    /// fit into the vocabulary are not indexed.
+    pub fn new_with_max_vocab(
+        db: Arc<RwLock<DB>>,
+        config: TextIndexParams,
+        field: &str,
+        is_appendable: bool,
+        max_vocab: usize,
+    ) -> Self {
+        let mut index = Self::new(db, config, field, is_appendable);
+        index.inverted_index = index.inverted_index.with_max_vocab(max_vocab);
+        index
+    }
+
+    /// Create a non-appendable index, which builds a bloom filter of every posting list
+    ///
+    /// Speeds up matching of single points against queries, at the cost of about
+    /// 10 bits per posting, see [`InvertedIndex::with_bloom_filter`].
+    pub fn new_with_bloom_filter(
+        db: Arc<RwLock<DB>>,
+        config: TextIndexParams,
+        field: &str,
+    ) -> Self {
+        let mut index = Self::new(db, config, field, false);
+        index.inverted_index = index.inverted_index.with_bloom_filter();
+        index
+    }
+
+    /// Reserve capacity of posting lists, given the expected number of points per token
+    ///
+    /// Useful when re-indexing data with a known token distribution, to avoid reallocations.
+    pub fn reserve_postings(&mut self, hints: HashMap<TokenId, usize>) {
+        self.inverted_index.reserve_postings(hints);
+    }
+
+    /// Whether points can be added to this index, see [`InvertedIndex::is_appendable`]
+    pub fn is_appendable(&self) -> bool {
+        self.inverted_index.is_appendable()
+    }
+
+    pub fn posting_cache_stats(&self) -> Option<PostingCacheStats> {
+        self.inverted_index.posting_cache_stats()
+    }
+
+    /// Remove `token` from all indexed points, e.g. to scrub a term
+    ///
+    /// Returns whether any point contained the token.
+    pub fn remove_token(&mut self, token: &str) -> OperationResult<bool> {
+        let Some(token_id) = self.inverted_index.get_token(token) else {
+            return Ok(false);
+        };
+        let query = ParsedQuery {
+            tokens: vec![Some(token_id)],
+        };
+        let affected_points: Vec<_> = self.inverted_index.filter(&query).collect();
+
+        if !self.inverted_index.remove_token(token)? {
+            return Ok(false);
+        }
+
+        // Persist documents without the removed token
+        for idx in affected_points {
+            let db_idx = Self::store_key(&idx);
+            let mut tokens = Self::deserialize_document(&self.db_wrapper.get(&db_idx)?)?;
+            tokens.remove(token);
+            let db_document = self.serialize_document_tokens(tokens)?;
+            self.db_wrapper.put(db_idx, db_document)?;
+        }
+
+        Ok(true)
+    }
+
+    /// Remove all points for which `keep` returns false, e.g. to reconcile the index with the
+    /// points still present in the segment
+    ///
+    /// Returns the number of removed points.
+    pub fn retain(&mut self, keep: impl Fn(PointOffsetType) -> bool) -> OperationResult<usize> {
+        // ascending, as indexed points are
+        let removed: Vec<_> = self
+            .inverted_index
+            .indexed_points()
+            .filter(|&idx| !keep(idx))
+            .collect();
+        self.inverted_index
+            .retain(|idx| removed.binary_search(&idx).is_err());
+
+        for idx in &removed {
+            self.db_wrapper.remove(Self::store_key(idx))?;
+        }
+        Ok(removed.len())
+    }
+
+    /// Replace the index with one imported from a pre-built term dictionary, e.g. when migrating
+    /// from another system, see [`MutableInvertedIndex::load_from_postings`]
+    ///
+    /// Token IDs are kept as given. Documents are stored by their tokens, replacing all stored
+    /// documents, so that the imported index is loaded as usual.
+    pub fn import_postings(
+        &mut self,
+        vocab: HashMap<String, TokenId>,
+        postings: Vec<Option<PostingList>>,
+        point_to_docs: Vec<Option<Document>>,
+    ) -> OperationResult<()> {
+        let index = MutableInvertedIndex::load_from_postings(vocab, postings, point_to_docs)?;
+        self.replace_index(InvertedIndex::Mutable(index))
+    }
+
+    /// Apply `normalize` to every indexed term, e.g. to migrate to case-insensitive matching
+    /// without reading payloads again, see [`MutableInvertedIndex::reindex_with`]
+    ///
+    /// Stored documents are replaced by their normalized tokens. Only an appendable index keeps
+    /// the documents to reindex.
+    pub fn reindex_with(&mut self, normalize: impl Fn(&str) -> String) -> OperationResult<()> {
+        let index = self.inverted_index.reindex_with(normalize)?;
+        self.replace_index(index)
+    }
+
+    /// Replace the index with `index`, a mutable index with documents, and store its documents
+    /// instead of all stored documents
+    ///
+    /// Token offsets are dropped, as they don't match the tokens of `index` anymore.
+    fn replace_index(&mut self, index: InvertedIndex) -> OperationResult<()> {
+        self.db_wrapper.recreate_column_family()?;
+        for (idx, tokens) in index.documents()? {
+            let tokens = tokens
+                .iter()
+                .filter_map(|&token_id| index.get_token_text(token_id))
+                .map(str::to_owned)
+                .collect();
+            let db_document = self.serialize_document_tokens(tokens, TokenOffsets::new())?;
+            self.db_wrapper.put(Self::store_key(&idx), db_document)?;
+        }
+
+        match index {
+            InvertedIndex::Mutable(index) => self.inverted_index.replace_with(index)?,
+            index => self.inverted_index = index,
+        }
+        Ok(())
+    }
+
+    /// Whether at least `threshold` of document slots were emptied by removals, so that the
+    /// index is worth rebuilding, see [`InvertedIndex::should_compact`]
+    pub fn should_compact(&self, threshold: f32) -> bool {
+        self.inverted_index.should_compact(threshold)
+    }
+
+    /// Check internal consistency of the loaded index
+    pub fn validate(&self) -> OperationResult<()> {
+        self.inverted_index.validate()
+    }
+
+    /// Number of tokens not indexed, because the vocabulary was full
+    pub fn dropped_token_count(&self) -> usize {
+        self.inverted_index.dropped_token_count()
+    }
+
+    pub fn get_telemetry_data(&self, detail: TelemetryDetail) -> PayloadIndexTelemetry {
+        let text_index = (detail.level >= DetailsLevel::Level2).then(|| TextIndexTelemetry {
+            vocabulary_size: self.inverted_index.vocabulary_size(),
+            postings_count: self.inverted_index.postings_count(),
+            memory_usage_bytes: self.inverted_index.memory_usage(),
+            is_appendable: self.inverted_index.is_appendable(),
+        });
+
         PayloadIndexTelemetry {
             field_name: None,
             points_values_count: self.inverted_index.points_count(),
             points_count: self.inverted_index.points_count(),
             histogram_bucket_size: None,
+            text_index,
         }
     }
 
@@ -92,13 +341,195 @@ impl FullTextIndex {
     }
 
     pub fn parse_query(&self, text: &str) -> ParsedQuery {
-        let mut tokens = HashSet::new();
+        let mut tokens = BTreeSet::new();
         Tokenizer::tokenize_query(text, &self.config, |token| {
-            tokens.insert(self.inverted_index.get_token(token));
+            tokens.insert(token.to_string());
         });
-        ParsedQuery {
-            tokens: tokens.into_iter().collect(),
-        }
+        self.inverted_index.parse_query(&tokens)
+    }
+
+    /// Number of distinct tokens in this index
+    pub fn vocabulary_size(&self) -> usize {
+        self.inverted_index.vocabulary_size()
+    }
+
+    /// Parse query for ranking, with a neutral boost for every token
+    pub fn parse_scored_query(&self, text: &str) -> ScoredQuery {
+        self.parse_query(text).into()
+    }
+
+    /// Set synonyms to expand query tokens with, e.g. `"car"` to also match `"automobile"`
+    ///
+    /// Synonyms are only applied at query time by
+    /// [`FullTextIndex::parse_query_with_synonyms`], indexing is not affected. Keys and synonyms
+    /// are matched against tokens as produced by the tokenizer.
+    pub fn set_synonyms(&mut self, synonyms: HashMap<String, Vec<String>>) {
+        self.synonyms = synonyms;
+    }
+
+    /// Parse a query, expanding every token into a clause of the token and its synonyms
+    ///
+    /// A document matches if it contains every token of the query, or any of its synonyms.
+    /// Synonyms not in the vocabulary contribute nothing.
+    pub fn parse_query_with_synonyms(&self, text: &str) -> CnfQuery {
+        let mut clauses = Vec::new();
+        let mut seen = HashSet::new();
+        Tokenizer::tokenize_query(text, &self.config, |token| {
+            if !seen.insert(token.to_string()) {
+                return;
+            }
+            let synonyms = self.synonyms.get(token).into_iter().flatten();
+            let clause: HashSet<_> = std::iter::once(token)
+                .chain(synonyms.map(String::as_str))
+                .map(|token| self.inverted_index.get_token(token))
+                .collect();
+            clauses.push(clause.into_iter().collect());
+        });
+        CnfQuery { clauses }
+    }
+
+    /// Points matching `query` in ascending order, see [`InvertedIndex::filter_cnf`]
+    pub fn filter_cnf(&self, query: &CnfQuery) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        self.inverted_index.filter_cnf(query)
+    }
+
+    /// Parse a query in conjunctive normal form
+    ///
+    /// Every clause matches documents containing any of its tokens, and a document must match
+    /// all clauses.
+    pub fn parse_cnf_query(&self, clauses: &[&str]) -> CnfQuery {
+        let clauses = clauses
+            .iter()
+            .map(|clause| {
+                let mut tokens = HashSet::new();
+                Tokenizer::tokenize_query(clause, &self.config, |token| {
+                    tokens.insert(self.inverted_index.get_token(token));
+                });
+                tokens.into_iter().collect()
+            })
+            .collect();
+        CnfQuery { clauses }
+    }
+
+    pub fn estimate_cnf_cardinality(
+        &self,
+        query: &CnfQuery,
+        condition: &FieldCondition,
+    ) -> CardinalityEstimation {
+        self.inverted_index
+            .estimate_cnf_cardinality(query, condition)
+    }
+
+    /// Expected fraction of points matching `query`, see [`InvertedIndex::estimate_selectivity`]
+    pub fn estimate_selectivity(&self, query: &ParsedQuery) -> f64 {
+        self.inverted_index.estimate_selectivity(query)
+    }
+
+    /// Number of tokens per posting length bucket, see
+    /// [`InvertedIndex::posting_length_histogram`]
+    pub fn posting_length_histogram(&self, buckets: &[usize]) -> Vec<usize> {
+        self.inverted_index.posting_length_histogram(buckets)
+    }
+
+    /// Token that should drive the intersection of `query`, with its posting length, see
+    /// [`InvertedIndex::best_primary_token`]
+    pub fn best_primary_token(&self, query: &ParsedQuery) -> Option<(TokenId, usize)> {
+        self.inverted_index.best_primary_token(query)
+    }
+
+    /// Indexed tokens similar to `term`, see [`InvertedIndex::suggest`]
+    pub fn suggest(&self, term: &str, max_distance: u8, limit: usize) -> Vec<(String, u8)> {
+        self.inverted_index.suggest(term, max_distance, limit)
+    }
+
+    /// Indexed tokens similar to `term` within `budget`, and whether the budget truncated them,
+    /// see [`InvertedIndex::suggest_with_budget`]
+    pub fn suggest_with_budget(
+        &self,
+        term: &str,
+        max_distance: u8,
+        limit: usize,
+        budget: ExpansionBudget,
+    ) -> (Vec<(String, u8)>, bool) {
+        self.inverted_index
+            .suggest_with_budget(term, max_distance, limit, budget)
+    }
+
+    /// Resolve a token ID, e.g. from a parsed query, back to the token string
+    pub fn get_token_text(&self, token_id: TokenId) -> Option<&str> {
+        self.inverted_index.get_token_text(token_id)
+    }
+
+    /// Points with any value in this field, see [`InvertedIndex::filter_exists`]
+    pub fn filter_exists(&self) -> impl Iterator<Item = PointOffsetType> + '_ {
+        self.inverted_index.filter_exists()
+    }
+
+    pub fn estimate_exists_cardinality(&self) -> CardinalityEstimation {
+        self.inverted_index.estimate_exists_cardinality()
+    }
+
+    /// Offsets of all points with a document in this index, in ascending order
+    pub fn indexed_points(&self) -> impl Iterator<Item = PointOffsetType> + '_ {
+        self.inverted_index.indexed_points()
+    }
+
+    /// The `n` most frequent tokens with their document frequency, e.g. for top keywords
+    pub fn top_terms(&self, n: usize) -> Vec<(String, usize)> {
+        self.inverted_index.top_terms(n)
+    }
+
+    /// Whether any point in the inclusive range `[lo, hi]` contains `token`, e.g. to combine a
+    /// text match with a range of point offsets, see [`InvertedIndex::contains_token_in_range`]
+    pub fn contains_token_in_range(
+        &self,
+        token: &str,
+        lo: PointOffsetType,
+        hi: PointOffsetType,
+    ) -> bool {
+        self.inverted_index
+            .get_token(token)
+            .is_some_and(|token_id| {
+                self.inverted_index
+                    .contains_token_in_range(token_id, lo, hi)
+            })
+    }
+
+    /// Token IDs with their posting length, shortest first, e.g. to pick a driving clause
+    pub fn postings_by_length(&self) -> Vec<(TokenId, usize)> {
+        self.inverted_index.postings_by_length()
+    }
+
+    /// Projected memory usage in bytes after compaction, to decide whether it is worthwhile
+    pub fn estimate_memory_after_compaction(&self) -> usize {
+        self.inverted_index.estimate_memory_after_compaction()
+    }
+
+    /// Distribution of the number of distinct tokens per indexed point
+    pub fn document_length_stats(&self) -> DocLengthStats {
+        self.inverted_index.document_length_stats()
+    }
+
+    /// Export the index as a sparse term-document matrix, see [`InvertedIndex::export_sparse`]
+    pub fn export_sparse(&self) -> impl Iterator<Item = (TokenId, PointOffsetType)> + '_ {
+        self.inverted_index.export_sparse()
+    }
+
+    /// Iterate over the token IDs of all indexed points, e.g. to reindex with a new tokenizer
+    pub fn documents(
+        &self,
+    ) -> OperationResult<impl Iterator<Item = (PointOffsetType, &[TokenId])> + '_> {
+        self.inverted_index.documents()
+    }
+
+    /// Jaccard similarity of the tokens of two indexed points, e.g. to find near-duplicates
+    pub fn document_similarity(&self, a: PointOffsetType, b: PointOffsetType) -> Option<f32> {
+        self.inverted_index.document_similarity(a, b)
+    }
+
+    /// Whether all tokens of point `a` are also in point `b`, e.g. to match tags against a tag set
+    pub fn is_subset(&self, a: PointOffsetType, b: PointOffsetType) -> Option<bool> {
+        self.inverted_index.is_subset(a, b)
     }
 
     pub fn parse_document(&self, text: &str) -> Document {
@@ -117,6 +548,19 @@ impl FullTextIndex {
         self.inverted_index.filter(&parsed_query)
     }
 
+    /// Points matching `parsed_query` in descending order, e.g. to paginate newest points first
+    pub fn filter_desc(
+        &self,
+        parsed_query: &ParsedQuery,
+    ) -> Box<dyn Iterator<Item = PointOffsetType> + '_> {
+        self.inverted_index.filter_desc(parsed_query)
+    }
+
+    /// Number of points matching `parsed_query`, e.g. for facet counts
+    pub fn count_matches(&self, parsed_query: &ParsedQuery) -> usize {
+        self.inverted_index.count_matches(parsed_query)
+    }
+
     pub fn values_count(&self, point_id: PointOffsetType) -> usize {
         self.inverted_index.values_count(point_id)
     }
@@ -128,6 +572,30 @@ impl FullTextIndex {
     pub fn check_match(&self, parsed_query: &ParsedQuery, point_id: PointOffsetType) -> bool {
         self.inverted_index.check_match(parsed_query, point_id)
     }
+
+    /// Rank documents matching any of the query tokens, see [`InvertedIndex::score`]
+    pub fn score(&self, query: &ScoredQuery) -> Vec<(PointOffsetType, f32)> {
+        self.inverted_index.score(query)
+    }
+
+    /// Rank documents across several text fields of the same segment, e.g. title and body
+    ///
+    /// Every `(index, weight, query)` field parses its own query, see [`MultiFieldScorer`].
+    pub fn score_fields(
+        fields: &[(&FullTextIndex, f32, &str)],
+    ) -> OperationResult<Vec<(PointOffsetType, f32)>> {
+        let scorer = MultiFieldScorer::new(
+            fields
+                .iter()
+                .map(|&(index, weight, _query)| (&index.inverted_index, weight))
+                .collect(),
+        );
+        let queries = fields
+            .iter()
+            .map(|&(index, _weight, query)| index.parse_scored_query(query))
+            .collect();
+        scorer.score(queries)
+    }
 }
 
 impl ValueIndexer<String> for FullTextIndex {
@@ -144,15 +612,7 @@ impl ValueIndexer<String> for FullTextIndex {
             });
         }
 
-        let document = self.inverted_index.document_from_tokens(&tokens);
-        self.inverted_index.index_document(idx, document)?;
-
-        let db_idx = Self::store_key(&idx);
-        let db_document = self.serialize_document_tokens(tokens)?;
-
-        self.db_wrapper.put(db_idx, db_document)?;
-
-        Ok(())
+        self.index_tokens(idx, tokens, TokenOffsets::new())
     }
 
     fn get_value(&self, value: &Value) -> Option<String> {
@@ -171,28 +631,123 @@ impl ValueIndexer<String> for FullTextIndex {
     }
 }
 
-impl PayloadFieldIndex for FullTextIndex {
-    fn count_indexed_points(&self) -> usize {
-        self.inverted_index.points_count()
-    }
-
-    fn load(&mut self) -> OperationResult<bool> {
+impl FullTextIndex {
+    /// Build the inverted index from stored documents with `build`
+    ///
+    /// Returns `false` if nothing was stored for this field yet.
+    fn load_with(
+        &mut self,
+        build: impl FnOnce(
+            &mut InvertedIndex,
+            &mut dyn Iterator<Item = OperationResult<(PointOffsetType, BTreeSet<String>)>>,
+        ) -> OperationResult<()>,
+    ) -> OperationResult<bool> {
         if !self.db_wrapper.has_column_family()? {
             return Ok(false);
         };
 
         let db = self.db_wrapper.lock_db();
-        let i = db.iter()?.map(|(key, value)| {
+        let mut documents = db.iter()?.map(|(key, value)| -> OperationResult<_> {
             let idx = Self::restore_key(&key);
             let tokens = Self::deserialize_document(&value)?;
             Ok((idx, tokens))
         });
-        self.inverted_index.build_index(i)?;
+        build(&mut self.inverted_index, &mut documents)?;
 
         Ok(true)
     }
 
+    /// Load the index like [`PayloadFieldIndex::load`], but give up once `stopped` is set
+    ///
+    /// Documents are indexed at once instead of in batches, see
+    /// [`InvertedIndex::build_index_cancellable`].
+    pub fn load_cancellable(&mut self, stopped: &AtomicBool) -> OperationResult<bool> {
+        self.load_with(|index, documents| index.build_index_cancellable(documents, stopped))
+    }
+
+    /// Load the index like [`PayloadFieldIndex::load`], and collect token statistics of the
+    /// stored documents on the way, e.g. to precompute IDF
+    ///
+    /// Returns `None` if nothing was stored for this field yet. Documents are indexed at once
+    /// instead of in batches, see [`InvertedIndex::build_index_collecting`].
+    pub fn load_with_token_stats(&mut self) -> OperationResult<Option<TokenStats>> {
+        let mut stats = None;
+        self.load_with(|index, documents| {
+            stats = Some(index.build_index_collecting(documents)?);
+            Ok(())
+        })?;
+        Ok(stats)
+    }
+
+    /// Index and store `tokens` of point `idx`, with their `offsets` if known
+    fn index_tokens(
+        &mut self,
+        idx: PointOffsetType,
+        tokens: BTreeSet<String>,
+        offsets: TokenOffsets,
+    ) -> OperationResult<()> {
+        let (document, new_tokens) = self.inverted_index.document_from_tokens(&tokens);
+        if !new_tokens.is_empty() {
+            log::trace!(
+                "Point {idx} added {} new tokens to full text index vocabulary",
+                new_tokens.len(),
+            );
+        }
+        self.inverted_index.index_document(idx, document)?;
+
+        let db_idx = Self::store_key(&idx);
+        let db_document = self.serialize_document_tokens(tokens, offsets)?;
+
+        self.db_wrapper.put(db_idx, db_document)?;
+
+        Ok(())
+    }
+
+    /// Index `text` as the only value of point `idx`, keeping the byte ranges of its tokens
+    ///
+    /// Ranges are the foundation for highlighting matches in search results, see
+    /// [`FullTextIndex::token_offsets`]. They are stored with the document, so they are not held
+    /// in memory, and survive a reload.
+    pub fn index_text_with_offsets(
+        &mut self,
+        idx: PointOffsetType,
+        text: &str,
+    ) -> OperationResult<()> {
+        self.remove_point(idx)?;
+        let offsets = Tokenizer::tokenize_doc_with_offsets(text, &self.config);
+        let tokens = offsets.iter().map(|(token, _)| token.clone()).collect();
+        self.index_tokens(idx, tokens, offsets)
+    }
+
+    /// Tokens of point `idx` with their byte ranges in the text, in order of appearance
+    ///
+    /// Read from the stored document, only available for points indexed with
+    /// [`FullTextIndex::index_text_with_offsets`].
+    pub fn token_offsets(&self, idx: PointOffsetType) -> OperationResult<Option<TokenOffsets>> {
+        let offsets = self
+            .db_wrapper
+            .get_pinned(&Self::store_key(&idx), Self::deserialize_document_offsets)?
+            .transpose()?;
+        Ok(offsets.filter(|offsets| !offsets.is_empty()))
+    }
+}
+
+impl PayloadFieldIndex for FullTextIndex {
+    fn count_indexed_points(&self) -> usize {
+        self.inverted_index.points_count()
+    }
+
+    fn load(&mut self) -> OperationResult<bool> {
+        self.load_with(|index, documents| index.build_index_batched(documents, LOAD_BATCH_SIZE))
+    }
+
     fn clear(self) -> OperationResult<()> {
+        #[cfg(feature = "mmap-vocab")]
+        if let Some(vocab_path) = self.inverted_index.vocab_path() {
+            if vocab_path.exists() {
+                std::fs::remove_file(vocab_path)?;
+            }
+        }
         self.db_wrapper.remove_column_family()
     }
 
@@ -238,7 +793,7 @@ impl PayloadFieldIndex for FullTextIndex {
 #[cfg(test)]
 mod tests {
     use rstest::rstest;
-    use tempfile::Builder;
+    use tempfile::{Builder, TempDir};
 
     use super::*;
     use crate::common::rocksdb_wrapper::open_db_with_existing_cf;
@@ -249,6 +804,188 @@ mod tests {
         FieldCondition::new_match(path("text"), Match::new_text(text))
     }
 
+    fn word_config() -> TextIndexParams {
+        TextIndexParams {
+            r#type: TextIndexType::Text,
+            tokenizer: TokenizerType::Word,
+            min_token_len: None,
+            max_token_len: None,
+            lowercase: None,
+        }
+    }
+
+    /// Index `payloads` as points `0..`, then reload them as immutable index if `immutable`
+    ///
+    /// The returned directory holds the storage, it must be kept while the index is used.
+    fn build_index(payloads: &[Value], immutable: bool) -> (TempDir, FullTextIndex) {
+        let temp_dir = Builder::new().prefix("test_dir").tempdir().unwrap();
+        let config = word_config();
+        let db = open_db_with_existing_cf(&temp_dir.path().join("test_db")).unwrap();
+        let mut index = FullTextIndex::new(db.clone(), config.clone(), "text", true);
+        index.recreate().unwrap();
+        for (idx, payload) in payloads.iter().enumerate() {
+            index.add_point(idx as PointOffsetType, &[payload]).unwrap();
+        }
+        if immutable {
+            index = FullTextIndex::new(db, config, "text", false);
+            assert!(index.load().unwrap());
+        }
+        (temp_dir, index)
+    }
+
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    fn test_synonyms(#[case] immutable: bool) {
+        let payloads = [
+            serde_json::json!("A red car"),
+            serde_json::json!("A red automobile"),
+            serde_json::json!("A blue automobile"),
+            serde_json::json!("A red bicycle"),
+        ];
+
+        let (_temp_dir, mut index) = build_index(&payloads, immutable);
+
+        let search = |index: &FullTextIndex, text: &str| {
+            let query = index.parse_query_with_synonyms(text);
+            index.filter_cnf(&query).collect::<Vec<_>>()
+        };
+
+        // Without synonyms only exact tokens match
+        assert_eq!(search(&index, "car"), vec![0]);
+
+        index.set_synonyms(HashMap::from([(
+            "car".to_string(),
+            vec!["automobile".to_string(), "unknown".to_string()],
+        )]));
+        assert_eq!(search(&index, "car"), vec![0, 1, 2]);
+        assert_eq!(search(&index, "red car"), vec![0, 1]);
+
+        // Synonyms are not symmetric, and don't affect plain queries
+        assert_eq!(search(&index, "automobile"), vec![1, 2]);
+        let parsed_query = index.parse_query("car");
+        assert_eq!(
+            index
+                .inverted_index
+                .filter(&parsed_query)
+                .collect::<Vec<_>>(),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn test_index_text_with_offsets() {
+        let temp_dir = Builder::new().prefix("test_dir").tempdir().unwrap();
+        let db = open_db_with_existing_cf(&temp_dir.path().join("test_db")).unwrap();
+        let mut index = FullTextIndex::new(db.clone(), word_config(), "text", true);
+        index.recreate().unwrap();
+
+        let text = "Schöne Grüße aus München";
+        index.index_text_with_offsets(0, text).unwrap();
+        index
+            .add_point(1, &[&serde_json::json!("München")])
+            .unwrap();
+
+        // Offsets are indexed like any other text
+        let query = index.parse_query("münchen");
+        assert_eq!(
+            index.inverted_index.filter(&query).collect::<Vec<_>>(),
+            vec![0, 1],
+        );
+
+        let offsets = index.token_offsets(0).unwrap().unwrap();
+        let highlights: Vec<_> = offsets
+            .iter()
+            .map(|(token, range)| (token.as_str(), &text[range.clone()]))
+            .collect();
+        assert_eq!(
+            highlights,
+            vec![
+                ("schöne", "Schöne"),
+                ("grüße", "Grüße"),
+                ("aus", "aus"),
+                ("münchen", "München"),
+            ],
+        );
+        assert_eq!(index.token_offsets(1).unwrap(), None);
+
+        // Offsets are stored with the document, so they survive a reload
+        let mut reloaded = FullTextIndex::new(db, word_config(), "text", true);
+        assert!(reloaded.load().unwrap());
+        assert_eq!(reloaded.token_offsets(0).unwrap(), Some(offsets));
+
+        index.remove_point(0).unwrap();
+        assert_eq!(index.token_offsets(0).unwrap(), None);
+    }
+
+    #[cfg(feature = "mmap-vocab")]
+    #[test]
+    fn test_mmap_vocab() {
+        let temp_dir = Builder::new().prefix("test_dir").tempdir().unwrap();
+        let config = word_config();
+        let db = open_db_with_existing_cf(&temp_dir.path().join("test_db")).unwrap();
+        let mut index = FullTextIndex::new(db.clone(), config.clone(), "text", true);
+        index.recreate().unwrap();
+        index
+            .add_point(0, &[&serde_json::json!("the quick brown fox")])
+            .unwrap();
+        index
+            .add_point(1, &[&serde_json::json!("the lazy dog")])
+            .unwrap();
+
+        // The dictionary is written on the first load and reused on the next one
+        let vocab_path = FullTextIndex::vocab_path(temp_dir.path(), "text");
+        for _ in 0..2 {
+            let mut index = FullTextIndex::new_with_mmap_vocab(
+                db.clone(),
+                config.clone(),
+                "text",
+                vocab_path.clone(),
+            );
+            assert!(index.load().unwrap());
+            assert!(vocab_path.exists());
+
+            let query = index.parse_query("lazy dog");
+            assert_eq!(
+                index.inverted_index.filter(&query).collect::<Vec<_>>(),
+                vec![1],
+            );
+            assert_eq!(index.count_indexed_points(), 2);
+        }
+
+        let mut index = FullTextIndex::new_with_mmap_vocab(db, config, "text", vocab_path.clone());
+        assert!(index.load().unwrap());
+        index.clear().unwrap();
+        assert!(!vocab_path.exists());
+    }
+
+    #[rstest]
+    #[case(true)]
+    #[case(false)]
+    fn test_telemetry_details(#[case] immutable: bool) {
+        let payloads = [
+            serde_json::json!("A red car"),
+            serde_json::json!("A blue car"),
+        ];
+
+        let (_temp_dir, index) = build_index(&payloads, immutable);
+
+        // Internal statistics are only reported at the highest detail level
+        let telemetry = index.get_telemetry_data(TelemetryDetail::default());
+        assert_eq!(telemetry.points_count, 2);
+        assert!(telemetry.text_index.is_none());
+
+        let detail = TelemetryDetail {
+            level: DetailsLevel::Level2,
+            histograms: false,
+        };
+        let text_index = index.get_telemetry_data(detail).text_index.unwrap();
+        assert_eq!(text_index.vocabulary_size, 4);
+        assert_eq!(text_index.postings_count, 6);
+        assert!(text_index.memory_usage_bytes > 0);
+        assert_eq!(text_index.is_appendable, !immutable);
+    }
+
     #[rstest]
     #[case(true)]
     #[case(false)]
@@ -265,13 +1002,7 @@ mod tests {
         ];
 
         let temp_dir = Builder::new().prefix("test_dir").tempdir().unwrap();
-        let config = TextIndexParams {
-            r#type: TextIndexType::Text,
-            tokenizer: TokenizerType::Word,
-            min_token_len: None,
-            max_token_len: None,
-            lowercase: None,
-        };
+        let config = word_config();
 
         {
             let db = open_db_with_existing_cf(&temp_dir.path().join("test_db")).unwrap();
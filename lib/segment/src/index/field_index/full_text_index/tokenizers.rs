@@ -1,3 +1,6 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
 use charabia::Tokenize;
 
 use crate::data_types::text_index::{TextIndexParams, TokenizerType};
@@ -72,6 +75,24 @@ impl MultilingualTokenizer {
             }
         });
     }
+
+    fn tokenize_with_offsets<C: FnMut(&str, Range<usize>)>(text: &str, mut callback: C) {
+        text.tokenize().for_each(|token| {
+            if token.is_word() {
+                callback(token.lemma(), token.byte_start..token.byte_end);
+            }
+        });
+    }
+}
+
+/// Byte range of `token` in `text`, `token` must be a subslice of `text`
+fn subslice_range(text: &str, token: &str) -> Range<usize> {
+    let start = token.as_ptr() as usize - text.as_ptr() as usize;
+    debug_assert!(
+        start + token.len() <= text.len(),
+        "token must be a subslice of text"
+    );
+    start..start + token.len()
 }
 
 pub struct Tokenizer;
@@ -82,28 +103,35 @@ impl Tokenizer {
         mut callback: C,
     ) -> impl FnMut(&str) + 'a {
         move |token: &str| {
-            if config
-                .min_token_len
-                .map(|min_len| token.len() < min_len && token.chars().count() < min_len)
-                .unwrap_or(false)
-            {
-                return;
-            }
-            if config
-                .max_token_len
-                .map(|max_len| token.len() > max_len && token.chars().count() > max_len)
-                .unwrap_or(false)
-            {
-                return;
-            }
-            if config.lowercase.unwrap_or(true) {
-                callback(&token.to_lowercase());
-            } else {
-                callback(token);
+            if let Some(token) = Self::filter_token(config, token) {
+                callback(&token);
             }
         }
     }
 
+    /// Apply length limits and normalization of `config` to a single token
+    fn filter_token<'a>(config: &TextIndexParams, token: &'a str) -> Option<Cow<'a, str>> {
+        if config
+            .min_token_len
+            .map(|min_len| token.len() < min_len && token.chars().count() < min_len)
+            .unwrap_or(false)
+        {
+            return None;
+        }
+        if config
+            .max_token_len
+            .map(|max_len| token.len() > max_len && token.chars().count() > max_len)
+            .unwrap_or(false)
+        {
+            return None;
+        }
+        if config.lowercase.unwrap_or(true) {
+            Some(Cow::Owned(token.to_lowercase()))
+        } else {
+            Some(Cow::Borrowed(token))
+        }
+    }
+
     pub fn tokenize_doc<C: FnMut(&str)>(text: &str, config: &TextIndexParams, mut callback: C) {
         let token_filter = Self::doc_token_filter(config, &mut callback);
         match config.tokenizer {
@@ -119,6 +147,38 @@ impl Tokenizer {
         }
     }
 
+    /// Tokenize a document like [`Tokenizer::tokenize_doc`], keeping the byte range of every
+    /// token in `text`
+    ///
+    /// Ranges are always on char boundaries of `text`. Tokens are normalized like indexed tokens,
+    /// e.g. lowercased, so a token may differ from the text it was taken from.
+    pub fn tokenize_doc_with_offsets(
+        text: &str,
+        config: &TextIndexParams,
+    ) -> Vec<(String, Range<usize>)> {
+        let mut tokens = Vec::new();
+        let mut push_token = |token: &str, range: Range<usize>| {
+            if let Some(token) = Self::filter_token(config, token) {
+                tokens.push((token.into_owned(), range));
+            }
+        };
+        let mut push_subslice = |token: &str| push_token(token, subslice_range(text, token));
+        match config.tokenizer {
+            TokenizerType::Whitespace => WhiteSpaceTokenizer::tokenize(text, &mut push_subslice),
+            TokenizerType::Word => WordTokenizer::tokenize(text, &mut push_subslice),
+            TokenizerType::Multilingual => {
+                MultilingualTokenizer::tokenize_with_offsets(text, &mut push_token)
+            }
+            TokenizerType::Prefix => PrefixTokenizer::tokenize(
+                text,
+                config.min_token_len.unwrap_or(1),
+                config.max_token_len.unwrap_or(usize::MAX),
+                &mut push_subslice,
+            ),
+        }
+        tokens
+    }
+
     pub fn tokenize_query<C: FnMut(&str)>(text: &str, config: &TextIndexParams, mut callback: C) {
         let token_filter = Self::doc_token_filter(config, &mut callback);
         match config.tokenizer {
@@ -267,4 +327,59 @@ mod tests {
         assert_eq!(tokens.get(5), Some(&"ми".to_owned()));
         assert_eq!(tokens.get(6), Some(&"мир".to_owned()));
     }
+
+    #[test]
+    fn test_tokenize_doc_with_offsets() {
+        let text = "Grüße, Мир! naïve 日本";
+        for tokenizer in [TokenizerType::Word, TokenizerType::Whitespace] {
+            let config = TextIndexParams {
+                r#type: TextIndexType::Text,
+                tokenizer,
+                min_token_len: None,
+                max_token_len: None,
+                lowercase: Some(true),
+            };
+            let tokens = Tokenizer::tokenize_doc_with_offsets(text, &config);
+
+            let mut expected = Vec::new();
+            Tokenizer::tokenize_doc(text, &config, |token| expected.push(token.to_owned()));
+            assert_eq!(
+                tokens.iter().map(|(token, _)| token).collect::<Vec<_>>(),
+                expected.iter().collect::<Vec<_>>(),
+            );
+
+            // Ranges slice back to the original, not normalized, tokens
+            for (token, range) in &tokens {
+                assert!(text.is_char_boundary(range.start) && text.is_char_boundary(range.end));
+                assert_eq!(&text[range.clone()].to_lowercase(), token);
+            }
+        }
+
+        let config = TextIndexParams {
+            r#type: TextIndexType::Text,
+            tokenizer: TokenizerType::Word,
+            min_token_len: None,
+            max_token_len: None,
+            lowercase: Some(false),
+        };
+        let tokens = Tokenizer::tokenize_doc_with_offsets(text, &config);
+        let slices: Vec<_> = tokens
+            .iter()
+            .map(|(_, range)| &text[range.clone()])
+            .collect();
+        assert_eq!(slices, vec!["Grüße", "Мир", "naïve", "日本"]);
+        assert_eq!(tokens[1].1, 9..15);
+
+        // Prefixes start at the beginning of their word
+        let config = TextIndexParams {
+            r#type: TextIndexType::Text,
+            tokenizer: TokenizerType::Prefix,
+            min_token_len: Some(1),
+            max_token_len: Some(2),
+            lowercase: Some(false),
+        };
+        let tokens = Tokenizer::tokenize_doc_with_offsets("Ab Мир", &config);
+        let ranges: Vec<_> = tokens.into_iter().map(|(_, range)| range).collect();
+        assert_eq!(ranges, vec![0..1, 0..2, 3..5, 3..7]);
+    }
 }
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+#[cfg(feature = "mmap-vocab")]
+use std::path::Path;
+use std::sync::Arc;
+
+use super::inverted_index::TokenId;
+#[cfg(feature = "mmap-vocab")]
+use super::mmap_vocab::MmapVocab;
+#[cfg(feature = "mmap-vocab")]
+use crate::common::operation_error::OperationResult;
+
+/// Vocabulary of an inverted index, mapping tokens to their IDs
+///
+/// Token strings are stored as `Arc<str>`, without spare capacity, and are only allocated once
+/// when a new token is added. Lookups of existing tokens don't allocate.
+///
+/// The same allocation is shared with a list indexed by token ID, for reverse lookups.
+///
+/// Optionally the number of tokens is capped, to bound memory usage on high-cardinality fields.
+/// Once full, new tokens are dropped and counted instead.
+///
+/// With the `mmap-vocab` feature, tokens can be offloaded into a memory-mapped dictionary, see
+/// [`Vocab::offload`]. Tokens added afterwards are kept in memory, with IDs following the
+/// dictionary.
+#[derive(Default, Debug, Clone)]
+pub struct Vocab {
+    tokens: HashMap<Arc<str>, TokenId>,
+    texts: Vec<Arc<str>>,
+    max_len: Option<usize>,
+    dropped_tokens: usize,
+    /// Tokens with IDs `0..dictionary.len()`, not resident in memory
+    #[cfg(feature = "mmap-vocab")]
+    dictionary: Option<Arc<MmapVocab>>,
+}
+
+impl Vocab {
+    /// Don't add new tokens once the vocabulary holds `max_len` tokens
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = Some(max_len);
+    }
+
+    pub fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    /// Number of times a new token was not added, because the vocabulary was full
+    pub fn dropped_tokens(&self) -> usize {
+        self.dropped_tokens
+    }
+
+    pub fn len(&self) -> usize {
+        self.dictionary_len() + self.tokens.len()
+    }
+
+    /// Number of tokens held in memory, not in the memory-mapped dictionary
+    #[cfg(feature = "mmap-vocab")]
+    pub fn resident_len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Number of tokens in the memory-mapped dictionary, which come before in-memory tokens
+    fn dictionary_len(&self) -> usize {
+        #[cfg(feature = "mmap-vocab")]
+        if let Some(dictionary) = &self.dictionary {
+            return dictionary.len();
+        }
+        0
+    }
+
+    /// Write all tokens into a memory-mapped dictionary at `path`, and drop them from memory
+    ///
+    /// Lookups go through the dictionary afterwards, only touching the pages they need.
+    #[cfg(feature = "mmap-vocab")]
+    pub fn offload(&mut self, path: &Path) -> OperationResult<()> {
+        MmapVocab::create(path, self.iter())?;
+        let max_len = self.max_len;
+        *self = Self::open_dictionary(path)?;
+        self.max_len = max_len;
+        Ok(())
+    }
+
+    /// Open a vocabulary from a dictionary written by [`Vocab::offload`], without reading tokens
+    #[cfg(feature = "mmap-vocab")]
+    pub fn open_dictionary(path: &Path) -> OperationResult<Self> {
+        Ok(Self {
+            dictionary: Some(Arc::new(MmapVocab::open(path)?)),
+            ..Default::default()
+        })
+    }
+
+    /// Move tokens of the memory-mapped dictionary back into memory
+    #[cfg(feature = "mmap-vocab")]
+    fn materialize(&mut self) {
+        let Some(dictionary) = self.dictionary.take() else {
+            return;
+        };
+
+        let mut texts: Vec<Arc<str>> = vec![Arc::from(""); dictionary.len()];
+        for (token, token_id) in dictionary.iter() {
+            let text: Arc<str> = token.into();
+            self.tokens.insert(text.clone(), token_id);
+            texts[token_id as usize] = text;
+        }
+        texts.append(&mut self.texts);
+        self.texts = texts;
+    }
+
+    /// Approximate heap memory held by the vocabulary, in bytes
+    ///
+    /// Token texts are shared between the map and the list, so they are only counted once.
+    pub fn memory_usage(&self) -> usize {
+        let texts_size: usize = self.texts.iter().map(|text| text.len()).sum();
+        texts_size
+            + self.texts.capacity() * std::mem::size_of::<Arc<str>>()
+            + self.tokens.capacity() * std::mem::size_of::<(Arc<str>, TokenId)>()
+    }
+
+    pub fn get(&self, token: &str) -> Option<TokenId> {
+        if let Some(&token_id) = self.tokens.get(token) {
+            return Some(token_id);
+        }
+        #[cfg(feature = "mmap-vocab")]
+        if let Some(dictionary) = &self.dictionary {
+            return dictionary.get(token);
+        }
+        None
+    }
+
+    /// Get the token string of a token ID
+    pub fn get_text(&self, token_id: TokenId) -> Option<&str> {
+        #[cfg(feature = "mmap-vocab")]
+        if let Some(dictionary) = &self.dictionary {
+            if (token_id as usize) < dictionary.len() {
+                return dictionary.get_text(token_id);
+            }
+        }
+        let index = (token_id as usize).checked_sub(self.dictionary_len())?;
+        self.texts.get(index).map(|text| text.as_ref())
+    }
+
+    /// Get ID of the token, or assign the next free ID if it's not in the vocabulary yet
+    ///
+    /// Returns `None` if the token is new, but the vocabulary is full.
+    pub fn get_or_insert(&mut self, token: &str) -> Option<TokenId> {
+        if let Some(token_id) = self.get(token) {
+            return Some(token_id);
+        }
+
+        if self.max_len.is_some_and(|max_len| self.len() >= max_len) {
+            self.dropped_tokens += 1;
+            return None;
+        }
+
+        let next_token_id = self.len() as TokenId;
+        let text: Arc<str> = token.into();
+        self.tokens.insert(text.clone(), next_token_id);
+        self.texts.push(text);
+        Some(next_token_id)
+    }
+
+    /// Keep only tokens for which `keep` returns true, and renumber them consecutively
+    ///
+    /// Returns the new ID of every previous token ID, `None` for removed tokens. Remaining tokens
+    /// keep their relative order.
+    pub fn retain(&mut self, mut keep: impl FnMut(TokenId) -> bool) -> Vec<Option<TokenId>> {
+        #[cfg(feature = "mmap-vocab")]
+        self.materialize();
+
+        let texts = std::mem::take(&mut self.texts);
+        self.tokens.clear();
+
+        texts
+            .into_iter()
+            .enumerate()
+            .map(|(token_id, text)| {
+                if !keep(token_id as TokenId) {
+                    return None;
+                }
+                let new_token_id = self.texts.len() as TokenId;
+                self.tokens.insert(text.clone(), new_token_id);
+                self.texts.push(text);
+                Some(new_token_id)
+            })
+            .collect()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, TokenId)> + '_ {
+        let tokens = self
+            .tokens
+            .iter()
+            .map(|(token, &token_id)| (token.as_ref(), token_id));
+        self.dictionary_iter().chain(tokens)
+    }
+
+    #[cfg(feature = "mmap-vocab")]
+    fn dictionary_iter(&self) -> impl Iterator<Item = (&str, TokenId)> + '_ {
+        self.dictionary
+            .iter()
+            .flat_map(|dictionary| dictionary.iter())
+    }
+
+    #[cfg(not(feature = "mmap-vocab"))]
+    fn dictionary_iter(&self) -> impl Iterator<Item = (&str, TokenId)> + '_ {
+        std::iter::empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.tokens.clear();
+        self.texts.clear();
+        self.dropped_tokens = 0;
+        #[cfg(feature = "mmap-vocab")]
+        {
+            self.dictionary = None;
+        }
+    }
+
+    pub fn shrink_to_fit(&mut self) {
+        self.tokens.shrink_to_fit();
+        self.texts.shrink_to_fit();
+    }
+
+    /// Total number of bytes used by all token strings
+    #[cfg(test)]
+    pub fn tokens_size(&self) -> usize {
+        self.iter().map(|(token, _)| token.len()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vocab_shared_tokens_stored_once() {
+        let mut vocab = Vocab::default();
+
+        let first_document = ["quick", "brown", "fox"];
+        let second_document = ["lazy", "brown", "dog", "fox"];
+
+        let first_ids: Vec<_> = first_document
+            .iter()
+            .map(|token| vocab.get_or_insert(token).unwrap())
+            .collect();
+        let size_after_first = vocab.tokens_size();
+
+        let second_ids: Vec<_> = second_document
+            .iter()
+            .map(|token| vocab.get_or_insert(token).unwrap())
+            .collect();
+
+        // Shared tokens map to the same IDs
+        assert_eq!(first_ids[1], second_ids[1]);
+        assert_eq!(first_ids[2], second_ids[3]);
+
+        // Only new tokens add to the storage
+        assert_eq!(vocab.len(), 5);
+        assert_eq!(
+            size_after_first,
+            "quick".len() + "brown".len() + "fox".len()
+        );
+        assert_eq!(
+            vocab.tokens_size(),
+            size_after_first + "lazy".len() + "dog".len(),
+        );
+
+        assert_eq!(vocab.get("brown"), Some(first_ids[1]));
+        assert_eq!(vocab.get("cat"), None);
+    }
+
+    #[test]
+    fn test_vocab_max_len() {
+        let mut vocab = Vocab::default();
+        vocab.set_max_len(2);
+
+        assert_eq!(vocab.get_or_insert("quick"), Some(0));
+        assert_eq!(vocab.get_or_insert("brown"), Some(1));
+        assert_eq!(vocab.get_or_insert("fox"), None);
+        assert_eq!(vocab.get_or_insert("dog"), None);
+
+        // Existing tokens are still resolved
+        assert_eq!(vocab.get_or_insert("quick"), Some(0));
+        assert_eq!(vocab.len(), 2);
+        assert_eq!(vocab.dropped_tokens(), 2);
+    }
+
+    #[cfg(feature = "mmap-vocab")]
+    #[test]
+    fn test_vocab_offload() {
+        let dir = tempfile::Builder::new().prefix("vocab").tempdir().unwrap();
+        let path = dir.path().join("vocab.dat");
+
+        let mut vocab = Vocab::default();
+        for token in ["quick", "brown", "fox"] {
+            vocab.get_or_insert(token).unwrap();
+        }
+        vocab.offload(&path).unwrap();
+
+        // Tokens are only in the dictionary
+        assert_eq!(vocab.memory_usage(), 0);
+        assert_eq!(vocab.len(), 3);
+        assert_eq!(vocab.get("brown"), Some(1));
+        assert_eq!(vocab.get_text(2), Some("fox"));
+
+        // New tokens are kept in memory, after the dictionary
+        assert_eq!(vocab.get_or_insert("dog"), Some(3));
+        assert_eq!(vocab.get_or_insert("quick"), Some(0));
+        assert_eq!(vocab.get("dog"), Some(3));
+        assert_eq!(vocab.get_text(3), Some("dog"));
+        assert_eq!(vocab.get_text(4), None);
+        assert_eq!(vocab.iter().count(), 4);
+
+        // Retaining tokens moves the dictionary back into memory
+        let token_ids = vocab.retain(|token_id| token_id != 1);
+        assert_eq!(token_ids, vec![Some(0), None, Some(1), Some(2)]);
+        assert_eq!(vocab.get("fox"), Some(1));
+        assert_eq!(vocab.get_text(2), Some("dog"));
+
+        let reopened = Vocab::open_dictionary(&path).unwrap();
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(reopened.get("quick"), Some(0));
+    }
+
+    #[test]
+    fn test_vocab_retain() {
+        let mut vocab = Vocab::default();
+        for token in ["quick", "brown", "fox", "dog"] {
+            vocab.get_or_insert(token).unwrap();
+        }
+
+        let token_ids = vocab.retain(|token_id| token_id % 2 == 1);
+        assert_eq!(token_ids, vec![None, Some(0), None, Some(1)]);
+
+        assert_eq!(vocab.len(), 2);
+        assert_eq!(vocab.get("quick"), None);
+        assert_eq!(vocab.get("brown"), Some(0));
+        assert_eq!(vocab.get("dog"), Some(1));
+        assert_eq!(vocab.get_text(1), Some("dog"));
+        assert_eq!(vocab.get_or_insert("cat"), Some(2));
+    }
+}
@@ -226,6 +226,7 @@ impl GeoMapIndex {
             points_count: self.points_count(),
             points_values_count: self.points_values_count(),
             histogram_bucket_size: None,
+            text_index: None,
         }
     }
 
@@ -1,9 +1,11 @@
+use std::path::Path;
 use std::sync::Arc;
 
 use parking_lot::RwLock;
 use rocksdb::DB;
 
 use super::binary_index::BinaryIndex;
+use crate::data_types::text_index::TextIndexParams;
 use crate::index::field_index::full_text_index::text_index::FullTextIndex;
 use crate::index::field_index::geo_index::GeoMapIndex;
 use crate::index::field_index::map_index::MapIndex;
@@ -15,10 +17,13 @@ use crate::types::{
 };
 
 /// Selects index types based on field type
+///
+/// `path` is the payload index directory, for indexes which keep files next to the database.
 pub fn index_selector(
     field: &JsonPath,
     payload_schema: &PayloadFieldSchema,
     db: Arc<RwLock<DB>>,
+    path: &Path,
     is_appendable: bool,
 ) -> Vec<FieldIndex> {
     let field: String = field.to_string();
@@ -51,10 +56,11 @@ pub fn index_selector(
                 field,
                 is_appendable,
             ))],
-            PayloadSchemaType::Text => vec![FieldIndex::FullTextIndex(FullTextIndex::new(
+            PayloadSchemaType::Text => vec![FieldIndex::FullTextIndex(full_text_index(
                 db,
                 Default::default(),
                 field,
+                path,
                 is_appendable,
             ))],
             PayloadSchemaType::Bool => vec![FieldIndex::BinaryIndex(BinaryIndex::new(db, field))],
@@ -65,9 +71,15 @@ pub fn index_selector(
             }
         },
         PayloadFieldSchema::FieldParams(payload_params) => match payload_params {
-            PayloadSchemaParams::Text(text_index_params) => vec![FieldIndex::FullTextIndex(
-                FullTextIndex::new(db, text_index_params.clone(), field, is_appendable),
-            )],
+            PayloadSchemaParams::Text(text_index_params) => {
+                vec![FieldIndex::FullTextIndex(full_text_index(
+                    db,
+                    text_index_params.clone(),
+                    field,
+                    path,
+                    is_appendable,
+                ))]
+            }
             PayloadSchemaParams::Integer(integer_params) => {
                 let lookup = integer_params.lookup.then(|| {
                     FieldIndex::IntMapIndex(MapIndex::new(db.clone(), field, is_appendable))
@@ -84,3 +96,21 @@ pub fn index_selector(
         },
     }
 }
+
+/// Non-appendable full-text indexes keep their vocabulary in a memory-mapped dictionary, if the
+/// `mmap-vocab` feature is enabled
+#[cfg_attr(not(feature = "mmap-vocab"), allow(unused_variables))]
+fn full_text_index(
+    db: Arc<RwLock<DB>>,
+    config: TextIndexParams,
+    field: &str,
+    path: &Path,
+    is_appendable: bool,
+) -> FullTextIndex {
+    #[cfg(feature = "mmap-vocab")]
+    if !is_appendable {
+        let vocab_path = FullTextIndex::vocab_path(path, field);
+        return FullTextIndex::new_with_mmap_vocab(db, config, field, vocab_path);
+    }
+    FullTextIndex::new(db, config, field, is_appendable)
+}
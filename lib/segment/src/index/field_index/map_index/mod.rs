@@ -146,6 +146,7 @@ impl<N: Hash + Eq + Clone + Display + FromStr + Default> MapIndex<N> {
             points_count: self.get_indexed_points(),
             points_values_count: self.get_values_count(),
             histogram_bucket_size: None,
+            text_index: None,
         }
     }
 
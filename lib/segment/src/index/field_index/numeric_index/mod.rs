@@ -300,6 +300,7 @@ impl<T: Encodable + Numericable + Default> NumericIndex<T> {
             points_count: self.get_points_count(),
             points_values_count: self.get_histogram().get_total_count(),
             histogram_bucket_size: Some(self.get_histogram().current_bucket_size()),
+            text_index: None,
         }
     }
 
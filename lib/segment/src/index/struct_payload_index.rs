@@ -5,7 +5,7 @@ use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
 use atomic_refcell::AtomicRefCell;
-use common::types::PointOffsetType;
+use common::types::{PointOffsetType, TelemetryDetail};
 use log::debug;
 use parking_lot::RwLock;
 use rocksdb::DB;
@@ -115,7 +115,13 @@ impl StructPayloadIndex {
         payload_schema: PayloadFieldSchema,
         is_appendable: bool,
     ) -> OperationResult<Vec<FieldIndex>> {
-        let mut indexes = index_selector(field, &payload_schema, self.db.clone(), is_appendable);
+        let mut indexes = index_selector(
+            field,
+            &payload_schema,
+            self.db.clone(),
+            &self.path,
+            is_appendable,
+        );
 
         let mut is_loaded = true;
         for ref mut index in indexes.iter_mut() {
@@ -176,7 +182,8 @@ impl StructPayloadIndex {
         payload_schema: PayloadFieldSchema,
     ) -> OperationResult<Vec<FieldIndex>> {
         let payload_storage = self.payload.borrow();
-        let mut field_indexes = index_selector(field, &payload_schema, self.db.clone(), true);
+        let mut field_indexes =
+            index_selector(field, &payload_schema, self.db.clone(), &self.path, true);
         for index in &field_indexes {
             index.recreate()?;
         }
@@ -311,13 +318,13 @@ impl StructPayloadIndex {
         }
     }
 
-    pub fn get_telemetry_data(&self) -> Vec<PayloadIndexTelemetry> {
+    pub fn get_telemetry_data(&self, detail: TelemetryDetail) -> Vec<PayloadIndexTelemetry> {
         self.field_indexes
             .iter()
             .flat_map(|(name, field)| -> Vec<PayloadIndexTelemetry> {
                 field
                     .iter()
-                    .map(|field| field.get_telemetry_data().set_name(name.to_string()))
+                    .map(|field| field.get_telemetry_data(detail).set_name(name.to_string()))
                     .collect()
             })
             .collect()
@@ -1796,7 +1796,7 @@ impl SegmentEntry for Segment {
             info: self.info(),
             config: self.config().clone(),
             vector_index_searches,
-            payload_field_indices: self.payload_index.borrow().get_telemetry_data(),
+            payload_field_indices: self.payload_index.borrow().get_telemetry_data(detail),
         }
     }
 
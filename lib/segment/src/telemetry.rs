@@ -29,6 +29,18 @@ pub struct PayloadIndexTelemetry {
 
     #[serde(skip_serializing_if = "Option::is_none")]
     pub histogram_bucket_size: Option<usize>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text_index: Option<TextIndexTelemetry>,
+}
+
+/// Internal statistics of a full text index, only collected at the highest detail level
+#[derive(Serialize, Clone, Debug, JsonSchema)]
+pub struct TextIndexTelemetry {
+    pub vocabulary_size: usize,
+    pub postings_count: usize,
+    pub memory_usage_bytes: usize,
+    pub is_appendable: bool,
 }
 
 impl PayloadIndexTelemetry {
@@ -175,6 +187,18 @@ impl Anonymize for PayloadIndexTelemetry {
             points_count: self.points_count.anonymize(),
             points_values_count: self.points_values_count.anonymize(),
             histogram_bucket_size: self.histogram_bucket_size,
+            text_index: self.text_index.anonymize(),
+        }
+    }
+}
+
+impl Anonymize for TextIndexTelemetry {
+    fn anonymize(&self) -> Self {
+        TextIndexTelemetry {
+            vocabulary_size: self.vocabulary_size.anonymize(),
+            postings_count: self.postings_count.anonymize(),
+            memory_usage_bytes: self.memory_usage_bytes.anonymize(),
+            is_appendable: self.is_appendable,
         }
     }
 }